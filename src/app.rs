@@ -0,0 +1,307 @@
+use crate::config::{self, Server};
+use crate::forward::Tunnel;
+use crate::health::{self, Status};
+use crate::session::{self, SessionCache};
+use crate::settings::{Settings, SortMode};
+use crate::timefmt;
+use console::style;
+use ssh2::Session;
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+use std::sync::mpsc;
+use std::time::{Instant, SystemTime};
+
+/// A port forward started from the Port Forwards menu.
+pub struct ActiveForward {
+    pub server: String,
+    pub spec: String,
+    pub tunnel: Tunnel,
+}
+
+pub struct LastError {
+    pub message: String,
+    pub at: SystemTime,
+}
+
+/// State that only lives for this run of the program and is never persisted.
+#[derive(Default)]
+pub struct App {
+    /// Most recent connection failure per server name, cleared on success.
+    pub last_errors: HashMap<String, LastError>,
+    /// Latest reachability probe result per server name.
+    pub status: HashMap<String, Status>,
+    /// Background probe round still reporting, and when it was started.
+    pub probes: Option<mpsc::Receiver<(String, Status)>>,
+    pub last_probe: Option<Instant>,
+    /// Set by `--yes`; behaves like fast mode without persisting it.
+    pub assume_yes: bool,
+    /// Where the main menu cursor was left, restored after each action.
+    pub menu_selection: usize,
+    /// Name of the server last picked, so the picker reopens on it.
+    pub last_server: Option<String>,
+    /// Order of server lists; saved to settings when changed in the picker.
+    pub sort_mode: SortMode,
+    /// Only servers with this tag are offered in the server picker.
+    pub tag_filter: Option<String>,
+    /// Groups folded away in the server picker.
+    pub collapsed_groups: HashSet<String>,
+    /// Masks hosts and users on screen for screen-sharing and demos.
+    pub privacy_mode: bool,
+    /// Authenticated sessions reused by later actions on the same server.
+    pub sessions: SessionCache,
+    /// Servers whose key or certificate file is missing or unreadable.
+    pub key_problems: HashMap<String, String>,
+    /// Results of the startup key scan still arriving in the background.
+    pub key_scan: Option<mpsc::Receiver<(String, String)>>,
+    /// Forwards running in the background until stopped or the app exits.
+    pub forwards: Vec<ActiveForward>,
+    /// Error from the last action, shown in a popup before the next menu.
+    pub error_message: Option<String>,
+    /// Successful connections not yet stamped on the saved servers.
+    pub connected: HashMap<String, SystemTime>,
+    /// Host key fingerprints seen by those connections, by server name.
+    pub host_keys: HashMap<String, String>,
+    /// Kept open once used: on X11 and Wayland the copied text is served by
+    /// this handle and would vanish with it.
+    pub clipboard: Option<arboard::Clipboard>,
+}
+
+impl App {
+    /// Returns a session to `server`, reusing a cached one when possible,
+    /// and records the outcome.
+    pub fn connect(&mut self, server: &Server, servers: &[Server], settings: &Settings) -> Result<Session, Box<dyn std::error::Error>> {
+        match self.sessions.get_or_connect(server, servers, settings) {
+            Ok(sess) => {
+                self.record_success(server);
+                if let Some(fingerprint) = session::host_key_fingerprint(&sess) {
+                    self.host_keys.insert(server.name.clone(), fingerprint);
+                }
+                Ok(sess)
+            }
+            Err(e) => {
+                self.record_failure(server, &e);
+                Err(e)
+            }
+        }
+    }
+
+    pub fn record_failure(&mut self, server: &Server, message: impl ToString) {
+        self.last_errors.insert(server.name.clone(), LastError {
+            message: message.to_string(),
+            at: SystemTime::now(),
+        });
+    }
+
+    pub fn record_success(&mut self, server: &Server) {
+        self.last_errors.remove(&server.name);
+        self.connected.insert(server.name.clone(), SystemTime::now());
+    }
+
+    /// Copies recorded connection times and host keys onto `servers`.
+    /// Returns whether any changed, i.e. whether the config needs saving.
+    pub fn stamp_connections(&mut self, servers: &mut [Server]) -> bool {
+        let mut changed = false;
+        for (name, at) in self.connected.drain() {
+            if let Some(server) = servers.iter_mut().find(|s| s.name == name) {
+                server.last_connected = Some(at);
+                changed = true;
+            }
+        }
+        for (name, fingerprint) in self.host_keys.drain() {
+            if let Some(server) = servers.iter_mut().find(|s| s.name == name) {
+                if server.host_key.as_ref() != Some(&fingerprint) {
+                    server.host_key = Some(fingerprint);
+                    changed = true;
+                }
+            }
+        }
+        changed
+    }
+
+    /// Picks up whatever the background key scan has found so far.
+    pub fn poll_key_scan(&mut self) {
+        if let Some(scan) = &self.key_scan {
+            loop {
+                match scan.try_recv() {
+                    Ok((name, problem)) => {
+                        self.key_problems.insert(name, problem);
+                    }
+                    Err(mpsc::TryRecvError::Empty) => break,
+                    Err(mpsc::TryRecvError::Disconnected) => {
+                        self.key_scan = None;
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Picks up finished reachability probes, and starts a new round in the
+    /// background once the last one is done and `REFRESH_INTERVAL` has
+    /// passed. Never waits on the network itself.
+    pub fn poll_probes(&mut self, servers: &[Server], settings: &Settings) {
+        self.collect_probes();
+        if self.probes.is_some() {
+            return;
+        }
+        let due = self.last_probe.is_none_or(|at| at.elapsed() >= health::REFRESH_INTERVAL);
+        if due && !servers.is_empty() {
+            self.probes = Some(health::spawn_probes(servers, settings));
+            self.last_probe = Some(Instant::now());
+        }
+    }
+
+    /// Picks up the probes of the current round that have finished.
+    pub fn collect_probes(&mut self) {
+        let Some(probes) = &self.probes else { return };
+        loop {
+            match probes.try_recv() {
+                Ok((name, status)) => {
+                    self.status.insert(name, status);
+                }
+                Err(mpsc::TryRecvError::Empty) => return,
+                Err(mpsc::TryRecvError::Disconnected) => {
+                    self.probes = None;
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Drops everything remembered about a server that is being removed.
+    pub fn forget(&mut self, server: &Server) {
+        self.last_errors.remove(&server.name);
+        self.status.remove(&server.name);
+        self.key_problems.remove(&server.name);
+        self.sessions.invalidate(server);
+        self.stop_forwards(|f| f.server == server.name);
+    }
+
+    /// Adds connections the running forwards couldn't make to the error
+    /// shown before the next menu.
+    pub fn poll_forwards(&mut self) {
+        let failures: Vec<String> = self.forwards.iter().flat_map(|f| f.tunnel.failures()).collect();
+        if failures.is_empty() {
+            return;
+        }
+        let message = self.error_message.take().into_iter().chain(failures).collect::<Vec<_>>().join("\n");
+        self.error_message = Some(message);
+    }
+
+    /// Closes the running forwards `matches` picks, waiting for each to stop.
+    pub fn stop_forwards(&mut self, matches: impl Fn(&ActiveForward) -> bool) {
+        let (stopping, running) = std::mem::take(&mut self.forwards).into_iter().partition(|f| matches(f));
+        self.forwards = running;
+        for forward in stopping {
+            forward.tunnel.close();
+        }
+    }
+
+    /// What would be cut off by quitting now, if anything. Quitting from a
+    /// clean state needs no confirmation.
+    pub fn busy(&self) -> Option<String> {
+        match self.forwards.len() {
+            0 => None,
+            1 => Some("1 port forward is running".to_string()),
+            n => Some(format!("{} port forwards are running", n)),
+        }
+    }
+
+    /// Puts `text` on the system clipboard. Fails on headless machines and
+    /// over plain SSH sessions, where there is no clipboard to reach.
+    pub fn copy_to_clipboard(&mut self, text: &str) -> Result<(), String> {
+        if self.clipboard.is_none() {
+            self.clipboard = Some(arboard::Clipboard::new().map_err(|e| e.to_string())?);
+        }
+        let clipboard = self.clipboard.as_mut().ok_or("no clipboard")?;
+        clipboard.set_text(text).map_err(|e| e.to_string())
+    }
+
+    pub fn last_error(&self, server: &Server) -> Option<&LastError> {
+        self.last_errors.get(&server.name)
+    }
+
+    pub fn host(&self, server: &Server) -> String {
+        if self.privacy_mode {
+            "••••".to_string()
+        } else {
+            server.host.clone()
+        }
+    }
+
+    pub fn user(&self, server: &Server) -> String {
+        if self.privacy_mode {
+            let initial: String = server.user.chars().take(1).collect();
+            format!("{}•••", initial)
+        } else {
+            server.user.clone()
+        }
+    }
+
+    /// `user@host:port`, masked in privacy mode.
+    pub fn address(&self, server: &Server) -> String {
+        format!("{}@{}", self.user(server), config::host_port(&self.host(server), server.port))
+    }
+
+    /// A local path that often names the host, such as a Unix socket,
+    /// masked in privacy mode.
+    pub fn path(&self, path: &str) -> String {
+        if self.privacy_mode {
+            "••••".to_string()
+        } else {
+            path.to_string()
+        }
+    }
+
+    /// `text` with every word naming the server's host or user, or any IP
+    /// address, masked in privacy mode. Errors quote where a connection went,
+    /// often as a resolved address rather than the saved host.
+    pub fn scrub(&self, server: &Server, text: &str) -> String {
+        if !self.privacy_mode {
+            return text.to_string();
+        }
+        let sensitive = |part: &str| {
+            let part = part.trim_start_matches('[').trim_end_matches(']');
+            part == server.host || (!server.user.is_empty() && part == server.user) || part.parse::<IpAddr>().is_ok()
+        };
+        text.split(' ')
+            .map(|word| {
+                // As is, then without a trailing `:` ("resolve host: ..."),
+                // then without a `:port`.
+                let core = word.trim_matches(|c: char| "'\"(),;.".contains(c));
+                let bare = core.trim_end_matches(':');
+                let before_port = core.rsplit_once(':').filter(|(_, port)| port.parse::<u16>().is_ok()).map(|(host, _)| host);
+                let hit = [Some(core), Some(bare), before_port].into_iter().flatten()
+                    .find(|part| !part.is_empty() && part.split('@').any(sensitive));
+                match hit {
+                    Some(part) => word.replacen(part, "••••", 1),
+                    None => word.to_string(),
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// Reachability dot from the latest probe, ⚪ until one has finished.
+    pub fn status_dot(&self, server: &Server) -> &'static str {
+        self.status.get(&server.name).copied().unwrap_or(Status::Unknown).dot()
+    }
+
+    /// Prefix shown in front of a server in lists.
+    pub fn marker(&self, server: &Server) -> String {
+        let mut marker = String::new();
+        if self.last_errors.contains_key(&server.name) {
+            marker.push_str(&format!("{} ", style("●").red()));
+        }
+        if self.key_problems.contains_key(&server.name) {
+            marker.push_str(&format!("{} ", style("⚠").yellow()));
+        }
+        marker
+    }
+}
+
+impl LastError {
+    pub fn describe(&self, settings: &Settings) -> String {
+        format!("{} ({})", self.message, timefmt::format_timestamp(self.at, settings))
+    }
+}
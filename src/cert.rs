@@ -0,0 +1,136 @@
+use base64::{engine::general_purpose, Engine as _};
+use std::fs;
+use std::path::Path;
+use crate::settings::Settings;
+use crate::timefmt;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The subset of an OpenSSH user certificate (`*-cert.pub`) we care about.
+pub struct CertInfo {
+    pub key_id: String,
+    pub principals: Vec<String>,
+    pub valid_after: u64,
+    pub valid_before: u64,
+}
+
+impl CertInfo {
+    pub fn load(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| format!("Could not read certificate {:?}: {}", path, e))?;
+        Self::parse(&content)
+    }
+
+    pub fn parse(content: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut parts = content.split_whitespace();
+        let key_type = parts.next().ok_or("Empty certificate file")?;
+        if !key_type.contains("-cert-v01@openssh.com") {
+            return Err(format!("Not an OpenSSH certificate (type {})", key_type).into());
+        }
+        let blob = general_purpose::STANDARD.decode(parts.next().ok_or("Missing certificate body")?)?;
+        let mut reader = BlobReader { data: &blob };
+
+        let blob_type = String::from_utf8(reader.string()?.to_vec())?;
+        if blob_type != key_type {
+            return Err("Certificate type does not match its body".into());
+        }
+        reader.string()?; // nonce
+
+        // Skip the embedded public key; every field is length-prefixed.
+        let key_fields = match blob_type.as_str() {
+            t if t.starts_with("ssh-rsa") => 2,
+            t if t.starts_with("ssh-dss") => 4,
+            t if t.starts_with("ecdsa-") => 2,
+            t if t.starts_with("ssh-ed25519") => 1,
+            t if t.starts_with("sk-ecdsa-") => 3,
+            t if t.starts_with("sk-ssh-ed25519") => 2,
+            t => return Err(format!("Unsupported certificate type {}", t).into()),
+        };
+        for _ in 0..key_fields {
+            reader.string()?;
+        }
+
+        reader.u64()?; // serial
+        reader.u32()?; // cert type
+        let key_id = String::from_utf8_lossy(reader.string()?).to_string();
+
+        let mut principals = Vec::new();
+        let mut principal_reader = BlobReader { data: reader.string()? };
+        while !principal_reader.data.is_empty() {
+            principals.push(String::from_utf8_lossy(principal_reader.string()?).to_string());
+        }
+
+        let valid_after = reader.u64()?;
+        let valid_before = reader.u64()?;
+
+        Ok(CertInfo { key_id, principals, valid_after, valid_before })
+    }
+
+    pub fn is_expired(&self) -> bool {
+        now_secs() >= self.valid_before
+    }
+
+    pub fn is_not_yet_valid(&self) -> bool {
+        now_secs() < self.valid_after
+    }
+
+    pub fn validity_window(&self, settings: &Settings) -> String {
+        format!("{} → {}", format_cert_time(self.valid_after, settings), format_cert_time(self.valid_before, settings))
+    }
+
+    pub fn status(&self) -> &'static str {
+        if self.is_expired() {
+            "❌ Expired"
+        } else if self.is_not_yet_valid() {
+            "⏳ Not yet valid"
+        } else {
+            "✅ Valid"
+        }
+    }
+}
+
+struct BlobReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> BlobReader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], Box<dyn std::error::Error>> {
+        if self.data.len() < n {
+            return Err("Truncated certificate".into());
+        }
+        let (head, tail) = self.data.split_at(n);
+        self.data = tail;
+        Ok(head)
+    }
+
+    fn u32(&mut self) -> Result<u32, Box<dyn std::error::Error>> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into()?))
+    }
+
+    fn u64(&mut self) -> Result<u64, Box<dyn std::error::Error>> {
+        Ok(u64::from_be_bytes(self.take(8)?.try_into()?))
+    }
+
+    fn string(&mut self) -> Result<&'a [u8], Box<dyn std::error::Error>> {
+        let len = self.u32()? as usize;
+        self.take(len)
+    }
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+/// Validity bounds are always shown as absolute local times; "relative"
+/// makes little sense for the two ends of a window.
+fn format_cert_time(secs: u64, settings: &Settings) -> String {
+    if secs == 0 {
+        return "always".to_string();
+    }
+    if secs == u64::MAX {
+        return "forever".to_string();
+    }
+    match UNIX_EPOCH.checked_add(Duration::from_secs(secs)) {
+        Some(time) => timefmt::format_absolute(time, settings),
+        None => secs.to_string(),
+    }
+}
@@ -2,38 +2,319 @@ use aes_gcm::{
     aead::{Aead, KeyInit, OsRng},
     Aes256Gcm, Nonce,
 };
+use argon2::{Algorithm, Argon2, Params, Version};
 use base64::{engine::general_purpose, Engine as _};
 use pbkdf2::pbkdf2;
 use rand::RngCore;
 use serde::{Deserialize, Serialize};
 use hmac::Hmac;
 use sha2::Sha256;
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
-use std::io::BufReader;
-use std::path::PathBuf;
-use rpassword;
+use std::io::Write;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::SystemTime;
 use ssh2_config::SshConfig;
+use zeroize::Zeroizing;
+use crate::csv;
+use crate::putty;
 
 const SALT_LEN: usize = 16;
 const NONCE_LEN: usize = 12;
-const ITERATIONS: u32 = 100_000;
+/// What files written before the KDF was recorded used.
+const LEGACY_PBKDF2_ITERATIONS: u32 = 100_000;
+/// OWASP's recommended Argon2id baseline: 19 MiB, 2 passes, 1 lane.
+pub const DEFAULT_ARGON2_MEMORY_KIB: u32 = 19 * 1024;
+pub const DEFAULT_ARGON2_PASSES: u32 = 2;
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+/// How the encryption key is derived from the master password. Stored in
+/// the file so older files keep decrypting after the default changes.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(tag = "algorithm", rename_all = "lowercase")]
+pub enum Kdf {
+    Pbkdf2 { iterations: u32 },
+    Argon2id { memory_kib: u32, passes: u32, lanes: u32 },
+}
+
+impl Default for Kdf {
+    fn default() -> Self {
+        Kdf::argon2id(DEFAULT_ARGON2_MEMORY_KIB, DEFAULT_ARGON2_PASSES)
+    }
+}
+
+impl Kdf {
+    pub fn argon2id(memory_kib: u32, passes: u32) -> Self {
+        Kdf::Argon2id { memory_kib, passes, lanes: 1 }
+    }
+
+    fn legacy() -> Self {
+        Kdf::Pbkdf2 { iterations: LEGACY_PBKDF2_ITERATIONS }
+    }
+
+    fn derive_key(&self, password: &str, salt: &[u8]) -> Result<Zeroizing<[u8; 32]>, Box<dyn std::error::Error>> {
+        let mut key = Zeroizing::new([0u8; 32]);
+        match *self {
+            Kdf::Pbkdf2 { iterations } => {
+                pbkdf2::<Hmac<Sha256>>(password.as_bytes(), salt, iterations, key.as_mut())
+                    .map_err(|e| format!("PBKDF2 failed: {}", e))?;
+            }
+            Kdf::Argon2id { memory_kib, passes, lanes } => {
+                let params = Params::new(memory_kib, passes, lanes, Some(key.len()))
+                    .map_err(|e| format!("Invalid Argon2 parameters: {}", e))?;
+                Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+                    .hash_password_into(password.as_bytes(), salt, key.as_mut())
+                    .map_err(|e| format!("Argon2 failed: {}", e))?;
+            }
+        }
+        Ok(key)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub enum AuthType {
-    Password(String),
+    /// Wiped from memory when dropped; serializes as a plain string.
+    Password(Zeroizing<String>),
     Key(String), // Path to key
+    #[default]
     Agent,
+    Certificate { key_path: String, cert_path: String },
+    /// Nothing stored; the server's prompts (e.g. a 2FA code) are answered
+    /// at the terminal on every login.
+    KeyboardInteractive,
+}
+
+/// How the SSH byte stream reaches the server.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub enum Transport {
+    #[default]
+    Tcp,
+    UnixSocket(String), // Path to socket
+    ProxyCommand(String), // Shell command speaking SSH on stdin/stdout, e.g. IAP tunnels
+}
+
+/// Which IP family to use when a host resolves to both A and AAAA records.
+/// The `Prefer*` variants fall back to the other family; `*Only` don't.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+pub enum AddressFamily {
+    #[default]
+    Auto,
+    PreferIpv4,
+    PreferIpv6,
+    Ipv4Only,
+    Ipv6Only,
+}
+
+impl AddressFamily {
+    pub const ALL: [AddressFamily; 5] = [
+        AddressFamily::Auto,
+        AddressFamily::PreferIpv4,
+        AddressFamily::PreferIpv6,
+        AddressFamily::Ipv4Only,
+        AddressFamily::Ipv6Only,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            AddressFamily::Auto => "Auto (resolver order)",
+            AddressFamily::PreferIpv4 => "Prefer IPv4",
+            AddressFamily::PreferIpv6 => "Prefer IPv6",
+            AddressFamily::Ipv4Only => "IPv4 only",
+            AddressFamily::Ipv6Only => "IPv6 only",
+        }
+    }
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+/// Options the ssh config importer writes into `Server::ssh_options`, and
+/// so owns when syncing. Options added by hand are left alone.
+const IMPORTED_SSH_OPTIONS: [&str; 2] = ["IdentityFile=", "IdentitiesOnly="];
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct Server {
     pub name: String,
     pub user: String,
     pub host: String,
     pub port: u16,
     pub auth_type: AuthType,
+    /// With agent auth, the agent key to offer first: its comment or its
+    /// `SHA256:` fingerprint. Unset offers every key in the agent's order.
+    #[serde(default)]
+    pub agent_identity: Option<String>,
     #[serde(default = "default_group")]
     pub group: String,
+    /// Free-form labels such as "prod" or "web", on top of the single group.
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Listed first, with a ★, in the picker and List Servers.
+    #[serde(default)]
+    pub is_favorite: bool,
+    #[serde(default)]
+    pub transport: Transport,
+    /// Named command snippets, e.g. ("restart nginx", "sudo systemctl restart nginx").
+    #[serde(default)]
+    pub commands: Vec<(String, String)>,
+    /// Extra `-o Key=Value` options for the system `ssh` client.
+    #[serde(default)]
+    pub ssh_options: Vec<String>,
+    /// Port of a web admin UI on the server, opened through a local forward.
+    #[serde(default)]
+    pub web_port: Option<u16>,
+    /// Overrides the global address family setting when set.
+    #[serde(default)]
+    pub address_family: Option<AddressFamily>,
+    /// `socks5://` or `http://` proxy URL, or `none` to bypass the global
+    /// proxy. Unset means use the global setting.
+    #[serde(default)]
+    pub proxy: Option<String>,
+    /// Alias of a saved server to hop through (`ssh -J`). Jump hosts can
+    /// have their own, for multi-hop chains.
+    #[serde(default)]
+    pub jump_host: Option<String>,
+    /// Port forwards in `forward::ForwardSpec` form, e.g. `L 8080:localhost:80`.
+    #[serde(default)]
+    pub forwards: Vec<String>,
+    /// One-shot commands run from Run Command, most recent first.
+    #[serde(default)]
+    pub recent_commands: Vec<String>,
+    /// When a session to this server was last opened, to spot stale hosts.
+    #[serde(default, with = "crate::timefmt::rfc3339")]
+    pub last_connected: Option<SystemTime>,
+    /// `SHA256:` fingerprint of the host key seen at the last successful
+    /// connection, shown in the details for checking by eye.
+    #[serde(default)]
+    pub host_key: Option<String>,
+    /// Environment variables, such as `LANG`, asked for before the built-in
+    /// shell starts. sshd only accepts the names its `AcceptEnv` allows.
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+}
+
+/// How many one-shot commands are remembered per server.
+const RECENT_COMMANDS: usize = 10;
+
+impl Server {
+    /// With stray whitespace removed around the fields that name things, so a
+    /// trailing space can't turn a host into a failed DNS lookup, and IPv6
+    /// hosts unbracketed. Passwords are left exactly as given.
+    pub fn trimmed(mut self) -> Server {
+        for field in [&mut self.name, &mut self.user, &mut self.host, &mut self.group] {
+            *field = field.trim().to_string();
+        }
+        // `[::1]` is how IPv6 hosts are often written, but resolving wants
+        // the bare address.
+        if let Some(bare) = self.host.strip_prefix('[').and_then(|h| h.strip_suffix(']')) {
+            self.host = bare.to_string();
+        }
+        self.tags = self.tags.iter().map(|t| t.trim().to_string()).filter(|t| !t.is_empty()).collect();
+        for field in [&mut self.jump_host, &mut self.agent_identity] {
+            *field = field.as_deref().map(str::trim).filter(|v| !v.is_empty()).map(str::to_string);
+        }
+        self
+    }
+
+    /// Moves `command` to the front of the recent commands.
+    pub fn remember_command(&mut self, command: &str) {
+        self.recent_commands.retain(|c| c != command);
+        self.recent_commands.insert(0, command.to_string());
+        self.recent_commands.truncate(RECENT_COMMANDS);
+    }
+
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.iter().any(|t| t.eq_ignore_ascii_case(tag))
+    }
+
+    /// Refreshes the connection details from a fresh import of the same
+    /// alias. Key and agent auth follow the source; passwords, certificates
+    /// and keyboard-interactive were set by hand and are kept, as are the
+    /// group, snippets and everything else the source doesn't describe.
+    /// Returns whether anything changed.
+    pub fn sync_from(&mut self, imported: Server) -> bool {
+        let mut changed = false;
+        let mut set = |changed_field: bool| changed |= changed_field;
+
+        set(self.host != imported.host);
+        self.host = imported.host;
+        set(self.user != imported.user);
+        self.user = imported.user;
+        set(self.port != imported.port);
+        self.port = imported.port;
+        set(self.jump_host != imported.jump_host);
+        self.jump_host = imported.jump_host;
+
+        let mut ssh_options: Vec<String> = self.ssh_options.iter()
+            .filter(|o| !IMPORTED_SSH_OPTIONS.iter().any(|prefix| o.starts_with(prefix)))
+            .cloned()
+            .collect();
+        ssh_options.extend(imported.ssh_options);
+        set(self.ssh_options != ssh_options);
+        self.ssh_options = ssh_options;
+
+        match (&self.auth_type, &imported.auth_type) {
+            (AuthType::Key(old), AuthType::Key(new)) if old == new => {}
+            (AuthType::Agent, AuthType::Agent) => {}
+            (AuthType::Key(_) | AuthType::Agent, _) => {
+                self.auth_type = imported.auth_type;
+                set(true);
+            }
+            _ => {}
+        }
+        changed
+    }
+}
+
+/// Whether two server names are the same alias. Case is ignored, since
+/// "Prod" and "prod" are the same alias to a person; the add/edit form and
+/// every importer use this to refuse a second one.
+pub fn same_name(a: &str, b: &str) -> bool {
+    a.trim().to_lowercase() == b.trim().to_lowercase()
+}
+
+/// Splits a comma separated tag list, dropping blanks, a leading `#` and
+/// repeats (ignoring case).
+pub fn parse_tags(input: &str) -> Vec<String> {
+    let mut tags: Vec<String> = Vec::new();
+    for tag in input.split(',').map(|t| t.trim().trim_start_matches('#').trim()) {
+        if !tag.is_empty() && !tags.iter().any(|t| t.eq_ignore_ascii_case(tag)) {
+            tags.push(tag.to_string());
+        }
+    }
+    tags
+}
+
+/// Reads a comma separated `NAME=value` list, dropping blanks. Names are
+/// letters, digits and underscores, not starting with a digit; a repeated
+/// name keeps its last value.
+pub fn parse_env(input: &str) -> Result<BTreeMap<String, String>, String> {
+    let mut env = BTreeMap::new();
+    for pair in input.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        let (name, value) = pair.split_once('=').ok_or(format!("'{}' is not NAME=value", pair))?;
+        let name = name.trim();
+        let valid = name.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !valid {
+            return Err(format!("'{}' is not a valid variable name", name));
+        }
+        env.insert(name.to_string(), value.trim().to_string());
+    }
+    Ok(env)
+}
+
+/// The inverse of `parse_env`, for editing.
+pub fn format_env(env: &BTreeMap<String, String>) -> String {
+    env.iter().map(|(name, value)| format!("{}={}", name, value)).collect::<Vec<_>>().join(", ")
+}
+
+/// Every tag in use, sorted, each spelled as it first appears.
+pub fn all_tags(servers: &[Server]) -> Vec<String> {
+    let mut tags: Vec<String> = Vec::new();
+    for tag in servers.iter().flat_map(|s| &s.tags) {
+        if !tags.iter().any(|t| t.eq_ignore_ascii_case(tag)) {
+            tags.push(tag.clone());
+        }
+    }
+    tags.sort_by_key(|t| t.to_lowercase());
+    tags
 }
 
 fn default_group() -> String {
@@ -50,37 +331,185 @@ struct LegacyServer {
 
 #[derive(Serialize, Deserialize)]
 struct EncryptedConfig {
+    /// Missing from files written before Argon2id, which all used PBKDF2.
+    #[serde(default = "Kdf::legacy")]
+    kdf: Kdf,
     salt: String,
     nonce: String,
     ciphertext: String,
 }
 
+impl EncryptedConfig {
+    fn encrypt(servers: &[Server], password: &str, kdf: Kdf) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut salt = [0u8; SALT_LEN];
+        OsRng.fill_bytes(&mut salt);
+
+        let key = kdf.derive_key(password, &salt)?;
+        let cipher = Aes256Gcm::new((&*key).into());
+
+        let mut nonce_bytes = [0u8; NONCE_LEN];
+        OsRng.fill_bytes(&mut nonce_bytes);
+        let nonce = Nonce::from_slice(&nonce_bytes);
+
+        let json = Zeroizing::new(serde_json::to_string(servers)?);
+        let ciphertext = cipher.encrypt(nonce, json.as_bytes())
+            .map_err(|_| "Encryption failed")?;
+
+        Ok(EncryptedConfig {
+            kdf,
+            salt: general_purpose::STANDARD.encode(salt),
+            nonce: general_purpose::STANDARD.encode(nonce_bytes),
+            ciphertext: general_purpose::STANDARD.encode(ciphertext),
+        })
+    }
+
+    fn decrypt(&self, password: &str) -> Result<Vec<Server>, Box<dyn std::error::Error>> {
+        let salt = general_purpose::STANDARD.decode(&self.salt)?;
+        let nonce_bytes = general_purpose::STANDARD.decode(&self.nonce)?;
+        let ciphertext = general_purpose::STANDARD.decode(&self.ciphertext)?;
+
+        let key = self.kdf.derive_key(password, &salt)?;
+        let cipher = Aes256Gcm::new((&*key).into());
+        let nonce = Nonce::from_slice(&nonce_bytes);
+
+        let plaintext = Zeroizing::new(cipher.decrypt(nonce, ciphertext.as_ref())
+            .map_err(|_| "Invalid password or corrupted data")?);
+
+        Ok(serde_json::from_slice(&plaintext)?)
+    }
+}
+
+/// Outcome of a bulk import: how many were added and why the rest were not.
+#[derive(Default)]
+pub struct ImportReport {
+    pub added: usize,
+    /// Existing servers refreshed from the source, when syncing.
+    pub updated: Vec<String>,
+    pub duplicates: Vec<String>,
+    pub errors: Vec<String>,
+}
+
+/// Maps `Server` fields to (dotted) field paths in an arbitrary JSON inventory,
+/// e.g. `host=status.ip`. `root` points at the array of hosts when it is
+/// nested inside an object (Kubernetes lists keep theirs under `items`).
+pub struct FieldMapping {
+    pub root: Option<String>,
+    pub name: String,
+    pub host: String,
+    pub user: String,
+    pub port: String,
+    pub group: String,
+}
+
+impl Default for FieldMapping {
+    fn default() -> Self {
+        FieldMapping {
+            root: None,
+            name: "name".to_string(),
+            host: "host".to_string(),
+            user: "user".to_string(),
+            port: "port".to_string(),
+            group: "group".to_string(),
+        }
+    }
+}
+
+impl FieldMapping {
+    /// Parses a comma separated list of `field=path` pairs, e.g. `host=ip,name=hostname`.
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let mut mapping = FieldMapping::default();
+        for pair in spec.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            mapping.set(pair)?;
+        }
+        Ok(mapping)
+    }
+
+    pub fn set(&mut self, pair: &str) -> Result<(), String> {
+        let (field, path) = pair.split_once('=')
+            .ok_or_else(|| format!("Mapping '{}' must look like field=path", pair))?;
+        let path = path.trim().to_string();
+        match field.trim() {
+            "root" => self.root = Some(path),
+            "name" => self.name = path,
+            "host" => self.host = path,
+            "user" => self.user = path,
+            "port" => self.port = path,
+            "group" => self.group = path,
+            other => return Err(format!("Unknown field '{}' (expected root, name, host, user, port or group)", other)),
+        }
+        Ok(())
+    }
+}
+
+const CSV_COLUMNS: [&str; 6] = ["name", "user", "host", "port", "group", "auth"];
+
 pub struct Config {
     pub servers: Vec<Server>,
-    master_password: Option<String>,
+    master_password: Option<Zeroizing<String>>,
+    /// Used on every save, so files in an older format are upgraded the
+    /// next time anything changes.
+    pub kdf: Kdf,
+    /// Off saves the plain `Vec<Server>` format, which `load` reads too.
+    pub encrypt: bool,
+    /// Whether saving unencrypted passwords has been warned about yet.
+    warned_plaintext: bool,
+    pub location: Location,
+    path: PathBuf,
+}
+
+/// Where a server list is stored. Each file has its own master password.
+#[derive(Clone, PartialEq)]
+pub enum Location {
+    /// `servers.json` in the config directory, or `servers-<name>.json`
+    /// for a named profile.
+    Profile(Option<String>),
+    /// A file given with `--config` or `SSHMANAGER_CONFIG`, used as is.
+    File(PathBuf),
+}
+
+impl Location {
+    fn path(&self) -> Result<PathBuf, Box<dyn std::error::Error>> {
+        match self {
+            Location::Profile(profile) => get_config_path(profile.as_deref()),
+            Location::File(path) => Ok(path.clone()),
+        }
+    }
+
+    pub fn label(&self) -> String {
+        match self {
+            Location::Profile(profile) => profile.as_deref().unwrap_or("default").to_string(),
+            Location::File(path) => path.display().to_string(),
+        }
+    }
 }
 
 impl Config {
-    pub fn new() -> Self {
-        Config {
+    pub fn new(location: Location) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(Config {
             servers: vec![],
             master_password: None,
-        }
+            kdf: Kdf::default(),
+            encrypt: true,
+            warned_plaintext: false,
+            path: location.path()?,
+            location,
+        })
     }
 
-    pub fn load() -> Result<Self, Box<dyn std::error::Error>> {
-        let config_path = get_config_path()?;
+    pub fn load(location: Location) -> Result<Self, Box<dyn std::error::Error>> {
+        let empty = Config::new(location)?;
+        let config_path = empty.path.clone();
         
         if !config_path.exists() {
-            return Ok(Config::new());
+            return Ok(empty);
         }
 
         let content = fs::read_to_string(&config_path)?;
         
         // 1. Unencrypted New Format
-        if let Ok(mut servers) = serde_json::from_str::<Vec<Server>>(&content) {
+        if let Ok(servers) = serde_json::from_str::<Vec<Server>>(&content) {
              // Ensure group is set (handled by serde default but explicit check doesn't hurt if we were manually parsing)
-             return Ok(Config { servers, master_password: None });
+             return Ok(Config { servers, ..empty });
         }
 
         // 2. Legacy Format
@@ -93,8 +522,9 @@ impl Config {
                 port: ls.port,
                 auth_type: AuthType::Agent,
                 group: "General".to_string(),
+                ..Default::default()
             }).collect();
-            return Ok(Config { servers, master_password: None });
+            return Ok(Config { servers, ..empty });
         }
 
         // 3. Encrypted Config
@@ -102,39 +532,40 @@ impl Config {
              format!("Failed to parse config file at {:?}: {}", config_path, e)
         })?;
         
-        println!("🔒 Encrypted configuration found. Please enter master password:");
-        let password = rpassword::read_password()?;
-
-        let salt = general_purpose::STANDARD.decode(&enc_config.salt)?;
-        let nonce_bytes = general_purpose::STANDARD.decode(&enc_config.nonce)?;
-        let ciphertext = general_purpose::STANDARD.decode(&enc_config.ciphertext)?;
-
-        let key = derive_key(&password, &salt);
-        let cipher = Aes256Gcm::new(&key.into());
-        let nonce = Nonce::from_slice(&nonce_bytes);
-
-        let plaintext = cipher.decrypt(nonce, ciphertext.as_ref())
-            .map_err(|_| "Invalid password or corrupted data")?;
-
-        let servers: Vec<Server> = serde_json::from_str(&String::from_utf8(plaintext)?)?;
+        // On stderr, so `sshmanager list --json | jq` still gets clean output.
+        eprintln!("🔒 Encrypted configuration found. Please enter master password:");
+        let password = Zeroizing::new(rpassword::read_password()?);
+        let servers = enc_config.decrypt(&password)?;
 
         Ok(Config {
             servers,
             master_password: Some(password),
+            ..empty
         })
     }
 
     pub fn save(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-        let config_path = get_config_path()?;
+        let config_path = &self.path;
         if let Some(parent) = config_path.parent() {
             fs::create_dir_all(parent)?;
         }
 
+        if !self.encrypt {
+            let passwords = self.servers.iter().filter(|s| matches!(s.auth_type, AuthType::Password(_))).count();
+            if passwords > 0 && !self.warned_plaintext {
+                eprintln!("⚠️  Encryption is off: {} stored password(s) are saved in plain text to {}.", passwords, config_path.display());
+                self.warned_plaintext = true;
+            }
+            let json = Zeroizing::new(serde_json::to_string_pretty(&self.servers)?);
+            write_atomic_private(config_path, json.as_bytes())?;
+            return Ok(());
+        }
+
         if self.master_password.is_none() {
             println!("🔒 Set a master password to encrypt your data:");
-             let p1 = rpassword::read_password()?;
+             let p1 = Zeroizing::new(rpassword::read_password()?);
              println!("🔒 Confirm master password:");
-             let p2 = rpassword::read_password()?;
+             let p2 = Zeroizing::new(rpassword::read_password()?);
              if p1 != p2 {
                  return Err("Passwords do not match".into());
              }
@@ -142,28 +573,73 @@ impl Config {
         }
 
         let password = self.master_password.as_ref().unwrap();
-        let mut salt = [0u8; SALT_LEN];
-        OsRng.fill_bytes(&mut salt);
-        
-        let key = derive_key(password, &salt);
-        let cipher = Aes256Gcm::new(&key.into());
-        
-        let mut nonce_bytes = [0u8; NONCE_LEN];
-        OsRng.fill_bytes(&mut nonce_bytes);
-        let nonce = Nonce::from_slice(&nonce_bytes);
+        let enc_config = EncryptedConfig::encrypt(&self.servers, password, self.kdf)?;
 
-        let json = serde_json::to_string(&self.servers)?;
-        let ciphertext = cipher.encrypt(nonce, json.as_bytes())
-            .map_err(|_| "Encryption failed")?;
+        let content = serde_json::to_string_pretty(&enc_config)?;
+        write_atomic(config_path, content.as_bytes())?;
+        Ok(())
+    }
 
-        let enc_config = EncryptedConfig {
-            salt: general_purpose::STANDARD.encode(salt),
-            nonce: general_purpose::STANDARD.encode(nonce_bytes),
-            ciphertext: general_purpose::STANDARD.encode(ciphertext),
+    /// True if the file on disk is encrypted while encryption is off, so the
+    /// next save would quietly turn it into plain text. Encryption is one
+    /// setting for every profile, so this happens on opening a profile that
+    /// was saved while it was on.
+    pub fn will_decrypt_on_save(&self) -> bool {
+        !self.encrypt && self.encrypted_on_disk().is_some()
+    }
+
+    fn encrypted_on_disk(&self) -> Option<EncryptedConfig> {
+        fs::read_to_string(&self.path).ok()
+            .and_then(|content| serde_json::from_str::<EncryptedConfig>(&content).ok())
+    }
+
+    /// Asks for the master password until it decrypts the file on disk, to
+    /// unlock after the idle lock. The key is derived afresh each time. With
+    /// no encrypted file there is nothing to check, so it returns at once.
+    pub fn unlock(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let Some(enc_config) = self.encrypted_on_disk() else {
+            return Ok(());
         };
+        loop {
+            println!("🔒 Master password to unlock:");
+            let password = Zeroizing::new(rpassword::read_password()?);
+            if enc_config.decrypt(&password).is_ok() {
+                return Ok(());
+            }
+            println!("❌ Wrong master password.");
+        }
+    }
 
-        let content = serde_json::to_string_pretty(&enc_config)?;
-        fs::write(config_path, content)?;
+    /// Re-encrypts the server list under a new master password. The current
+    /// password is checked against the file on disk first, and nothing is
+    /// written unless both new entries match.
+    pub fn change_master_password(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        if !self.encrypt {
+            return Err("Encryption is turned off in Settings, so there is no master password".into());
+        }
+        if let Some(enc_config) = self.encrypted_on_disk() {
+            println!("🔒 Current master password:");
+            let current = Zeroizing::new(rpassword::read_password()?);
+            enc_config.decrypt(&current).map_err(|_| "Current master password is incorrect")?;
+        }
+
+        println!("🔒 New master password:");
+        let p1 = Zeroizing::new(rpassword::read_password()?);
+        if p1.is_empty() {
+            return Err("The master password can't be empty".into());
+        }
+        println!("🔒 Confirm new master password:");
+        let p2 = Zeroizing::new(rpassword::read_password()?);
+        if p1 != p2 {
+            return Err("Passwords do not match".into());
+        }
+
+        // A fresh salt and nonce come from `save`.
+        let previous = self.master_password.replace(p1);
+        if let Err(e) = self.save() {
+            self.master_password = previous;
+            return Err(e);
+        }
         Ok(())
     }
 
@@ -171,75 +647,838 @@ impl Config {
         self.servers.push(server);
     }
 
+    /// Names of every server but the one at `except`, for checking that a
+    /// name being saved is unique.
+    pub fn names_except(&self, except: Option<usize>) -> Vec<String> {
+        self.servers.iter().enumerate()
+            .filter(|(i, _)| Some(*i) != except)
+            .map(|(_, s)| s.name.clone())
+            .collect()
+    }
+
+    pub fn update_server(&mut self, index: usize, server: Server) {
+        if let Some(existing) = self.servers.get_mut(index) {
+            *existing = server;
+        }
+    }
+
     pub fn remove_server(&mut self, index: usize) {
         if index < self.servers.len() {
             self.servers.remove(index);
         }
     }
 
-    pub fn import_ssh_config(&mut self) -> Result<usize, Box<dyn std::error::Error>> {
+    /// Imports every concrete host in `~/.ssh/config`, following `Include`
+    /// directives. Options from wildcard blocks such as `Host *` apply to
+    /// the hosts they match, but the wildcard blocks themselves aren't
+    /// imported. With `update_existing`, servers already known by alias are
+    /// refreshed instead of skipped; see `Server::sync_from`.
+    pub fn import_ssh_config(&mut self, update_existing: bool) -> Result<ImportReport, Box<dyn std::error::Error>> {
         let ssh_dir = dirs::home_dir().ok_or("No home dir")?.join(".ssh");
         let config_path = ssh_dir.join("config");
-        
+
+        let mut report = ImportReport::default();
         if !config_path.exists() {
-            return Ok(0);
+            return Ok(report);
         }
 
-        let mut reader = BufReader::new(fs::File::open(config_path)?);
-        let config = SshConfig::default().parse(&mut reader, ssh2_config::ParseRule::ALLOW_UNKNOWN_FIELDS)?;
-        
-        let mut count = 0;
-        
-        let file_content = fs::read_to_string(ssh_dir.join("config"))?;
-            
-        for line in file_content.lines() {
-            let line = line.trim();
-            if line.starts_with("Host ") {
-                let host_alias = line.trim_start_matches("Host ").trim();
-                if host_alias.contains('*') { continue; } 
-                
-                let params = config.query(host_alias);
-                
-                let hostname = params.host_name.unwrap_or(host_alias.to_string());
-                let user = params.user.unwrap_or(whoami::username());
-                let port = params.port.unwrap_or(22);
-                // identity_file is Option<Vec<PathBuf>>
-                let identity = params.identity_file.and_then(|files| files.first().map(|p| p.to_string_lossy().to_string()));
-
-                
-                // Check duplicate
-                if !self.servers.iter().any(|s| s.name == host_alias) {
-                    self.servers.push(Server {
-                        name: host_alias.to_string(),
-                        user,
-                        host: hostname,
-                        port,
-                        auth_type: if let Some(path) = identity {
-                            AuthType::Key(path)
-                        } else {
-                            AuthType::Agent // Default to agent if no key specified but in config
-                        },
-                        group: "Imported".to_string(),
-                    });
-                    count += 1;
+        let file_content = read_ssh_config(&config_path, &ssh_dir, 0)?;
+        let config = SshConfig::default().parse(&mut file_content.as_bytes(), ssh2_config::ParseRule::ALLOW_UNKNOWN_FIELDS)?;
+        let aliases = concrete_hosts(&file_content);
+        let mut jumps = self.jump_links(&file_content, &aliases, &mut report);
+
+        for host_alias in aliases {
+            let params = config.query(&host_alias);
+            let hostname = params.host_name.map_or(host_alias.clone(), |name| name.replace("%h", &host_alias));
+            let user = params.user.unwrap_or(whoami::username());
+            let port = params.port.unwrap_or(22);
+
+            // ssh tries every IdentityFile in turn; the first becomes the
+            // key, and the rest are passed on to the system ssh client.
+            let mut identities = host_values(&file_content, &host_alias, "identityfile").into_iter().map(|path| expand_tilde(&path));
+            let identity = identities.next();
+            let mut ssh_options: Vec<String> = identities.map(|path| format!("IdentityFile={}", path)).collect();
+            if let Some(only) = host_values(&file_content, &host_alias, "identitiesonly").into_iter().next() {
+                ssh_options.push(format!("IdentitiesOnly={}", only));
+            }
+
+            let imported = Server {
+                user,
+                host: hostname,
+                port,
+                auth_type: if let Some(path) = identity {
+                    AuthType::Key(path)
+                } else {
+                    AuthType::Agent // Default to agent if no key specified but in config
+                },
+                group: "Imported".to_string(),
+                jump_host: jumps.remove(&host_alias),
+                ssh_options,
+                name: host_alias,
+                ..Default::default()
+            };
+
+            match self.servers.iter_mut().find(|s| same_name(&s.name, &imported.name)) {
+                None => {
+                    self.servers.push(imported);
+                    report.added += 1;
+                }
+                Some(existing) if update_existing => {
+                    if existing.sync_from(imported) {
+                        report.updated.push(existing.name.clone());
+                    }
+                }
+                Some(_) => report.duplicates.push(imported.name),
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// The jump host each alias in the ssh config should get. `ProxyJump a,b`
+    /// on a host links it to `b`, and `b` in turn to `a`, unless `b` already
+    /// jumps some other way, which is reported instead. A hop that names
+    /// neither a saved server nor a `Host` being imported is reported, and
+    /// that host gets no jump host rather than a broken one.
+    fn jump_links(&self, content: &str, aliases: &[String], report: &mut ImportReport) -> HashMap<String, String> {
+        let imported = |name: &str| aliases.iter().any(|a| a == name);
+        let saved = |name: &str| self.servers.iter().find(|s| s.name == name);
+        let mut chains = Vec::new();
+        for alias in aliases {
+            let hops = proxy_jump(content, alias);
+            match hops.iter().find(|hop| !imported(hop) && saved(hop).is_none()) {
+                Some(unknown) => report.errors.push(format!(
+                    "{}: ProxyJump goes through '{}', which is neither a saved server nor a Host in the ssh config; imported without a jump host",
+                    alias, unknown,
+                )),
+                None => chains.push((alias, hops)),
+            }
+        }
+
+        let mut jumps: HashMap<String, String> = chains.iter()
+            .filter_map(|(alias, hops)| Some((alias.to_string(), hops.last()?.clone())))
+            .collect();
+        for (alias, hops) in &chains {
+            for pair in hops.windows(2) {
+                let (via, hop) = (&pair[0], &pair[1]);
+                let current = match imported(hop) {
+                    true => jumps.get(hop).cloned(),
+                    false => saved(hop).and_then(|s| s.jump_host.clone()),
+                };
+                match current {
+                    Some(current) if current == *via => {}
+                    None if imported(hop) => {
+                        jumps.insert(hop.clone(), via.clone());
+                    }
+                    _ => report.errors.push(format!(
+                        "{}: ProxyJump reaches '{}' through '{}', but '{}' is set up to jump {}; that hop is skipped",
+                        alias, hop, via, hop, current.map_or("directly".to_string(), |c| format!("through '{}'", c)),
+                    )),
+                }
+            }
+        }
+        jumps
+    }
+
+    /// Imports servers from a CSV file whose header names any of the columns
+    /// `name,user,host,port,group,auth`. Only `name` and `host` are required.
+    pub fn import_csv(&mut self, path: &Path) -> Result<ImportReport, Box<dyn std::error::Error>> {
+        let content = fs::read_to_string(path)?;
+        let mut records = csv::parse(&content)?.into_iter();
+
+        let header = records.next().ok_or("CSV file is empty")?;
+        let mut columns = Vec::new();
+        for name in &header.fields {
+            let name = name.to_lowercase();
+            if !CSV_COLUMNS.contains(&name.as_str()) {
+                return Err(format!("Unknown CSV column '{}' (expected {})", name, CSV_COLUMNS.join(",")).into());
+            }
+            columns.push(name);
+        }
+        if !columns.iter().any(|c| c == "name") || !columns.iter().any(|c| c == "host") {
+            return Err("CSV header must contain at least 'name' and 'host'".into());
+        }
+
+        let mut report = ImportReport::default();
+        for record in records {
+            let field = |column: &str| {
+                columns.iter().position(|c| c == column)
+                    .and_then(|i| record.fields.get(i))
+                    .map(|v| v.trim())
+                    .filter(|v| !v.is_empty())
+            };
+
+            if record.fields.len() > columns.len() {
+                report.errors.push(format!("line {}: expected at most {} fields, found {}", record.line, columns.len(), record.fields.len()));
+                continue;
+            }
+            let (Some(name), Some(host)) = (field("name"), field("host")) else {
+                report.errors.push(format!("line {}: 'name' and 'host' are required", record.line));
+                continue;
+            };
+            let port = match field("port").map(str::parse::<u16>) {
+                None => 22,
+                Some(Ok(port)) if port != 0 => port,
+                Some(_) => {
+                    report.errors.push(format!("line {}: invalid port '{}'", record.line, field("port").unwrap_or_default()));
+                    continue;
+                }
+            };
+            let auth_type = match parse_csv_auth(field("auth")) {
+                Ok(auth) => auth,
+                Err(e) => {
+                    report.errors.push(format!("line {}: {}", record.line, e));
+                    continue;
+                }
+            };
+
+            if self.servers.iter().any(|s| same_name(&s.name, name)) {
+                report.duplicates.push(name.to_string());
+                continue;
+            }
+            self.servers.push(Server {
+                name: name.to_string(),
+                user: field("user").map(str::to_string).unwrap_or_else(whoami::username),
+                host: host.to_string(),
+                port,
+                auth_type,
+                group: field("group").unwrap_or("Imported").to_string(),
+                ..Default::default()
+            });
+            report.added += 1;
+        }
+
+        Ok(report)
+    }
+
+    /// Imports servers from a JSON inventory in an arbitrary schema, using
+    /// `mapping` to find each server field. Imported servers use agent auth.
+    pub fn import_json(&mut self, path: &Path, mapping: &FieldMapping) -> Result<ImportReport, Box<dyn std::error::Error>> {
+        let document: serde_json::Value = serde_json::from_str(&fs::read_to_string(path)?)?;
+        let root = match &mapping.root {
+            Some(root) => json_lookup(&document, root).ok_or_else(|| format!("No '{}' in inventory", root))?,
+            None => &document,
+        };
+        let entries = root.as_array().ok_or("Inventory must be a JSON array of hosts (set root=<path> if it is nested)")?;
+
+        let mut report = ImportReport::default();
+        for (i, entry) in entries.iter().enumerate() {
+            let (Some(name), Some(host)) = (json_string(entry, &mapping.name), json_string(entry, &mapping.host)) else {
+                report.errors.push(format!("entry {}: missing '{}' or '{}'", i, mapping.name, mapping.host));
+                continue;
+            };
+            let port = match json_string(entry, &mapping.port).map(|p| p.parse::<u16>()) {
+                None => 22,
+                Some(Ok(port)) if port != 0 => port,
+                Some(_) => {
+                    report.errors.push(format!("entry {}: invalid port", i));
+                    continue;
                 }
+            };
+
+            if self.servers.iter().any(|s| same_name(&s.name, &name)) {
+                report.duplicates.push(name);
+                continue;
             }
+            self.servers.push(Server {
+                name,
+                user: json_string(entry, &mapping.user).unwrap_or_else(whoami::username),
+                host,
+                port,
+                auth_type: AuthType::Agent,
+                group: json_string(entry, &mapping.group).unwrap_or_else(|| "Imported".to_string()),
+                ..Default::default()
+            });
+            report.added += 1;
         }
 
-        Ok(count)
+        Ok(report)
     }
+
+    /// Imports a plain list of hosts, one per line as `host`, `user@host` or
+    /// `user@host:port`, into `group` with agent auth. Lines in `/etc/hosts`
+    /// format (`address name [aliases...]`) are accepted too; loopback and
+    /// other non-routable entries are skipped. `#` starts a comment.
+    pub fn import_hostlist(&mut self, path: &Path, group: &str) -> Result<ImportReport, Box<dyn std::error::Error>> {
+        let content = fs::read_to_string(path)?;
+        let mut report = ImportReport::default();
+
+        for (i, line) in content.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let mut tokens = line.split_whitespace();
+            let first = tokens.next().unwrap_or_default();
+            let (name, user, host, port) = match (first.parse::<IpAddr>(), tokens.next()) {
+                (Ok(addr), Some(hostname)) => {
+                    if addr.is_loopback() || addr.is_unspecified() || addr.is_multicast() {
+                        continue;
+                    }
+                    (hostname.to_string(), None, addr.to_string(), None)
+                }
+                (_, None) => {
+                    let (user, host, port) = parse_host_spec(first);
+                    (host.clone(), user, host, port)
+                }
+                (Err(_), Some(_)) => {
+                    report.errors.push(format!("line {}: expected one host per line", i + 1));
+                    continue;
+                }
+            };
+            if host.is_empty() {
+                report.errors.push(format!("line {}: missing host", i + 1));
+                continue;
+            }
+
+            if self.servers.iter().any(|s| same_name(&s.name, &name)) {
+                report.duplicates.push(name);
+                continue;
+            }
+            self.servers.push(Server {
+                name,
+                user: user.unwrap_or_else(whoami::username),
+                host,
+                port: port.unwrap_or(22),
+                auth_type: AuthType::Agent,
+                group: group.to_string(),
+                ..Default::default()
+            });
+            report.added += 1;
+        }
+
+        Ok(report)
+    }
+
+    /// Imports the saved sessions from a PuTTY registry export (`.reg`) into
+    /// the "Imported (PuTTY)" group. Sessions without a host name, such as
+    /// PuTTY's "Default Settings", and non-SSH sessions are skipped. A
+    /// session's key file becomes key auth; the rest use the agent.
+    pub fn import_putty(&mut self, path: &Path) -> Result<ImportReport, Box<dyn std::error::Error>> {
+        let text = putty::decode(&fs::read(path)?);
+        let sessions = putty::parse(&text);
+        if sessions.is_empty() {
+            return Err("No PuTTY sessions found (expected a .reg export of HKEY_CURRENT_USER\\Software\\SimonTatham\\PuTTY\\Sessions)".into());
+        }
+
+        let mut report = ImportReport::default();
+        for session in sessions {
+            let Some(host_name) = session.host else { continue };
+            if session.protocol.as_deref().is_some_and(|p| p != "ssh") {
+                report.errors.push(format!("{}: not an SSH session", session.name));
+                continue;
+            }
+            // PuTTY accepts user@host in the host field too.
+            let (spec_user, host, _) = parse_host_spec(&host_name);
+            let port = match session.port.map(u16::try_from) {
+                None => 22,
+                Some(Ok(port)) if port != 0 => port,
+                Some(_) => {
+                    report.errors.push(format!("{}: invalid port", session.name));
+                    continue;
+                }
+            };
+
+            if self.servers.iter().any(|s| same_name(&s.name, &session.name)) {
+                report.duplicates.push(session.name);
+                continue;
+            }
+            self.servers.push(Server {
+                name: session.name,
+                user: session.user.or(spec_user).unwrap_or_else(whoami::username),
+                host,
+                port,
+                auth_type: session.key_file.map_or(AuthType::Agent, AuthType::Key),
+                group: "Imported (PuTTY)".to_string(),
+                ..Default::default()
+            });
+            report.added += 1;
+        }
+
+        Ok(report)
+    }
+
+    /// Writes the decrypted server list, stored passwords included, as
+    /// pretty JSON for backups and migration. Returns how many servers were
+    /// written. The file is readable only by you but is not encrypted.
+    pub fn export_plaintext(&self, path: &Path) -> Result<usize, Box<dyn std::error::Error>> {
+        let json = Zeroizing::new(serde_json::to_string_pretty(&self.servers)?);
+        write_atomic_private(path, json.as_bytes())?;
+        Ok(self.servers.len())
+    }
+
+    /// Reads back a file written by `export_plaintext`. Each entry is checked
+    /// on its own, so one bad entry doesn't stop the rest; names already in
+    /// the list are skipped as duplicates.
+    pub fn import_plaintext(&mut self, path: &Path) -> Result<ImportReport, Box<dyn std::error::Error>> {
+        let content = Zeroizing::new(fs::read_to_string(path)?);
+        let entries: Vec<serde_json::Value> = serde_json::from_str(&content)
+            .map_err(|e| format!("Not a server list export: {}", e))?;
+
+        let mut report = ImportReport::default();
+        for (i, entry) in entries.into_iter().enumerate() {
+            let server: Server = match serde_json::from_value::<Server>(entry) {
+                Ok(server) => server.trimmed(),
+                Err(e) => {
+                    report.errors.push(format!("entry {}: {}", i, e));
+                    continue;
+                }
+            };
+            if server.name.trim().is_empty() || server.host.trim().is_empty() {
+                report.errors.push(format!("entry {}: 'name' and 'host' are required", i));
+                continue;
+            }
+            if server.port == 0 {
+                report.errors.push(format!("entry {} ({}): invalid port 0", i, server.name));
+                continue;
+            }
+
+            if self.servers.iter().any(|s| same_name(&s.name, &server.name)) {
+                report.duplicates.push(server.name);
+                continue;
+            }
+            self.servers.push(server);
+            report.added += 1;
+        }
+
+        Ok(report)
+    }
+}
+
+/// Checks that an `ssh -o` option looks like `Key=Value`, e.g. `ServerAliveInterval=60`.
+pub fn validate_ssh_option(option: &str) -> Result<(), String> {
+    let Some((key, value)) = option.split_once('=') else {
+        return Err("Options must look like Key=Value".to_string());
+    };
+    if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return Err(format!("'{}' is not a valid option name", key));
+    }
+    if value.trim().is_empty() {
+        return Err(format!("{} needs a value", key));
+    }
+    Ok(())
 }
 
-fn derive_key(password: &str, salt: &[u8]) -> [u8; 32] {
-    let mut key = [0u8; 32];
-    pbkdf2::<Hmac<Sha256>>(password.as_bytes(), salt, ITERATIONS, &mut key)
-        .expect("PBKDF2 failed");
-    key
+/// `host:port`, with an IPv6 address in brackets (`[::1]:22`) so the port
+/// can be told apart, as `ssh -J`, `scp` and HTTP expect.
+pub fn host_port(host: &str, port: u16) -> String {
+    format!("{}:{}", bracket_host(host), port)
 }
 
-fn get_config_path() -> Result<PathBuf, Box<dyn std::error::Error>> {
+/// `host`, in brackets if it is an IPv6 address.
+pub fn bracket_host(host: &str) -> String {
+    if host.contains(':') { format!("[{}]", host) } else { host.to_string() }
+}
+
+/// Splits a pasted connection string such as `deploy@10.0.0.5:2222` or
+/// `root@[2001:db8::1]:22` into user, host and port. A bare IPv6 literal
+/// like `::1` is returned whole, since its colons are not a port separator.
+pub fn parse_host_spec(input: &str) -> (Option<String>, String, Option<u16>) {
+    let input = input.trim();
+    let (user, rest) = match input.rsplit_once('@') {
+        Some((user, rest)) if !user.is_empty() => (Some(user.to_string()), rest),
+        _ => (None, input),
+    };
+
+    if let Some(bracketed) = rest.strip_prefix('[') {
+        if let Some((host, after)) = bracketed.split_once(']') {
+            let port = after.strip_prefix(':').and_then(|p| p.parse().ok());
+            return (user, host.to_string(), port);
+        }
+    }
+
+    match rest.split_once(':') {
+        Some((host, port)) if !port.contains(':') => match port.parse() {
+            Ok(port) => (user, host.to_string(), Some(port)),
+            Err(_) => (user, rest.to_string(), None),
+        },
+        _ => (user, rest.to_string(), None),
+    }
+}
+
+/// The hops in a `Host` block's `ProxyJump`, first hop first. `user@` and
+/// `:port` are dropped since jump hosts are referenced by alias.
+fn proxy_jump(content: &str, alias: &str) -> Vec<String> {
+    let Some(value) = host_values(content, alias, "proxyjump").into_iter().next() else {
+        return Vec::new();
+    };
+    if value.eq_ignore_ascii_case("none") {
+        return Vec::new();
+    }
+    value.split(',')
+        .map(|hop| parse_host_spec(hop.trim()).1)
+        .filter(|host| !host.is_empty())
+        .collect()
+}
+
+/// Deepest `Include` nesting followed, as a guard against include loops.
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+/// Reads an ssh config file with each `Include` replaced by the files it
+/// names, in place, so that includes inside a `Host` block stay part of
+/// that block as they do for ssh.
+fn read_ssh_config(path: &Path, ssh_dir: &Path, depth: usize) -> Result<String, Box<dyn std::error::Error>> {
+    if depth > MAX_INCLUDE_DEPTH {
+        return Err(format!("Include nested too deeply at {}", path.display()).into());
+    }
+    let content = fs::read_to_string(path).map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+    let mut flattened = String::with_capacity(content.len());
+    for line in content.lines() {
+        let (key, value) = split_option(line);
+        if key.eq_ignore_ascii_case("include") {
+            for pattern in value.split_whitespace() {
+                for included in expand_include(pattern, ssh_dir) {
+                    flattened.push_str(&read_ssh_config(&included, ssh_dir, depth + 1)?);
+                    flattened.push('\n');
+                }
+            }
+        } else {
+            flattened.push_str(line);
+            flattened.push('\n');
+        }
+    }
+    Ok(flattened)
+}
+
+/// Files an `Include` pattern names, sorted. Relative patterns are taken
+/// from `~/.ssh`, and `*` and `?` may appear in any path component.
+fn expand_include(pattern: &str, ssh_dir: &Path) -> Vec<PathBuf> {
+    let pattern = PathBuf::from(expand_tilde(pattern));
+    let pattern = if pattern.is_absolute() { pattern } else { ssh_dir.join(pattern) };
+    let mut matches = vec![PathBuf::from("/")];
+    for component in pattern.components().skip(1) {
+        let part = component.as_os_str().to_string_lossy();
+        if !part.contains(['*', '?']) {
+            matches = matches.into_iter().map(|dir| dir.join(&*part)).collect();
+            continue;
+        }
+        matches = matches.into_iter()
+            .filter_map(|dir| fs::read_dir(&dir).ok())
+            .flat_map(|entries| entries.flatten())
+            .filter(|entry| {
+                let name = entry.file_name().to_string_lossy().into_owned();
+                !name.starts_with('.') && wildcard_match(&part, &name)
+            })
+            .map(|entry| entry.path())
+            .collect();
+        matches.sort();
+    }
+    matches.retain(|path| path.is_file());
+    matches
+}
+
+/// Aliases from `Host` lines that name a single host, in file order, without
+/// repeats. Patterns and negations only supply defaults.
+fn concrete_hosts(content: &str) -> Vec<String> {
+    let mut aliases: Vec<String> = Vec::new();
+    for line in content.lines() {
+        let (key, value) = split_option(line);
+        if !key.eq_ignore_ascii_case("host") {
+            continue;
+        }
+        for alias in value.split_whitespace() {
+            if !alias.contains(['*', '?', '!']) && !aliases.iter().any(|a| a == alias) {
+                aliases.push(alias.to_string());
+            }
+        }
+    }
+    aliases
+}
+
+/// Every value given for `key` in the blocks that apply to `alias`, in file
+/// order. For single-valued options ssh uses the first.
+fn host_values(content: &str, alias: &str, key: &str) -> Vec<String> {
+    let mut values = Vec::new();
+    // Options before the first Host line apply to every host.
+    let mut in_block = true;
+    for line in content.lines() {
+        let (option, value) = split_option(line);
+        match option.to_ascii_lowercase().as_str() {
+            "host" => in_block = host_patterns_match(value, alias),
+            "match" => in_block = false,
+            option if in_block && option == key && !value.is_empty() => values.push(value.trim_matches('"').to_string()),
+            _ => {}
+        }
+    }
+    values
+}
+
+/// Whether a `Host` line's patterns select `alias`: one of them matches and
+/// no negated (`!`) one does.
+fn host_patterns_match(patterns: &str, alias: &str) -> bool {
+    let mut matched = false;
+    for pattern in patterns.split_whitespace() {
+        match pattern.strip_prefix('!') {
+            Some(negated) if wildcard_match(negated, alias) => return false,
+            Some(_) => {}
+            None => matched |= wildcard_match(pattern, alias),
+        }
+    }
+    matched
+}
+
+/// Splits an ssh config line into its keyword and value, which may be
+/// separated by whitespace or `=`. Comments and blank lines give two empty
+/// strings.
+fn split_option(line: &str) -> (&str, &str) {
+    let line = line.trim();
+    if line.starts_with('#') {
+        return ("", "");
+    }
+    match line.split_once(|c: char| c.is_whitespace() || c == '=') {
+        Some((key, value)) => (key, value.trim_start_matches([' ', '\t', '=']).trim()),
+        None => (line, ""),
+    }
+}
+
+/// Shell-style matching where `*` is any run of characters and `?` any one.
+fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let (pattern, text): (Vec<char>, Vec<char>) = (pattern.chars().collect(), text.chars().collect());
+    let (mut p, mut t) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match star {
+                Some((star_p, star_t)) => {
+                    p = star_p + 1;
+                    t = star_t + 1;
+                    star = Some((star_p, star_t + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// `~` and `~/...` relative to the home directory; other paths unchanged.
+pub fn expand_tilde(path: &str) -> String {
+    match (path.strip_prefix('~'), dirs::home_dir()) {
+        (Some(rest), Some(home)) if rest.is_empty() || rest.starts_with('/') => format!("{}{}", home.display(), rest),
+        _ => path.to_string(),
+    }
+}
+
+fn json_lookup<'a>(value: &'a serde_json::Value, path: &str) -> Option<&'a serde_json::Value> {
+    path.split('.').try_fold(value, |v, key| match v {
+        serde_json::Value::Array(items) => items.get(key.parse::<usize>().ok()?),
+        _ => v.get(key),
+    })
+}
+
+fn json_string(value: &serde_json::Value, path: &str) -> Option<String> {
+    match json_lookup(value, path)? {
+        serde_json::Value::String(s) if !s.trim().is_empty() => Some(s.trim().to_string()),
+        serde_json::Value::Number(n) => Some(n.to_string()),
+        _ => None,
+    }
+}
+
+/// `auth` cells are `agent` (the default), `key:<path>`, `password:<secret>`,
+/// `cert:<key path>:<cert path>` or `keyboard-interactive`.
+fn parse_csv_auth(value: Option<&str>) -> Result<AuthType, String> {
+    let Some(value) = value else {
+        return Ok(AuthType::Agent);
+    };
+    let (kind, arg) = value.split_once(':').unwrap_or((value, ""));
+    match (kind.to_lowercase().as_str(), arg) {
+        ("agent", "") => Ok(AuthType::Agent),
+        ("keyboard-interactive", "") => Ok(AuthType::KeyboardInteractive),
+        ("key", path) if !path.is_empty() => Ok(AuthType::Key(path.to_string())),
+        ("password", secret) if !secret.is_empty() => Ok(AuthType::Password(Zeroizing::new(secret.to_string()))),
+        ("cert", paths) => match paths.split_once(':') {
+            Some((key_path, cert_path)) if !key_path.is_empty() && !cert_path.is_empty() => Ok(AuthType::Certificate {
+                key_path: key_path.to_string(),
+                cert_path: cert_path.to_string(),
+            }),
+            _ => Err("cert auth must be 'cert:<key path>:<cert path>'".to_string()),
+        },
+        _ => Err(format!("invalid auth '{}'", value)),
+    }
+}
+
+/// Writes through a temporary file in the same directory and renames it
+/// over `path`, so a crash or full disk leaves either the old file or the
+/// new one, never a truncated mix. That matters most for the encrypted
+/// server list, where a partial write is unrecoverable.
+pub fn write_atomic(path: &Path, content: &[u8]) -> std::io::Result<()> {
+    write_atomic_with(path, content, false)
+}
+
+/// `write_atomic` for cleartext secrets: the file is readable only by you
+/// from the moment it is created, before any of `content` is written.
+pub fn write_atomic_private(path: &Path, content: &[u8]) -> std::io::Result<()> {
+    write_atomic_with(path, content, true)
+}
+
+fn write_atomic_with(path: &Path, content: &[u8], private: bool) -> std::io::Result<()> {
+    let file_name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+    let tmp_path = path.with_file_name(format!(".{}.tmp-{}", file_name, std::process::id()));
+    let result = (|| {
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        if private {
+            // A leftover from a crash would keep its old permissions.
+            let _ = fs::remove_file(&tmp_path);
+            options.create_new(true);
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::OpenOptionsExt;
+                options.mode(0o600);
+            }
+        }
+        let mut file = options.open(&tmp_path)?;
+        file.write_all(content)?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, path)
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    result
+}
+
+/// Set when `--config` names the server list, so the files that live
+/// beside it follow it there.
+static CONFIG_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+/// Keeps settings, history, the add-server draft and session logs in `dir`
+/// instead of the usual config directory. Call before anything is loaded.
+pub fn set_config_dir(dir: PathBuf) {
+    let _ = CONFIG_DIR.set(dir);
+}
+
+pub fn get_config_dir() -> Result<PathBuf, Box<dyn std::error::Error>> {
+    if let Some(dir) = CONFIG_DIR.get() {
+        return Ok(dir.clone());
+    }
     let mut path = dirs::config_dir().ok_or("Could not find config directory")?;
     path.push("ssh-manager");
-    path.push("servers.json");
     Ok(path)
 }
+
+/// `servers.json` for the default profile, `servers-<name>.json` otherwise.
+fn get_config_path(profile: Option<&str>) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    match profile {
+        None => Ok(get_config_dir()?.join("servers.json")),
+        Some(name) => {
+            validate_profile_name(name)?;
+            Ok(get_config_dir()?.join(format!("servers-{}.json", name)))
+        }
+    }
+}
+
+/// Profile names end up in a file name, so they are kept to letters,
+/// digits, `-` and `_`.
+pub fn validate_profile_name(name: &str) -> Result<(), String> {
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        return Err(format!("Invalid profile name '{}': use letters, digits, '-' and '_'", name));
+    }
+    Ok(())
+}
+
+/// Names of the profiles that have a saved server list, sorted.
+pub fn list_profiles() -> Vec<String> {
+    let Ok(entries) = get_config_dir().and_then(|dir| Ok(fs::read_dir(dir)?)) else {
+        return Vec::new();
+    };
+    let mut profiles: Vec<String> = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let file_name = entry.file_name().to_string_lossy().into_owned();
+            let name = file_name.strip_prefix("servers-")?.strip_suffix(".json")?.to_string();
+            validate_profile_name(&name).is_ok().then_some(name)
+        })
+        .collect();
+    profiles.sort();
+    profiles
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Encrypts the way `save` did before the KDF was recorded: PBKDF2 at
+    /// 100,000 iterations and no `kdf` field in the file.
+    fn legacy_file(servers: &[Server], password: &str) -> String {
+        let mut salt = [0u8; SALT_LEN];
+        OsRng.fill_bytes(&mut salt);
+        let mut key = [0u8; 32];
+        pbkdf2::<Hmac<Sha256>>(password.as_bytes(), &salt, 100_000, &mut key).unwrap();
+        let cipher = Aes256Gcm::new(&key.into());
+        let mut nonce_bytes = [0u8; NONCE_LEN];
+        OsRng.fill_bytes(&mut nonce_bytes);
+        let json = serde_json::to_string(servers).unwrap();
+        let ciphertext = cipher.encrypt(Nonce::from_slice(&nonce_bytes), json.as_bytes()).unwrap();
+        serde_json::json!({
+            "salt": general_purpose::STANDARD.encode(salt),
+            "nonce": general_purpose::STANDARD.encode(nonce_bytes),
+            "ciphertext": general_purpose::STANDARD.encode(ciphertext),
+        })
+        .to_string()
+    }
+
+    #[test]
+    fn legacy_pbkdf2_file_round_trips_and_upgrades() {
+        let servers = vec![Server {
+            name: "web".into(),
+            user: "deploy".into(),
+            host: "10.0.0.5".into(),
+            port: 2222,
+            auth_type: AuthType::Password(Zeroizing::new("hunter2".into())),
+            ..Default::default()
+        }];
+        let file = legacy_file(&servers, "master");
+
+        let legacy: EncryptedConfig = serde_json::from_str(&file).unwrap();
+        assert_eq!(legacy.kdf, Kdf::legacy());
+        assert!(legacy.decrypt("wrong").is_err());
+        let decrypted = legacy.decrypt("master").unwrap();
+        assert_eq!(serde_json::to_string(&decrypted).unwrap(), serde_json::to_string(&servers).unwrap());
+
+        // Saving again re-encrypts with Argon2id, which records itself.
+        let kdf = Kdf::argon2id(8 * 1024, 1);
+        let upgraded = EncryptedConfig::encrypt(&decrypted, "master", kdf).unwrap();
+        let reloaded: EncryptedConfig = serde_json::from_str(&serde_json::to_string(&upgraded).unwrap()).unwrap();
+        assert_eq!(reloaded.kdf, kdf);
+        let decrypted = reloaded.decrypt("master").unwrap();
+        assert_eq!(serde_json::to_string(&decrypted).unwrap(), serde_json::to_string(&servers).unwrap());
+    }
+
+    #[test]
+    fn host_specs_split_into_user_host_and_port() {
+        let cases: &[(&str, Option<&str>, &str, Option<u16>)] = &[
+            ("deploy@10.0.0.5:2222", Some("deploy"), "10.0.0.5", Some(2222)),
+            ("web.example.com", None, "web.example.com", None),
+            ("root@[2001:db8::1]:22", Some("root"), "2001:db8::1", Some(22)),
+            ("[::1]:2222", None, "::1", Some(2222)),
+            ("[::1]", None, "::1", None),
+            // A bare IPv6 address: its colons are not a port separator.
+            ("::1", None, "::1", None),
+            ("admin@2001:db8::1", Some("admin"), "2001:db8::1", None),
+            // Without a valid port the whole input is taken as the host.
+            ("host:99999", None, "host:99999", None),
+            ("host:ssh", None, "host:ssh", None),
+        ];
+        for &(input, user, host, port) in cases {
+            let parsed = parse_host_spec(input);
+            assert_eq!(parsed, (user.map(str::to_string), host.to_string(), port), "{}", input);
+        }
+    }
+
+    #[test]
+    fn host_port_brackets_ipv6_and_round_trips() {
+        assert_eq!(host_port("10.0.0.5", 22), "10.0.0.5:22");
+        assert_eq!(host_port("::1", 2222), "[::1]:2222");
+        assert_eq!(parse_host_spec(&host_port("2001:db8::1", 22)), (None, "2001:db8::1".to_string(), Some(22)));
+    }
+}
@@ -0,0 +1,108 @@
+/// A parsed CSV record along with the line it started on, for error reporting.
+pub struct Record {
+    pub line: usize,
+    pub fields: Vec<String>,
+}
+
+/// Splits CSV text into records. Supports double-quoted fields containing
+/// commas, newlines and `""` escapes. Blank lines are dropped.
+pub fn parse(content: &str) -> Result<Vec<Record>, String> {
+    let mut records = Vec::new();
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut line = 1;
+    let mut record_line = 1;
+    let mut chars = content.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if in_quotes {
+            match c {
+                '"' if chars.peek() == Some(&'"') => {
+                    chars.next();
+                    field.push('"');
+                }
+                '"' => in_quotes = false,
+                '\n' => {
+                    line += 1;
+                    field.push(c);
+                }
+                _ => field.push(c),
+            }
+            continue;
+        }
+
+        match c {
+            '"' if field.trim().is_empty() => {
+                field.clear();
+                in_quotes = true;
+            }
+            ',' => fields.push(std::mem::take(&mut field)),
+            '\r' => {}
+            '\n' => {
+                fields.push(std::mem::take(&mut field));
+                push_record(&mut records, std::mem::take(&mut fields), record_line);
+                line += 1;
+                record_line = line;
+            }
+            _ => field.push(c),
+        }
+    }
+
+    if in_quotes {
+        return Err(format!("Unterminated quoted field starting on line {}", record_line));
+    }
+    fields.push(field);
+    push_record(&mut records, fields, record_line);
+    Ok(records)
+}
+
+fn push_record(records: &mut Vec<Record>, fields: Vec<String>, line: usize) {
+    if fields.iter().all(|f| f.trim().is_empty()) {
+        return;
+    }
+    records.push(Record {
+        line,
+        fields: fields.into_iter().map(|f| f.trim().to_string()).collect(),
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields(content: &str) -> Vec<Vec<String>> {
+        parse(content).unwrap().into_iter().map(|r| r.fields).collect()
+    }
+
+    #[test]
+    fn quoted_fields_keep_commas_and_doubled_quotes() {
+        assert_eq!(
+            fields("name,host\n\"web, eu\",\"say \"\"hi\"\"\"\n"),
+            vec![vec!["name", "host"], vec!["web, eu", "say \"hi\""]],
+        );
+    }
+
+    #[test]
+    fn crlf_line_endings_are_stripped() {
+        let records = parse("name,host\r\nweb,10.0.0.5\r\n\r\ndb,10.0.0.6\r\n").unwrap();
+        assert_eq!(records.iter().map(|r| r.line).collect::<Vec<_>>(), vec![1, 2, 4]);
+        assert_eq!(records[1].fields, vec!["web", "10.0.0.5"]);
+        assert_eq!(records[2].fields, vec!["db", "10.0.0.6"]);
+    }
+
+    #[test]
+    fn short_rows_keep_only_the_fields_given() {
+        let records = parse("name,host,user,port\nweb,10.0.0.5\n").unwrap();
+        assert_eq!(records[1].fields, vec!["web", "10.0.0.5"]);
+        assert_eq!(records[1].line, 2);
+    }
+
+    #[test]
+    fn quoted_newlines_count_towards_later_line_numbers() {
+        let records = parse("name,notes\nweb,\"two\nlines\"\ndb,x\n").unwrap();
+        assert_eq!(records[1].fields, vec!["web", "two\nlines"]);
+        assert_eq!(records[2].line, 4);
+        assert!(parse("name,host\n\"web,10.0.0.5\n").is_err());
+    }
+}
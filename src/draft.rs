@@ -0,0 +1,50 @@
+use crate::config::{get_config_dir, write_atomic};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+
+/// The non-secret fields of a half-filled add-server form, written after
+/// every answer so a crash or stray Ctrl-C doesn't lose them. Passwords are
+/// never drafted since this file is not encrypted.
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct ServerDraft {
+    pub group: Option<String>,
+    #[serde(default)]
+    pub tags: Option<Vec<String>>,
+    pub name: Option<String>,
+    pub host: Option<String>,
+    pub user: Option<String>,
+    pub port: Option<u16>,
+}
+
+impl ServerDraft {
+    pub fn load() -> Option<Self> {
+        let content = fs::read_to_string(draft_path().ok()?).ok()?;
+        serde_json::from_str(&content).ok()
+    }
+
+    /// Best effort: failing to write a draft shouldn't interrupt the form.
+    pub fn save(&self) {
+        let Ok(path) = draft_path() else { return };
+        if let Some(parent) = path.parent() {
+            let _ = fs::create_dir_all(parent);
+        }
+        if let Ok(content) = serde_json::to_string_pretty(self) {
+            let _ = write_atomic(&path, content.as_bytes());
+        }
+    }
+
+    pub fn clear() {
+        if let Ok(path) = draft_path() {
+            let _ = fs::remove_file(path);
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        self.name.clone().or_else(|| self.host.clone()).unwrap_or_else(|| "unnamed".to_string())
+    }
+}
+
+fn draft_path() -> Result<PathBuf, Box<dyn std::error::Error>> {
+    Ok(get_config_dir()?.join("draft.json"))
+}
@@ -0,0 +1,314 @@
+use crate::config;
+use ssh2::{Channel, Listener, Session};
+use std::fmt;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum ForwardKind {
+    /// `-L`: listen here, connect from the server.
+    Local,
+    /// `-R`: listen on the server, connect from here.
+    Remote,
+}
+
+/// A saved forward such as `L 8080:localhost:80` or
+/// `R 0.0.0.0:9000:localhost:9000`, in `ssh -L`/`-R` order.
+pub struct ForwardSpec {
+    pub kind: ForwardKind,
+    pub bind_host: Option<String>,
+    pub bind_port: u16,
+    pub host: String,
+    pub port: u16,
+}
+
+impl ForwardSpec {
+    pub fn parse(spec: &str) -> Result<ForwardSpec, String> {
+        let usage = "Forwards look like 'L [bind:]port:host:port' or 'R [bind:]port:host:port'";
+        let (kind, rest) = spec.trim().split_once(char::is_whitespace).ok_or(usage)?;
+        let kind = match kind.to_ascii_uppercase().as_str() {
+            "L" => ForwardKind::Local,
+            "R" => ForwardKind::Remote,
+            _ => return Err(usage.to_string()),
+        };
+        let parts: Vec<&str> = rest.trim().split(':').collect();
+        let (bind_host, bind_port, host, port) = match parts[..] {
+            [bind_port, host, port] => (None, bind_port, host, port),
+            [bind_host, bind_port, host, port] => (Some(bind_host.to_string()), bind_port, host, port),
+            _ => return Err(usage.to_string()),
+        };
+        let parse_port = |p: &str| p.parse::<u16>().map_err(|_| format!("'{}' is not a valid port", p));
+        if host.is_empty() {
+            return Err(usage.to_string());
+        }
+        Ok(ForwardSpec {
+            kind,
+            bind_host,
+            bind_port: parse_port(bind_port)?,
+            host: host.to_string(),
+            port: parse_port(port)?,
+        })
+    }
+
+    /// Starts the forward on its own session.
+    pub fn start(&self, sess: Session) -> Result<Tunnel, Box<dyn std::error::Error>> {
+        match self.kind {
+            ForwardKind::Local => {
+                let bind = config::host_port(self.bind_host.as_deref().unwrap_or("127.0.0.1"), self.bind_port);
+                Tunnel::local(sess, &bind, &self.host, self.port)
+            }
+            ForwardKind::Remote => Tunnel::remote(sess, self.bind_host.as_deref(), self.bind_port, &self.host, self.port),
+        }
+    }
+}
+
+impl fmt::Display for ForwardSpec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kind = match self.kind {
+            ForwardKind::Local => "L",
+            ForwardKind::Remote => "R",
+        };
+        write!(f, "{} ", kind)?;
+        if let Some(bind_host) = &self.bind_host {
+            write!(f, "{}:", bind_host)?;
+        }
+        write!(f, "{}:{}:{}", self.bind_port, self.host, self.port)
+    }
+}
+
+/// A port forward (`ssh -L` or `-R`) running on a background thread. The
+/// session is owned by that thread, which drives every connection from a
+/// single non-blocking loop since libssh2 channels can't be used from
+/// several threads at once.
+pub struct Tunnel {
+    /// The local end: where we listen for `-L`, where we connect to for `-R`.
+    pub local_addr: SocketAddr,
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+    /// Connections that couldn't be forwarded. The thread never prints, so
+    /// it can't write over whatever menu is on screen.
+    failures: Receiver<String>,
+}
+
+impl Tunnel {
+    /// Listens on `bind` and forwards each connection to `remote_host:remote_port`
+    /// as seen from the server.
+    pub fn local(sess: Session, bind: &str, remote_host: &str, remote_port: u16) -> Result<Tunnel, Box<dyn std::error::Error>> {
+        let listener = TcpListener::bind(bind).map_err(|e| format!("Could not listen on {}: {}", bind, e))?;
+        listener.set_nonblocking(true)?;
+        let local_addr = listener.local_addr()?;
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let (report, failures) = mpsc::channel();
+        let remote_host = remote_host.to_string();
+        let handle = {
+            let stop = Arc::clone(&stop);
+            thread::spawn(move || run(sess, listener, &remote_host, remote_port, &stop, &report))
+        };
+
+        Ok(Tunnel { local_addr, stop, handle: Some(handle), failures })
+    }
+
+    /// Asks the server to listen on `bind_port` (0 picks one) and connects
+    /// each incoming connection to `local_host:local_port` from here.
+    pub fn remote(sess: Session, bind_host: Option<&str>, bind_port: u16, local_host: &str, local_port: u16) -> Result<Tunnel, Box<dyn std::error::Error>> {
+        let local_addr = (local_host, local_port).to_socket_addrs()
+            .map_err(|e| format!("Could not resolve {}: {}", local_host, e))?
+            .next()
+            .ok_or_else(|| format!("{} has no addresses", local_host))?;
+        let (listener, _) = sess.channel_forward_listen(bind_port, bind_host, None)
+            .map_err(|e| format!("Server refused to listen on port {}: {}", bind_port, e))?;
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let (report, failures) = mpsc::channel();
+        let handle = {
+            let stop = Arc::clone(&stop);
+            thread::spawn(move || run_remote(sess, listener, local_addr, &stop, &report))
+        };
+
+        Ok(Tunnel { local_addr, stop, handle: Some(handle), failures })
+    }
+
+    /// Failures since the last call, for the caller to show when it suits.
+    pub fn failures(&self) -> Vec<String> {
+        self.failures.try_iter().collect()
+    }
+
+    pub fn close(mut self) {
+        self.shutdown();
+    }
+
+    fn shutdown(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+impl Drop for Tunnel {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+fn run(sess: Session, listener: TcpListener, remote_host: &str, remote_port: u16, stop: &AtomicBool, report: &Sender<String>) {
+    sess.set_blocking(false);
+    let mut connections: Vec<(TcpStream, Channel)> = Vec::new();
+    let mut buf = [0u8; 16384];
+
+    while !stop.load(Ordering::Relaxed) {
+        let mut idle = true;
+
+        match listener.accept() {
+            Ok((socket, _)) => {
+                idle = false;
+                match open_channel(&sess, remote_host, remote_port, stop) {
+                    Ok(channel) if socket.set_nonblocking(true).is_ok() => connections.push((socket, channel)),
+                    Ok(_) => {}
+                    Err(e) => {
+                        let _ = report.send(format!("Forward to {}:{} failed: {}", remote_host, remote_port, e));
+                    }
+                }
+            }
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+            Err(_) => break,
+        }
+
+        connections.retain_mut(|(socket, channel)| pump(socket, channel, &mut buf, &mut idle));
+
+        if idle {
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    for (_, mut channel) in connections {
+        let _ = channel.close();
+    }
+}
+
+fn run_remote(sess: Session, mut listener: Listener, local_addr: SocketAddr, stop: &AtomicBool, report: &Sender<String>) {
+    sess.set_blocking(false);
+    let mut connections: Vec<(TcpStream, Channel)> = Vec::new();
+    let mut buf = [0u8; 16384];
+
+    while !stop.load(Ordering::Relaxed) {
+        let mut idle = true;
+
+        match listener.accept().map_err(std::io::Error::from) {
+            Ok(mut channel) => {
+                idle = false;
+                match TcpStream::connect(local_addr) {
+                    Ok(socket) if socket.set_nonblocking(true).is_ok() => connections.push((socket, channel)),
+                    Ok(_) => {}
+                    Err(e) => {
+                        let _ = report.send(format!("Forward to {} failed: {}", local_addr, e));
+                        let _ = channel.close();
+                    }
+                }
+            }
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+            Err(_) => break,
+        }
+
+        connections.retain_mut(|(socket, channel)| pump(socket, channel, &mut buf, &mut idle));
+
+        if idle {
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    for (_, mut channel) in connections {
+        let _ = channel.close();
+    }
+}
+
+fn open_channel(sess: &Session, host: &str, port: u16, stop: &AtomicBool) -> Result<Channel, std::io::Error> {
+    loop {
+        match sess.channel_direct_tcpip(host, port, None) {
+            Ok(channel) => return Ok(channel),
+            Err(e) => {
+                let e = std::io::Error::from(e);
+                if e.kind() != ErrorKind::WouldBlock || stop.load(Ordering::Relaxed) {
+                    return Err(e);
+                }
+                thread::sleep(Duration::from_millis(1));
+            }
+        }
+    }
+}
+
+/// Moves whatever is ready in each direction between a non-blocking socket
+/// and a channel on a non-blocking session. Returns false once the
+/// connection is finished and should be dropped.
+pub fn pump<S: Read + Write + Shutdown>(socket: &mut S, channel: &mut Channel, buf: &mut [u8], idle: &mut bool) -> bool {
+    match socket.read(buf) {
+        Ok(0) => {
+            let _ = channel.send_eof();
+            let _ = channel.close();
+            return false;
+        }
+        Ok(n) => {
+            *idle = false;
+            if write_all(channel, &buf[..n]).is_err() {
+                return false;
+            }
+        }
+        Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+        Err(_) => return false,
+    }
+
+    match channel.read(buf) {
+        Ok(0) if channel.eof() => {
+            socket.shutdown_both();
+            let _ = channel.close();
+            return false;
+        }
+        Ok(0) => {}
+        Ok(n) => {
+            *idle = false;
+            if write_all(socket, &buf[..n]).is_err() {
+                return false;
+            }
+        }
+        Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+        Err(_) => return false,
+    }
+    true
+}
+
+/// Sockets `pump` can hang up on once the channel closes.
+pub trait Shutdown {
+    fn shutdown_both(&self);
+}
+
+impl Shutdown for TcpStream {
+    fn shutdown_both(&self) {
+        let _ = self.shutdown(std::net::Shutdown::Both);
+    }
+}
+
+#[cfg(unix)]
+impl Shutdown for std::os::unix::net::UnixStream {
+    fn shutdown_both(&self) {
+        let _ = self.shutdown(std::net::Shutdown::Both);
+    }
+}
+
+/// `write_all` for non-blocking writers: waits out `WouldBlock` instead of failing.
+fn write_all<W: Write>(writer: &mut W, mut data: &[u8]) -> std::io::Result<()> {
+    while !data.is_empty() {
+        match writer.write(data) {
+            Ok(0) => return Err(ErrorKind::WriteZero.into()),
+            Ok(n) => data = &data[n..],
+            Err(e) if e.kind() == ErrorKind::WouldBlock => thread::sleep(Duration::from_millis(1)),
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,230 @@
+use crate::app::App;
+use crate::config::{Server, Transport};
+use crate::keymap::{self, Binding};
+use crate::proxy;
+use crate::settings::Settings;
+use crate::ui;
+use crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use crossterm::terminal::{Clear, ClearType};
+use crossterm::{cursor::MoveTo, execute};
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const PROBE_TIMEOUT: Duration = Duration::from_secs(3);
+const PROBE_WORKERS: usize = 16;
+pub const REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Status {
+    Unknown,
+    Online,
+    Offline,
+}
+
+impl Status {
+    pub fn dot(self) -> &'static str {
+        match self {
+            Status::Unknown => "⚪",
+            Status::Online => "🟢",
+            Status::Offline => "🔴",
+        }
+    }
+}
+
+/// Checks whether something accepts TCP connections on the server's port.
+/// Servers behind a socket, proxy command, jump host or proxy can't be
+/// probed this cheaply; a direct connection says nothing about them.
+pub fn probe(server: &Server, settings: &Settings) -> Status {
+    if server.transport != Transport::Tcp || server.jump_host.is_some() {
+        return Status::Unknown;
+    }
+    if !matches!(proxy::for_server(server, settings), Ok(None)) {
+        return Status::Unknown;
+    }
+    let Ok(addrs) = (server.host.as_str(), server.port).to_socket_addrs() else {
+        return Status::Offline;
+    };
+    for addr in addrs {
+        if TcpStream::connect_timeout(&addr, PROBE_TIMEOUT).is_ok() {
+            return Status::Online;
+        }
+    }
+    Status::Offline
+}
+
+/// Probes every server on a small worker pool, sending `(name, status)` as
+/// each finishes.
+pub fn spawn_probes(servers: &[Server], settings: &Settings) -> mpsc::Receiver<(String, Status)> {
+    let (tx, rx) = mpsc::channel();
+    let queue = Arc::new(Mutex::new(servers.to_vec()));
+    let settings = Arc::new(settings.clone());
+    for _ in 0..PROBE_WORKERS.min(servers.len()) {
+        let queue = Arc::clone(&queue);
+        let settings = Arc::clone(&settings);
+        let tx = tx.clone();
+        thread::spawn(move || {
+            while let Some(server) = queue.lock().ok().and_then(|mut q| q.pop()) {
+                if tx.send((server.name.clone(), probe(&server, &settings))).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+    rx
+}
+
+#[derive(Clone, Copy)]
+enum Grouping {
+    Status,
+    Group,
+}
+
+/// Live view of server health. Results fill in as probes finish and are
+/// refreshed on demand (`r`) and every `REFRESH_INTERVAL`.
+pub fn health_view(servers: &[Server], app: &mut App, settings: &Settings) -> Result<(), Box<dyn std::error::Error>> {
+    let mut grouping = Grouping::Status;
+    let mut probes = spawn_probes(servers, settings);
+    let mut last_probe = Instant::now();
+    // Set by `r`, so the next round starts as soon as the current one ends.
+    let mut refresh_now = false;
+    let mut pending = servers.len();
+    let mut dirty = true;
+
+    let mut raw_mode = Some(ui::RawMode::enable()?);
+    let result = (|| -> Result<(), Box<dyn std::error::Error>> {
+        loop {
+            while let Ok((name, status)) = probes.try_recv() {
+                app.status.insert(name, status);
+                pending = pending.saturating_sub(1);
+                dirty = true;
+            }
+            if dirty {
+                render(servers, app, grouping, pending)?;
+                dirty = false;
+            }
+
+            if event::poll(Duration::from_millis(200))? {
+                if let Event::Key(key) = event::read()? {
+                    if key.kind != KeyEventKind::Press {
+                        continue;
+                    }
+                    let binding = match key.code {
+                        KeyCode::Esc => Some(Binding::Quit),
+                        KeyCode::Char(c) => keymap::lookup(c, keymap::HEALTH),
+                        _ => None,
+                    };
+                    match binding {
+                        Some(Binding::Quit) => return Ok(()),
+                        Some(Binding::ToggleView) => {
+                            grouping = match grouping {
+                                Grouping::Status => Grouping::Group,
+                                Grouping::Group => Grouping::Status,
+                            };
+                            dirty = true;
+                        }
+                        Some(Binding::Refresh) => refresh_now = true,
+                        Some(Binding::Help) => {
+                            // The popup draws with plain newlines.
+                            raw_mode = None;
+                            ui::show_help();
+                            raw_mode = Some(ui::RawMode::enable()?);
+                            dirty = true;
+                        }
+                        _ => {}
+                    }
+                }
+            }
+
+            if (refresh_now || last_probe.elapsed() >= REFRESH_INTERVAL) && pending == 0 {
+                probes = spawn_probes(servers, settings);
+                last_probe = Instant::now();
+                refresh_now = false;
+                pending = servers.len();
+                dirty = true;
+            }
+        }
+    })();
+    drop(raw_mode);
+    result
+}
+
+fn render(servers: &[Server], app: &App, grouping: Grouping, pending: usize) -> Result<(), Box<dyn std::error::Error>> {
+    let status_of = |server: &Server| app.status.get(&server.name).copied().unwrap_or(Status::Unknown);
+    let sections: Vec<(String, Vec<String>)> = match grouping {
+        Grouping::Status => [(Status::Online, "Online"), (Status::Offline, "Offline"), (Status::Unknown, "Unknown")]
+            .into_iter()
+            .map(|(status, title)| {
+                let lines = servers.iter()
+                    .filter(|s| status_of(s) == status)
+                    .map(|s| format!("[{}] {} ({})", s.group, s.name, app.address(s)))
+                    .collect();
+                (format!("{} {}", status.dot(), title), lines)
+            })
+            .collect(),
+        Grouping::Group => {
+            let mut groups: BTreeMap<&str, Vec<String>> = BTreeMap::new();
+            for s in servers {
+                groups.entry(&s.group).or_default()
+                    .push(format!("{} {} ({})", status_of(s).dot(), s.name, app.address(s)));
+            }
+            groups.into_iter().map(|(group, lines)| (format!("📂 {}", group), lines)).collect()
+        }
+    };
+
+    let mut stdout = std::io::stdout();
+    execute!(stdout, Clear(ClearType::All), MoveTo(0, 0))?;
+    let view = match grouping {
+        Grouping::Status => "by status",
+        Grouping::Group => "by group",
+    };
+    write!(stdout, "🩺 Server health ({})", view)?;
+    if pending > 0 {
+        write!(stdout, " — probing {} more...", pending)?;
+    }
+    write!(stdout, "\r\n")?;
+    for (title, lines) in &sections {
+        write!(stdout, "\r\n{} ({})\r\n", title, lines.len())?;
+        for line in lines {
+            write!(stdout, "   {}\r\n", line)?;
+        }
+    }
+    write!(
+        stdout,
+        "\r\n[{}] toggle status/group view  [{}] refresh now  [{}] help  [{}/Esc] back\r\n",
+        keymap::key(Binding::ToggleView), keymap::key(Binding::Refresh), keymap::key(Binding::Help), keymap::key(Binding::Quit),
+    )?;
+    stdout.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    #[test]
+    fn proxied_servers_are_not_probed_directly() {
+        // Something is listening, so a direct probe would call it online.
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let server = Server {
+            name: "behind-proxy".into(),
+            host: "127.0.0.1".into(),
+            port: listener.local_addr().unwrap().port(),
+            proxy: Some("socks5://127.0.0.1:1080".into()),
+            ..Default::default()
+        };
+        let settings = Settings::default();
+        assert!(probe(&server, &settings) == Status::Unknown);
+
+        let global = Settings { proxy: Some("http://proxy.example:3128".into()), ..Default::default() };
+        let plain = Server { proxy: None, ..server.clone() };
+        assert!(probe(&plain, &global) == Status::Unknown);
+
+        let direct = Server { proxy: Some(proxy::DIRECT.into()), ..server };
+        assert!(probe(&direct, &global) == Status::Online);
+    }
+}
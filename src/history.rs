@@ -0,0 +1,76 @@
+use crate::config::{get_config_dir, write_atomic};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+/// One shell session, or an attempt at one that failed to connect.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Entry {
+    pub server: String,
+    #[serde(with = "crate::timefmt::rfc3339")]
+    pub at: Option<SystemTime>,
+    pub success: bool,
+    /// From starting to connect until the shell ended or the attempt failed.
+    pub duration_secs: u64,
+}
+
+impl Entry {
+    pub fn new(server: &str, started: SystemTime, success: bool) -> Entry {
+        Entry {
+            server: server.to_string(),
+            at: Some(started),
+            success,
+            duration_secs: started.elapsed().unwrap_or_default().as_secs(),
+        }
+    }
+}
+
+/// Past connections, oldest first. A missing file is an empty history; one
+/// that can't be parsed is an error, so it isn't overwritten with a fresh
+/// history and lost.
+pub fn load() -> Result<Vec<Entry>, Box<dyn std::error::Error>> {
+    let path = history_path()?;
+    let content = match fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(format!("{}: {}", path.display(), e).into()),
+    };
+    serde_json::from_str(&content).map_err(|e| {
+        format!("{} can't be read ({}); fix or remove it to record connections again", path.display(), e).into()
+    })
+}
+
+/// Appends `entry`, dropping the oldest entries beyond `limit`. A limit of
+/// zero keeps no history at all. A history file that can't be read is left
+/// alone and the error returned.
+pub fn record(entry: Entry, limit: usize) -> Result<(), Box<dyn std::error::Error>> {
+    if limit == 0 {
+        return Ok(());
+    }
+    let mut entries = load()?;
+    entries.push(entry);
+    let excess = entries.len().saturating_sub(limit);
+    entries.drain(..excess);
+
+    let path = history_path()?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    write_atomic(&path, serde_json::to_string_pretty(&entries)?.as_bytes())?;
+    Ok(())
+}
+
+/// `1h 05m`, `12m 30s` or `45s`.
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    match (secs / 3600, secs % 3600 / 60, secs % 60) {
+        (0, 0, s) => format!("{}s", s),
+        (0, m, s) => format!("{}m {:02}s", m, s),
+        (h, m, _) => format!("{}h {:02}m", h, m),
+    }
+}
+
+fn history_path() -> Result<PathBuf, Box<dyn std::error::Error>> {
+    Ok(get_config_dir()?.join("history.json"))
+}
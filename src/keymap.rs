@@ -0,0 +1,173 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::RwLock;
+
+/// Things the pager and health view do on a letter key. Arrow keys, Page
+/// Up/Down, Home/End and Esc always work as well and can't be remapped.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Binding {
+    Quit,
+    Next,
+    Prev,
+    PageDown,
+    PageUp,
+    /// Pressed twice, like vim's `gg`.
+    Top,
+    Bottom,
+    Help,
+    Refresh,
+    ToggleView,
+}
+
+impl Binding {
+    const ALL: [Binding; 10] = [
+        Binding::Quit,
+        Binding::Next,
+        Binding::Prev,
+        Binding::PageDown,
+        Binding::PageUp,
+        Binding::Top,
+        Binding::Bottom,
+        Binding::Help,
+        Binding::Refresh,
+        Binding::ToggleView,
+    ];
+
+    /// Name used in the `keys` section of `settings.json`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Binding::Quit => "quit",
+            Binding::Next => "next",
+            Binding::Prev => "prev",
+            Binding::PageDown => "page_down",
+            Binding::PageUp => "page_up",
+            Binding::Top => "top",
+            Binding::Bottom => "bottom",
+            Binding::Help => "help",
+            Binding::Refresh => "refresh",
+            Binding::ToggleView => "toggle_view",
+        }
+    }
+
+    fn default_key(self) -> char {
+        match self {
+            Binding::Quit => 'q',
+            Binding::Next => 'j',
+            Binding::Prev => 'k',
+            Binding::PageDown => ' ',
+            Binding::PageUp => 'b',
+            Binding::Top => 'g',
+            Binding::Bottom => 'G',
+            Binding::Help => '?',
+            Binding::Refresh => 'r',
+            Binding::ToggleView => 'g',
+        }
+    }
+}
+
+/// The actions the output pager offers.
+pub const PAGER: &[Binding] = &[
+    Binding::Quit,
+    Binding::Next,
+    Binding::Prev,
+    Binding::PageDown,
+    Binding::PageUp,
+    Binding::Top,
+    Binding::Bottom,
+    Binding::Help,
+];
+
+/// The actions the health view offers.
+pub const HEALTH: &[Binding] = &[Binding::Quit, Binding::ToggleView, Binding::Refresh, Binding::Help];
+
+static CURRENT: RwLock<Option<HashMap<Binding, char>>> = RwLock::new(None);
+
+/// Makes `keys` (action name to key) the bindings from now on. Unknown
+/// actions, unusable keys and keys already taken on the same screen keep
+/// the default and are returned as warnings.
+pub fn apply(keys: &BTreeMap<String, String>) -> Vec<String> {
+    let mut map: HashMap<Binding, char> = Binding::ALL.iter().map(|b| (*b, b.default_key())).collect();
+    let mut warnings = Vec::new();
+    for (action, key) in keys {
+        let Some(binding) = Binding::ALL.iter().find(|b| b.name() == action.trim()) else {
+            let names: Vec<&str> = Binding::ALL.iter().map(|b| b.name()).collect();
+            warnings.push(format!("Unknown key binding '{}' (one of {})", action, names.join(", ")));
+            continue;
+        };
+        match parse_key(key) {
+            Ok(c) => {
+                map.insert(*binding, c);
+            }
+            Err(e) => warnings.push(format!("Key binding '{}': {}", action, e)),
+        }
+    }
+    // Resetting one clash can cause another (two actions remapped onto each
+    // other's defaults), so repeat until every screen is clash-free.
+    while let Some((binding, other)) = clash(&map) {
+        warnings.push(format!(
+            "Key binding '{}': '{}' is already used by '{}', keeping '{}'",
+            binding.name(), map[&binding], other.name(), binding.default_key()
+        ));
+        map.insert(binding, binding.default_key());
+    }
+    if let Ok(mut current) = CURRENT.write() {
+        *current = Some(map);
+    }
+    warnings
+}
+
+/// A remapped binding whose key another action on the same screen also
+/// uses, paired with that action.
+fn clash(map: &HashMap<Binding, char>) -> Option<(Binding, Binding)> {
+    for screen in [PAGER, HEALTH] {
+        for (i, a) in screen.iter().enumerate() {
+            for b in &screen[i + 1..] {
+                if map[a] != map[b] {
+                    continue;
+                }
+                // Blame the one the user changed; if both were, the later one.
+                if map[b] != b.default_key() {
+                    return Some((*b, *a));
+                }
+                if map[a] != a.default_key() {
+                    return Some((*a, *b));
+                }
+            }
+        }
+    }
+    None
+}
+
+fn parse_key(key: &str) -> Result<char, String> {
+    if key.eq_ignore_ascii_case("space") {
+        return Ok(' ');
+    }
+    let mut chars = key.chars();
+    match (chars.next(), chars.next()) {
+        // Digits are taken by the pager's count prefix.
+        (Some(c), None) if c.is_ascii_digit() => Err(format!("'{}' is reserved for counts", key)),
+        (Some(c), None) if !c.is_control() => Ok(c),
+        _ => Err(format!("'{}' is not a single key (use one character, or 'space')", key)),
+    }
+}
+
+/// The key bound to `binding`.
+pub fn key(binding: Binding) -> char {
+    CURRENT.read().ok()
+        .and_then(|map| map.as_ref().and_then(|m| m.get(&binding).copied()))
+        .unwrap_or(binding.default_key())
+}
+
+/// Which of `candidates` the key `c` triggers. Each screen passes only the
+/// actions it offers, so one key can mean different things in different
+/// places.
+pub fn lookup(c: char, candidates: &[Binding]) -> Option<Binding> {
+    candidates.iter().copied().find(|b| key(*b) == c)
+}
+
+/// The key bound to `binding` as shown on screen, with `Space` spelled out.
+pub fn label(binding: Binding) -> String {
+    match key(binding) {
+        ' ' => "Space".to_string(),
+        c => c.to_string(),
+    }
+}
@@ -0,0 +1,107 @@
+use console::Term;
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Locks the app once a prompt that waits for the user (the main menu, the
+/// server picker or the output pager) has had no key pressed for the idle
+/// timeout. A background thread blanks the screen (scrollback included)
+/// when the time is up; the prompt then gives up without acting, and the
+/// main loop asks for the master password before showing anything else.
+/// Shells, transfers and other actions never count as idle.
+struct Shared {
+    /// When the current prompt started waiting, while it waits.
+    waiting_since: Mutex<Option<Instant>>,
+    timeout_secs: AtomicU64,
+    /// Set when the screen was blanked, until `unlocked`.
+    locked: AtomicBool,
+    watching: AtomicBool,
+}
+
+static SHARED: Shared = Shared {
+    waiting_since: Mutex::new(None),
+    timeout_secs: AtomicU64::new(0),
+    locked: AtomicBool::new(false),
+    watching: AtomicBool::new(false),
+};
+
+/// `timeout_mins` of 0 never locks. The watcher thread starts the first
+/// time a non-zero timeout is set.
+pub fn set_timeout(timeout_mins: u64) {
+    SHARED.timeout_secs.store(timeout_mins.saturating_mul(60), Ordering::SeqCst);
+    if timeout_mins > 0 && !SHARED.watching.swap(true, Ordering::SeqCst) {
+        thread::spawn(|| loop {
+            thread::sleep(CHECK_INTERVAL);
+            if SHARED.due() && !SHARED.locked.swap(true, Ordering::SeqCst) {
+                let term = Term::stderr();
+                let _ = term.clear_screen();
+                let _ = term.write_str("\x1b[3J");
+                let _ = writeln!(&term, "🔒 Locked after being idle. Press Enter, then type the master password.");
+            }
+        });
+    }
+}
+
+/// Call just before a prompt waits for the user.
+pub fn waiting() {
+    *SHARED.waiting_since.lock().unwrap() = Some(Instant::now());
+}
+
+/// Call once the prompt returned. True if the app locked while it waited,
+/// in which case the prompt's answer should be dropped.
+pub fn resumed() -> bool {
+    if SHARED.due() {
+        SHARED.locked.store(true, Ordering::SeqCst);
+    }
+    *SHARED.waiting_since.lock().unwrap() = None;
+    locked()
+}
+
+/// True from when the screen was blanked until `unlocked`.
+pub fn locked() -> bool {
+    SHARED.locked.load(Ordering::SeqCst)
+}
+
+/// Call once the master password was given again.
+pub fn unlocked() {
+    SHARED.locked.store(false, Ordering::SeqCst);
+}
+
+impl Shared {
+    fn due(&self) -> bool {
+        let timeout = self.timeout_secs.load(Ordering::SeqCst);
+        let Some(since) = *self.waiting_since.lock().unwrap() else {
+            return false;
+        };
+        let idle = match last_keypress() {
+            Some(pressed) => since.elapsed().min(pressed),
+            None => since.elapsed(),
+        };
+        timeout > 0 && idle >= Duration::from_secs(timeout)
+    }
+}
+
+/// How long ago a key was last read from the terminal. The kernel keeps
+/// this as the tty's access time, to within 8 seconds; it is also where
+/// `w` gets its IDLE column from. Key presses inside a prompt never return
+/// to the app, so this is the only way to see them.
+#[cfg(unix)]
+fn last_keypress() -> Option<Duration> {
+    use std::io::IsTerminal;
+    use std::os::fd::AsFd;
+    let stdin = std::io::stdin();
+    if !stdin.is_terminal() {
+        return None;
+    }
+    let tty = std::fs::File::from(stdin.as_fd().try_clone_to_owned().ok()?);
+    tty.metadata().ok()?.accessed().ok()?.elapsed().ok()
+}
+
+#[cfg(not(unix))]
+fn last_keypress() -> Option<Duration> {
+    None
+}
@@ -1,21 +1,43 @@
+mod app;
+mod cert;
 mod config;
+mod csv;
+mod draft;
+mod forward;
+mod health;
+mod history;
+mod keycheck;
+mod keymap;
+mod lock;
+mod proxy;
+mod putty;
+mod session;
+mod settings;
+mod theme;
+mod timefmt;
+mod transfer;
+mod transport;
 mod ui;
 
-use config::{Config, AuthType, Server};
-use std::net::TcpStream;
-use std::io::{Read, Write};
+use app::{ActiveForward, App};
+use cert::CertInfo;
+use clap::{Parser, Subcommand};
+use draft::ServerDraft;
+use settings::Settings;
+use config::{Config, AuthType, Location, FieldMapping, ImportReport, Server, Transport};
+use std::io::{IsTerminal, Read, Write};
 use std::thread;
+use std::time::{Instant, SystemTime};
 use std::sync::mpsc;
-use std::path::Path;
-use std::fs::File;
-use crossterm::terminal::{enable_raw_mode, disable_raw_mode};
+use std::path::{Path, PathBuf};
 use ssh2::Session;
 use tabled::{Table, Tabled};
-use indicatif::{ProgressBar, ProgressStyle};
 
 // Wrapper for Tabled to print Server nicely
 #[derive(Tabled)]
 struct ServerDisplay {
+    #[tabled(rename = "")]
+    status: &'static str,
     #[tabled(rename = "Group")]
     group: String,
     #[tabled(rename = "Alias")]
@@ -28,98 +50,642 @@ struct ServerDisplay {
     port: u16,
     #[tabled(rename = "Auth")]
     auth_mode: String,
+    #[tabled(rename = "Tags")]
+    tags: String,
+}
+
+#[derive(Parser)]
+#[command(name = "sshmanager", version, about = "Manage and connect to your SSH servers")]
+struct Cli {
+    /// Fast mode for this run: skip routine confirmations
+    #[arg(short, long, global = true)]
+    yes: bool,
+
+    /// Use a named profile's server list (servers-<name>.json), each with its own master password
+    #[arg(long, global = true, env = "SSHMANAGER_PROFILE")]
+    profile: Option<String>,
+
+    /// Read and write the server list at this path instead (overrides --profile)
+    #[arg(long, global = true, env = "SSHMANAGER_CONFIG", value_name = "PATH")]
+    config: Option<PathBuf>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Connect to a saved server by name and open a shell, skipping the menus
+    Connect { name: String },
+    /// Open the SFTP menu for a saved server by name, skipping the main menu
+    Sftp { name: String },
+    /// Print the saved servers, one `[group] name (user@host:port)` per line
+    List {
+        /// Print a JSON array instead, for scripts (no passwords are included)
+        #[arg(long)]
+        json: bool,
+        /// Only list servers in this group
+        #[arg(long)]
+        group: Option<String>,
+    },
+    /// Import servers from a CSV file (header: name,user,host,port,group,auth)
+    ImportCsv { path: PathBuf },
+    /// Import servers from a JSON inventory in any schema
+    ImportJson {
+        path: PathBuf,
+        /// Map a server field to a dotted JSON path, e.g. `--map host=status.ip`.
+        /// Fields: root, name, host, user, port, group.
+        #[arg(long = "map", value_name = "FIELD=PATH")]
+        mappings: Vec<String>,
+    },
+    /// Import a plain host list (one `[user@]host[:port]` per line) or /etc/hosts
+    ImportHosts {
+        path: PathBuf,
+        /// Group to put the imported servers in
+        #[arg(long, default_value = "Imported")]
+        group: String,
+    },
+    /// Import saved sessions from a PuTTY registry export (.reg)
+    ImportPutty { path: PathBuf },
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let mut config = Config::load()?;
+    let cli = Cli::parse();
+
+    // The menus and shells need a real terminal; imports are fine without one.
+    let interactive = matches!(cli.command, None | Some(Command::Connect { .. } | Command::Sftp { .. }));
+    if interactive && !(std::io::stdin().is_terminal() && std::io::stdout().is_terminal()) {
+        eprintln!("❌ Not a terminal: the interactive menus need stdin and stdout attached to a TTY.");
+        eprintln!("   Run sshmanager directly in a terminal (or use `ssh -t` / `docker run -it`),");
+        eprintln!("   or use a subcommand for scripted use (see `sshmanager --help`).");
+        std::process::exit(1);
+    }
+    let location = match cli.config.clone() {
+        Some(path) => {
+            let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+            config::set_config_dir(dir.to_path_buf());
+            Location::File(path)
+        }
+        None => Location::Profile(cli.profile.clone()),
+    };
+    let mut settings = Settings::load()?;
+    for warning in theme::apply(&settings.theme).into_iter().chain(keymap::apply(&settings.keys)) {
+        eprintln!("⚠️  {}", warning);
+    }
+    let mut config = Config::load(location)?;
+    config.kdf = settings.kdf();
+    config.encrypt = !settings.plaintext_servers;
+    warn_if_decrypting(&config);
+    let mut app = App { assume_yes: cli.yes, sort_mode: settings.sort_mode, ..Default::default() };
+
+    if let Some(command) = cli.command {
+        return run_command(&mut config, &settings, &mut app, command);
+    }
+
+    if let Some(draft) = ServerDraft::load() {
+        if ui::resume_draft_prompt(&draft) {
+            add_server(&mut config, draft, &settings)?;
+        } else {
+            ServerDraft::clear();
+        }
+    }
+
+    app.key_scan = Some(keycheck::spawn_scan(&config.servers));
 
     loop {
-        match ui::main_menu() {
+        // Without encryption there is no master password to lock behind.
+        lock::set_timeout(if config.encrypt { settings.idle_lock_mins } else { 0 });
+        // A picker or pager that locked gave up; nothing else is shown
+        // until the master password is given.
+        if lock::locked() {
+            let _ = console::Term::stderr().clear_screen();
+            config.unlock()?;
+            lock::unlocked();
+        }
+        app.poll_key_scan();
+        app.poll_probes(&config.servers, &settings);
+        app.poll_forwards();
+        if app.stamp_connections(&mut config.servers) {
+            config.save()?;
+        }
+        if app.sort_mode != settings.sort_mode {
+            settings.sort_mode = app.sort_mode;
+            settings.save()?;
+        }
+        if let Some(message) = app.error_message.take() {
+            ui::error_popup(&message);
+        }
+        lock::waiting();
+        let action = ui::main_menu(&mut app, &config.location.label(), config.servers.len());
+        // The key press that woke a locked menu only unlocks it; the menu
+        // is shown again rather than acting on whatever was highlighted.
+        if lock::resumed() {
+            continue;
+        }
+        match action {
             ui::Action::Connect => {
-                if let Some(index) = ui::select_server(&config.servers) {
+                if let Some(index) = ui::select_server(&config.servers, &mut app) {
                     let server = &config.servers[index];
-                    println!("🚀 Connecting to {} ({}@{})...", server.name, server.user, server.host);
+                    println!("🚀 Connecting to {} ({}@{})...", server.name, app.user(server), app.host(server));
                     
-                    match create_session(server) {
+                    let started = SystemTime::now();
+                    match app.connect(server, &config.servers, &settings) {
                         Ok(sess) => {
-                             if let Err(e) = run_shell(sess) {
-                                 println!("❌ Connection failed: {}", e);
-                             }
+                            if let Err(e) = session_loop(sess, index, &mut config, started, &settings) {
+                                app.sessions.invalidate(&config.servers[index]);
+                                app.error_message = Some(e);
+                            }
                         },
-                        Err(e) => println!("❌ Connection failed: {}", e),
+                        Err(e) => {
+                            record_history(server, started, false, &settings);
+                            app.error_message = Some(format!("Connection failed: {}", e));
+                        }
+                    }
+                }
+            }
+            ui::Action::ConnectGroup => {
+                let Some(group) = ui::select_group(&config.servers) else { continue };
+                let members: Vec<&Server> = config.servers.iter().filter(|s| s.group == group).collect();
+                if let Err(e) = connect_group(&members, &config, &mut app, &settings) {
+                    app.error_message = Some(format!("Group connect failed: {}", e));
+                }
+            }
+            ui::Action::SystemSsh => {
+                if let Some(index) = ui::select_server(&config.servers, &mut app) {
+                    if let Err(e) = run_system_ssh(&config.servers[index], &config.servers, &app) {
+                        println!("❌ {}", e);
                     }
-                    
-                    let _ = disable_raw_mode();
+                    println!("\nPress Enter to continue...");
+                    let _ = std::io::stdin().read_line(&mut String::new());
+                }
+            }
+            ui::Action::CopyCommand => {
+                let Some(index) = ui::select_server(&config.servers, &mut app) else { continue };
+                let Some(format) = ui::copy_format_prompt() else { continue };
+                let command = match copy_command(&config.servers[index], &config.servers, format) {
+                    Ok(command) => command,
+                    Err(e) => {
+                        app.error_message = Some(e.to_string());
+                        continue;
+                    }
+                };
+                match app.copy_to_clipboard(&command) {
+                    Ok(()) => println!("📋 Copied to the clipboard."),
+                    Err(e) => {
+                        println!("⚠️  No clipboard available ({}); here it is to copy by hand:", e);
+                        if app.privacy_mode {
+                            println!("   (hidden in privacy mode)");
+                        } else {
+                            println!("   {}", command);
+                        }
+                    }
+                }
+                std::thread::sleep(std::time::Duration::from_millis(1000));
+            }
+            ui::Action::OpenWebUi => {
+                let Some(index) = ui::select_server(&config.servers, &mut app) else { continue };
+                if config.servers[index].web_port.is_none() {
+                    let Some(port) = ui::web_port_prompt() else { continue };
+                    config.servers[index].web_port = Some(port);
+                    config.save()?;
+                }
+                let server = &config.servers[index];
+                match app.connect(server, &config.servers, &settings) {
+                    Ok(sess) => {
+                        if let Err(e) = open_web_ui(sess, server.web_port.unwrap_or_default()) {
+                            app.sessions.invalidate(server);
+                            app.error_message = Some(e.to_string());
+                        }
+                    }
+                    Err(e) => app.error_message = Some(format!("Connection failed: {}", e)),
+                }
+                if app.error_message.is_none() {
                     println!("\nPress Enter to continue...");
                     let _ = std::io::stdin().read_line(&mut String::new());
                 }
             }
             ui::Action::FileTransfer => {
-                if let Some(index) = ui::select_server(&config.servers) {
+                if let Some(index) = ui::select_server(&config.servers, &mut app) {
                     let server = &config.servers[index];
                     println!("🚀 Connecting to {} for SFTP...", server.name);
 
-                     match create_session(server) {
+                     match app.connect(server, &config.servers, &settings) {
                         Ok(sess) => {
-                             if let Err(e) = run_sftp(sess) {
-                                 println!("❌ SFTP failed: {}", e);
+                             if let Err(e) = run_sftp(sess, server, &config.servers, &settings) {
+                                 app.sessions.invalidate(server);
+                                 app.error_message = Some(format!("SFTP failed: {}", e));
                              }
                         },
-                        Err(e) => println!("❌ Connection failed: {}", e),
+                        Err(e) => app.error_message = Some(format!("Connection failed: {}", e)),
+                    }
+                    if app.error_message.is_none() {
+                        println!("\nPress Enter to continue...");
+                        let _ = std::io::stdin().read_line(&mut String::new());
                     }
-                    println!("\nPress Enter to continue...");
-                    let _ = std::io::stdin().read_line(&mut String::new());
                 }
             }
-            ui::Action::AddServer => {
-                let server = ui::add_server_prompt();
+            ui::Action::AddServer => add_server(&mut config, ServerDraft::default(), &settings)?,
+            ui::Action::EditServer => {
+                let Some(index) = ui::select_server(&config.servers, &mut app) else { continue };
+                let Some(server) = ui::edit_server_prompt(&config.servers[index], &config.names_except(Some(index))) else {
+                    println!("🚫 Changes discarded.");
+                    std::thread::sleep(std::time::Duration::from_millis(1000));
+                    continue;
+                };
+                app.forget(&config.servers[index]);
+                app.last_server = Some(server.name.clone());
+                config.update_server(index, server);
+                config.save()?;
+                println!("✅ Server updated.");
+                std::thread::sleep(std::time::Duration::from_millis(1000));
+            }
+            ui::Action::DuplicateServer => {
+                let Some(index) = ui::select_server(&config.servers, &mut app) else { continue };
+                // Everything is copied, stored credentials included; only the
+                // connection history starts afresh.
+                let mut copy = config.servers[index].clone();
+                copy.name = format!("{} (copy)", copy.name);
+                copy.last_connected = None;
+                copy.host_key = None;
+                let Some(server) = ui::edit_server_prompt(&copy, &config.names_except(None)) else {
+                    println!("🚫 Copy discarded.");
+                    std::thread::sleep(std::time::Duration::from_millis(1000));
+                    continue;
+                };
+                app.last_server = Some(server.name.clone());
                 config.add_server(server);
                 config.save()?;
-                println!("✅ Server added successfully!");
+                println!("✅ Server duplicated.");
                 std::thread::sleep(std::time::Duration::from_millis(1000));
             }
             ui::Action::RemoveServer => {
-                if let Some(index) = ui::select_server(&config.servers) {
-                    config.remove_server(index);
+                if let Some(mut indices) = ui::select_servers(&config.servers, &mut app) {
+                    let names: Vec<&str> = indices.iter().map(|&i| config.servers[i].name.as_str()).collect();
+                    let prompt = match names.as_slice() {
+                        [name] => format!("Remove {}?", name),
+                        _ => format!("Remove {} servers ({})?", names.len(), names.join(", ")),
+                    };
+                    if !ui::confirm(ui::Confirmation::RemoveServer, &prompt, settings.fast_mode || app.assume_yes) {
+                        continue;
+                    }
+                    // Highest index first, so the ones still to go keep their places.
+                    indices.sort_unstable_by(|a, b| b.cmp(a));
+                    for &index in &indices {
+                        app.forget(&config.servers[index]);
+                        config.remove_server(index);
+                    }
                     config.save()?;
-                    println!("🗑️  Server removed.");
+                    // Reopen the picker on the neighbour that took the first
+                    // one's place, or the new last server if it was at the end.
+                    let first = indices[indices.len() - 1];
+                    app.last_server = config.servers.get(first.min(config.servers.len().saturating_sub(1)))
+                        .map(|s| s.name.clone());
+                    match indices.len() {
+                        1 => println!("🗑️  Server removed."),
+                        n => println!("🗑️  {} servers removed.", n),
+                    }
+                    std::thread::sleep(std::time::Duration::from_millis(1000));
+                }
+            }
+            ui::Action::HealthView => {
+                if config.servers.is_empty() {
+                    println!("⚠️  No servers found.");
                     std::thread::sleep(std::time::Duration::from_millis(1000));
+                } else if let Err(e) = health::health_view(&config.servers, &mut app, &settings) {
+                    println!("❌ Health view failed: {}", e);
+                }
+            }
+            ui::Action::ServerDetails => {
+                if let Some(index) = ui::select_server(&config.servers, &mut app) {
+                    let server = &config.servers[index];
+                    let title = format!("🔎 {}{}", app.marker(server), server.name);
+                    ui::show_output(&title, &server_details(server, &app, &settings));
+                }
+            }
+            ui::Action::ToggleFavorite => {
+                if let Some(index) = ui::select_server(&config.servers, &mut app) {
+                    let server = &mut config.servers[index];
+                    server.is_favorite = !server.is_favorite;
+                    config.save()?;
+                    // `last_server` already names it, so the picker reopens on
+                    // it wherever it moved to.
+                    let server = &config.servers[index];
+                    match server.is_favorite {
+                        true => println!("★ {} is now a favorite.", server.name),
+                        false => println!("☆ {} is no longer a favorite.", server.name),
+                    }
+                    std::thread::sleep(std::time::Duration::from_millis(1000));
+                }
+            }
+            ui::Action::ReorderServers => {
+                if config.servers.len() < 2 {
+                    println!("⚠️  There is nothing to reorder yet.");
+                    std::thread::sleep(std::time::Duration::from_millis(1000));
+                    continue;
+                }
+                // Pick in the saved order, so what moves is what was shown.
+                let previous = std::mem::replace(&mut app.sort_mode, settings::SortMode::Manual);
+                let picked = ui::select_server(&config.servers, &mut app);
+                let Some(mut index) = picked else {
+                    app.sort_mode = previous;
+                    continue;
+                };
+                let mut last = 0;
+                while let Some(step) = ui::move_step(&config.servers, index, &mut last) {
+                    let Some(target) = index.checked_add_signed(step).filter(|&t| t < config.servers.len()) else {
+                        continue;
+                    };
+                    config.servers.swap(index, target);
+                    config.save()?;
+                    index = target;
+                }
+                app.sort_mode = settings::SortMode::Manual;
+                if previous != settings::SortMode::Manual {
+                    println!("↕️  Server lists now follow your order; pick the Sorted by row in Connect to change it.");
+                    std::thread::sleep(std::time::Duration::from_millis(1500));
+                }
+            }
+            ui::Action::History => ui::show_output("📜 Connection History", &history_report(&settings)),
+            ui::Action::PinnedCommands => {
+                let Some(index) = ui::select_server(&config.servers, &mut app) else { continue };
+                loop {
+                    match ui::command_palette(&config.servers[index]) {
+                        ui::PaletteChoice::Add => {
+                            let command = ui::pinned_command_prompt();
+                            config.servers[index].commands.push(command);
+                            config.save()?;
+                        }
+                        ui::PaletteChoice::Remove => {
+                            if let Some(i) = ui::select_pinned_command(&config.servers[index]) {
+                                config.servers[index].commands.remove(i);
+                                config.save()?;
+                            }
+                        }
+                        ui::PaletteChoice::Run(i) => {
+                            let server = &config.servers[index];
+                            let (name, command) = &server.commands[i];
+                            println!("▶ {}: {}", name, command);
+                            match app.connect(server, &config.servers, &settings) {
+                                Ok(sess) => match run_exec(sess, command) {
+                                    Ok(status) => println!("\n⏹️  Exit status: {}", status),
+                                    Err(e) => {
+                                        app.sessions.invalidate(server);
+                                        app.error_message = Some(format!("Command failed: {}", e));
+                                    }
+                                },
+                                Err(e) => app.error_message = Some(format!("Connection failed: {}", e)),
+                            }
+                            match app.error_message.take() {
+                                Some(message) => ui::error_popup(&message),
+                                None => {
+                                    println!("\nPress Enter to continue...");
+                                    let _ = std::io::stdin().read_line(&mut String::new());
+                                }
+                            }
+                        }
+                        ui::PaletteChoice::Inject(i) => {
+                            let server = &config.servers[index];
+                            let started = SystemTime::now();
+                            match app.connect(server, &config.servers, &settings) {
+                                Ok(sess) => {
+                                    if let Err(e) = run_shell(sess, server, Some(&server.commands[i].1), &settings) {
+                                        app.sessions.invalidate(server);
+                                        app.error_message = Some(format!("Connection failed: {}", e));
+                                    }
+                                }
+                                Err(e) => app.error_message = Some(format!("Connection failed: {}", e)),
+                            }
+                            record_history(server, started, app.error_message.is_none(), &settings);
+                            match app.error_message.take() {
+                                Some(message) => ui::error_popup(&message),
+                                None => {
+                                    println!("\nPress Enter to continue...");
+                                    let _ = std::io::stdin().read_line(&mut String::new());
+                                }
+                            }
+                        }
+                        ui::PaletteChoice::Back => break,
+                    }
+                }
+            }
+            ui::Action::RunCommand => {
+                let Some(indices) = ui::select_servers(&config.servers, &mut app) else { continue };
+                let Some(command) = ui::one_shot_command_prompt(&config.servers[indices[0]]) else { continue };
+                for &index in &indices {
+                    config.servers[index].remember_command(&command);
+                }
+                config.save()?;
+                let [index] = indices[..] else {
+                    let report = run_on_each(&indices, &command, &config.servers, &mut app, &settings);
+                    ui::show_output(&format!("$ {} on {} servers", command, indices.len()), &report);
+                    continue;
+                };
+
+                let server = &config.servers[index];
+                println!("⏳ Running on {}: {}", server.name, command);
+                match app.connect(server, &config.servers, &settings) {
+                    Ok(sess) => match capture_exec(sess, &command) {
+                        Ok((output, status)) => {
+                            ui::show_output(&format!("{} $ {} (exit status {})", server.name, command, status), &output);
+                            continue;
+                        }
+                        Err(e) => {
+                            app.sessions.invalidate(server);
+                            app.error_message = Some(format!("Command failed: {}", e));
+                        }
+                    },
+                    Err(e) => app.error_message = Some(format!("Connection failed: {}", e)),
+                }
+            }
+            ui::Action::SshOptions => {
+                let Some(index) = ui::select_server(&config.servers, &mut app) else { continue };
+                loop {
+                    match ui::ssh_options_menu(&config.servers[index]) {
+                        ui::OptionsChoice::Add => {
+                            let option = ui::ssh_option_prompt();
+                            config.servers[index].ssh_options.push(option);
+                            config.save()?;
+                        }
+                        ui::OptionsChoice::Remove(i) => {
+                            config.servers[index].ssh_options.remove(i);
+                            config.save()?;
+                        }
+                        ui::OptionsChoice::Back => break,
+                    }
+                }
+            }
+            ui::Action::Forwards => {
+                let Some(index) = ui::select_server(&config.servers, &mut app) else { continue };
+                loop {
+                    let server = &config.servers[index];
+                    let active: Vec<&str> = app.forwards.iter()
+                        .filter(|f| f.server == server.name)
+                        .map(|f| f.spec.as_str())
+                        .collect();
+                    match ui::forwards_menu(server, &active) {
+                        ui::ForwardsChoice::Start => start_forwards(server, &config.servers, &mut app, &settings),
+                        ui::ForwardsChoice::Stop => {
+                            let name = server.name.clone();
+                            app.stop_forwards(|f| f.server == name);
+                            println!("⏹️  Forwards stopped.");
+                        }
+                        ui::ForwardsChoice::Add => {
+                            let spec = ui::forward_prompt();
+                            config.servers[index].forwards.push(spec);
+                            config.save()?;
+                        }
+                        ui::ForwardsChoice::Remove(i) => {
+                            let spec = config.servers[index].forwards.remove(i);
+                            let name = config.servers[index].name.clone();
+                            app.stop_forwards(|f| f.server == name && f.spec == spec);
+                            config.save()?;
+                        }
+                        ui::ForwardsChoice::Back => break,
+                    }
+                }
+            }
+            ui::Action::Diagnose => {
+                if let Some(index) = ui::select_server(&config.servers, &mut app) {
+                    diagnose(&config.servers[index], &config.servers, &mut app, &settings);
+                    println!("\nPress Enter to continue...");
+                    let _ = std::io::stdin().read_line(&mut String::new());
                 }
             }
-            ui::Action::ImportConfig => {
-                println!("📥 Importing servers from ~/.ssh/config...");
-                match config.import_ssh_config() {
-                    Ok(count) => {
-                        config.save()?;
-                        println!("✅ Imported {} servers.", count);
+            ui::Action::CheckKeys => {
+                check_key_files(&mut config, &mut app)?;
+                println!("\nPress Enter to continue...");
+                let _ = std::io::stdin().read_line(&mut String::new());
+            }
+            ui::Action::Import => {
+                let Some(source) = ui::import_menu() else { continue };
+                match run_import(&mut config, source) {
+                    Ok(report) => {
+                        if report.added > 0 || !report.updated.is_empty() {
+                            config.save()?;
+                        }
+                        // Sessions to a refreshed server may point at its old address.
+                        for name in &report.updated {
+                            if let Some(server) = config.servers.iter().find(|s| &s.name == name) {
+                                app.sessions.invalidate(server);
+                            }
+                        }
+                        print_import_report(&report);
                     },
                     Err(e) => println!("❌ Import failed: {}", e),
                 }
-                std::thread::sleep(std::time::Duration::from_millis(2000));
+                println!("\nPress Enter to continue...");
+                let _ = std::io::stdin().read_line(&mut String::new());
+            }
+            ui::Action::Export => {
+                let Some(path) = ui::export_prompt() else { continue };
+                match config.export_plaintext(Path::new(&path)) {
+                    Ok(count) => println!("📤 Exported {} servers to {}. Remember: this file is not encrypted.", count, path),
+                    Err(e) => println!("❌ Export failed: {}", e),
+                }
+                println!("\nPress Enter to continue...");
+                let _ = std::io::stdin().read_line(&mut String::new());
             }
             ui::Action::ListServers => {
                 if config.servers.is_empty() {
                     println!("⚠️  No servers found.");
                 } else {
-                    let display_list: Vec<ServerDisplay> = config.servers.iter().map(|s| ServerDisplay {
+                    let sorted = app.sort_mode.order(&config.servers).into_iter().map(|i| &config.servers[i]);
+                    let display_list: Vec<ServerDisplay> = sorted.map(|s| ServerDisplay {
+                        status: app.status_dot(s),
                         group: s.group.clone(),
-                        name: s.name.clone(),
-                        user: s.user.clone(),
-                        host: s.host.clone(),
+                        name: format!("{}{}", if s.is_favorite { "★ " } else { "" }, match (app.last_error(s).is_some(), app.key_problems.contains_key(&s.name)) {
+                            (true, true) => format!("🔴 ⚠️ {}", s.name),
+                            (true, false) => format!("🔴 {}", s.name),
+                            (false, true) => format!("⚠️ {}", s.name),
+                            (false, false) => s.name.clone(),
+                        }),
+                        user: app.user(s),
+                        host: app.host(s),
                         port: s.port,
+                        tags: s.tags.join(", "),
                         auth_mode: match &s.auth_type {
                             AuthType::Password(_) => "🔑 Password".to_string(),
                             AuthType::Key(_) => "🗝️ Key".to_string(),
                             AuthType::Agent => "🕵️ Agent".to_string(),
+                            AuthType::Certificate { .. } => "📜 Certificate".to_string(),
+                            AuthType::KeyboardInteractive => "⌨️ Keyboard-interactive".to_string(),
                         },
                     }).collect();
                     
-                    println!("{}", Table::new(display_list).to_string());
+                    println!("{}", Table::new(display_list));
                 }
                 println!("\nPress Enter to continue...");
                 let _ = std::io::stdin().read_line(&mut String::new());
             }
+            ui::Action::Settings => {
+                if ui::settings_menu(&mut settings) {
+                    settings.save()?;
+                    config.kdf = settings.kdf();
+                    theme::apply(&settings.theme);
+                    println!("✅ Settings saved.");
+                    // Rewrite the list now so the file on disk matches.
+                    if config.encrypt == settings.plaintext_servers {
+                        config.encrypt = !settings.plaintext_servers;
+                        match config.save() {
+                            Ok(()) if config.encrypt => println!("🔒 Server list encrypted."),
+                            Ok(()) => println!("🔓 Server list saved without encryption."),
+                            Err(e) => app.error_message = Some(format!("Could not rewrite the server list: {}", e)),
+                        }
+                    }
+                    std::thread::sleep(std::time::Duration::from_millis(1000));
+                }
+            }
+            ui::Action::TogglePrivacy => app.privacy_mode = !app.privacy_mode,
+            ui::Action::SwitchProfile => {
+                let Location::Profile(current) = &config.location else {
+                    app.error_message = Some("Profiles can't be switched while --config or SSHMANAGER_CONFIG is set".to_string());
+                    continue;
+                };
+                let Some(profile) = ui::profile_prompt(current.as_deref(), &config::list_profiles()) else { continue };
+                let location = Location::Profile(profile);
+                if location == config.location {
+                    continue;
+                }
+                println!("👤 Opening profile {}...", location.label());
+                match Config::load(location) {
+                    Ok(loaded) => {
+                        // Nothing from the old profile carries over, running
+                        // forwards and cached sessions included.
+                        app.stop_forwards(|_| true);
+                        config = loaded;
+                        config.kdf = settings.kdf();
+                        config.encrypt = !settings.plaintext_servers;
+                        warn_if_decrypting(&config);
+                        app = App {
+                            assume_yes: app.assume_yes,
+                            sort_mode: app.sort_mode,
+                            privacy_mode: app.privacy_mode,
+                            key_scan: Some(keycheck::spawn_scan(&config.servers)),
+                            ..Default::default()
+                        };
+                    }
+                    Err(e) => app.error_message = Some(format!("Could not open profile: {}", e)),
+                }
+            }
+            ui::Action::ChangeMasterPassword => {
+                match config.change_master_password() {
+                    Ok(()) => println!("✅ Master password changed."),
+                    Err(e) => println!("❌ Master password not changed: {}", e),
+                }
+                std::thread::sleep(std::time::Duration::from_millis(1000));
+            }
+            ui::Action::Help => ui::show_help(),
             ui::Action::Exit => {
+                if let Some(busy) = app.busy() {
+                    let prompt = format!("{}. Quit anyway?", busy);
+                    if !ui::confirm(ui::Confirmation::Quit, &prompt, settings.fast_mode || app.assume_yes) {
+                        continue;
+                    }
+                }
+                if !app.forwards.is_empty() {
+                    println!("🔌 Closing {} port forward(s)...", app.forwards.len());
+                    app.stop_forwards(|_| true);
+                }
                 println!("👋 Bye!");
                 break;
             }
@@ -129,33 +695,626 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
-fn create_session(server: &Server) -> Result<Session, Box<dyn std::error::Error>> {
-    let tcp = TcpStream::connect(format!("{}:{}", server.host, server.port))?;
-    let mut sess = Session::new()?;
-    sess.set_tcp_stream(tcp);
-    sess.handshake()?;
+/// Warns before an encrypted server list gets saved in plain text just
+/// because encryption was turned off while another profile was open.
+fn warn_if_decrypting(config: &Config) {
+    if config.will_decrypt_on_save() {
+        eprintln!("⚠️  The server list for {} is encrypted, but encryption is off in Settings: the next change saves it in plain text. Turn Encrypt server lists back on to keep it encrypted.", config.location.label());
+    }
+}
+
+fn add_server(config: &mut Config, draft: ServerDraft, settings: &Settings) -> Result<(), Box<dyn std::error::Error>> {
+    let Some(server) = ui::add_server_prompt(draft, &config.names_except(None), settings) else {
+        println!("🚫 Server not saved.");
+        std::thread::sleep(std::time::Duration::from_millis(1000));
+        return Ok(());
+    };
+    config.add_server(server);
+    config.save()?;
+    ServerDraft::clear();
+    println!("✅ Server added successfully!");
+    std::thread::sleep(std::time::Duration::from_millis(1000));
+    Ok(())
+}
 
+fn run_command(config: &mut Config, settings: &Settings, app: &mut App, command: Command) -> Result<(), Box<dyn std::error::Error>> {
+    match command {
+        Command::Connect { name } => connect_by_name(config, settings, app, &name, false)?,
+        Command::Sftp { name } => connect_by_name(config, settings, app, &name, true)?,
+        Command::List { json, group } => {
+            let listed: Vec<&Server> = settings.sort_mode.order(&config.servers).into_iter()
+                .map(|i| &config.servers[i])
+                .filter(|s| group.as_ref().is_none_or(|g| s.group.to_lowercase() == g.trim().to_lowercase()))
+                .collect();
+            if json {
+                let entries: Vec<serde_json::Value> = listed.iter().map(|s| serde_json::json!({
+                    "name": s.name,
+                    "group": s.group,
+                    "tags": s.tags,
+                    "user": s.user,
+                    "host": s.host,
+                    "port": s.port,
+                    "auth": match s.auth_type {
+                        AuthType::Password(_) => "password",
+                        AuthType::Key(_) => "key",
+                        AuthType::Agent => "agent",
+                        AuthType::Certificate { .. } => "cert",
+                        AuthType::KeyboardInteractive => "keyboard-interactive",
+                    },
+                    "jump_host": s.jump_host,
+                })).collect();
+                println!("{}", serde_json::to_string_pretty(&entries)?);
+            } else {
+                for s in listed {
+                    println!("[{}] {} ({}@{})", s.group, s.name, s.user, config::host_port(&s.host, s.port));
+                }
+            }
+        }
+        Command::ImportCsv { path } => {
+            let report = config.import_csv(&path)?;
+            if report.added > 0 {
+                config.save()?;
+            }
+            print_import_report(&report);
+        }
+        Command::ImportJson { path, mappings } => {
+            let mut mapping = FieldMapping::default();
+            for pair in &mappings {
+                mapping.set(pair)?;
+            }
+            let report = config.import_json(&path, &mapping)?;
+            if report.added > 0 {
+                config.save()?;
+            }
+            print_import_report(&report);
+        }
+        Command::ImportHosts { path, group } => {
+            let report = config.import_hostlist(&path, &group)?;
+            if report.added > 0 {
+                config.save()?;
+            }
+            print_import_report(&report);
+        }
+        Command::ImportPutty { path } => {
+            let report = config.import_putty(&path)?;
+            if report.added > 0 {
+                config.save()?;
+            }
+            print_import_report(&report);
+        }
+    }
+    Ok(())
+}
+
+/// Prompts for what an import needs and runs it.
+fn run_import(config: &mut Config, source: ui::ImportSource) -> Result<ImportReport, Box<dyn std::error::Error>> {
+    match source {
+        ui::ImportSource::Csv => {
+            let path = ui::get_local_path("CSV file path");
+            config.import_csv(Path::new(&path))
+        }
+        ui::ImportSource::Json => {
+            let path = ui::get_local_path("Inventory JSON path");
+            let mapping = FieldMapping::parse(&ui::field_mapping_prompt())?;
+            config.import_json(Path::new(&path), &mapping)
+        }
+        ui::ImportSource::HostList => {
+            let path = ui::get_local_path("Host list path");
+            let group = ui::group_prompt("Imported");
+            config.import_hostlist(Path::new(&path), &group)
+        }
+        ui::ImportSource::Putty => {
+            let path = ui::get_local_path("PuTTY .reg file path");
+            config.import_putty(Path::new(&path))
+        }
+        ui::ImportSource::Plaintext => {
+            let path = ui::get_local_path("Export file path");
+            config.import_plaintext(Path::new(&path))
+        }
+        ui::ImportSource::SshConfig | ui::ImportSource::SshConfigSync => {
+            println!("📥 Importing servers from ~/.ssh/config...");
+            config.import_ssh_config(matches!(source, ui::ImportSource::SshConfigSync))
+        }
+    }
+}
+
+fn print_import_report(report: &ImportReport) {
+    println!("✅ Imported {} servers.", report.added);
+    if !report.updated.is_empty() {
+        println!("🔄 Updated {}: {}", report.updated.len(), report.updated.join(", "));
+    }
+    if !report.duplicates.is_empty() {
+        println!("⏭️  Skipped {} duplicates: {}", report.duplicates.len(), report.duplicates.join(", "));
+    }
+    for error in &report.errors {
+        println!("⚠️  Skipped {}", error);
+    }
+}
+
+/// Everything known about a server, one line per field, for the details pager.
+fn server_details(server: &Server, app: &App, settings: &Settings) -> String {
+    let mut lines = Vec::new();
+    lines.push(format!("   Group: {}", server.group));
+    if !server.tags.is_empty() {
+        lines.push(format!("   Tags:  {}", server.tags.iter().map(|t| format!("#{}", t)).collect::<Vec<_>>().join(" ")));
+    }
+    lines.push(format!("   User:  {}", app.user(server)));
+    lines.push(format!("   Host:  {}", app.host(server)));
+    lines.push(format!("   Port:  {}", server.port));
+    match server.last_connected {
+        Some(at) => lines.push(format!("   Last connected: {}", timefmt::format_timestamp(at, settings))),
+        None => lines.push("   Last connected: Never".to_string()),
+    }
+    match &server.host_key {
+        Some(fingerprint) => lines.push(format!("   Host key: {}", fingerprint)),
+        None => lines.push("   Host key: not seen yet (connect once to record it)".to_string()),
+    }
+    match &server.transport {
+        Transport::Tcp => {}
+        Transport::UnixSocket(path) => lines.push(format!("   Via:   Unix socket {}", app.path(path))),
+        Transport::ProxyCommand(command) => {
+            let command = transport::expand_proxy_command(command, server);
+            lines.push(format!("   Via:   {}", app.scrub(server, &command)));
+        }
+    }
     match &server.auth_type {
-        AuthType::Password(p) => sess.userauth_password(&server.user, p)?,
-        AuthType::Key(p) => sess.userauth_pubkey_file(&server.user, None, std::path::Path::new(p), None)?,
-        AuthType::Agent => {
-            sess.userauth_agent(&server.user)?;
+        AuthType::Password(_) => lines.push("   Auth:  Password".to_string()),
+        AuthType::Key(path) => lines.push(format!("   Auth:  Key ({})", app.path(path))),
+        AuthType::Agent => match &server.agent_identity {
+            Some(identity) => lines.push(format!("   Auth:  Agent ({})", identity)),
+            None => lines.push("   Auth:  Agent".to_string()),
+        },
+        AuthType::KeyboardInteractive => lines.push("   Auth:  Keyboard-interactive".to_string()),
+        AuthType::Certificate { key_path, cert_path } => {
+            lines.push(format!("   Auth:  Certificate ({} + {})", app.path(key_path), app.path(cert_path)));
+            match CertInfo::load(Path::new(cert_path)) {
+                Ok(cert) => {
+                    let cert_line = format!("{} [{}]", cert.key_id, cert.principals.join(", "));
+                    lines.push(format!("   Cert:  {}", app.scrub(server, &cert_line)));
+                    lines.push(format!("   Valid: {} {}", cert.validity_window(settings), cert.status()));
+                }
+                Err(e) => lines.push(format!("   Cert:  ⚠️  {}", e)),
+            }
         }
     }
+    for option in &server.ssh_options {
+        lines.push(format!("   ssh -o {}", option));
+    }
+    if let Some(port) = server.web_port {
+        lines.push(format!("   Web UI: port {}", port));
+    }
+    if let Some(family) = server.address_family {
+        lines.push(format!("   Address family: {}", family.label()));
+    }
+    if let Some(jump) = &server.jump_host {
+        lines.push(format!("   Jump host: {}", jump));
+    }
+    for (name, value) in &server.env {
+        lines.push(format!("   Env:   {}={}", name, value));
+    }
+    match proxy::for_server(server, settings) {
+        Ok(Some(proxy)) => lines.push(format!("   Proxy: {}", proxy)),
+        Ok(None) => {}
+        Err(e) => lines.push(format!("   Proxy: ⚠️  {}", e)),
+    }
+    if let Some(problem) = app.key_problems.get(&server.name) {
+        lines.push(format!("   ⚠️  {}", problem));
+    }
+    if let Some(last_error) = app.last_error(server) {
+        lines.push(format!("   Last error: {}", app.scrub(server, &last_error.describe(settings))));
+    }
+    lines.join("\n")
+}
 
-    if !sess.authenticated() {
-        return Err("Authentication failed".into());
+/// Re-checks every server's key files, reports the broken ones and offers
+/// to fix each.
+fn check_key_files(config: &mut Config, app: &mut App) -> Result<(), Box<dyn std::error::Error>> {
+    app.key_scan = None;
+    app.key_problems = config.servers.iter()
+        .filter_map(|s| keycheck::check(s).map(|problem| (s.name.clone(), problem)))
+        .collect();
+    if app.key_problems.is_empty() {
+        println!("✅ All key and certificate files are present and readable.");
+        return Ok(());
+    }
+
+    println!("⚠️  {} server(s) have key file problems:", app.key_problems.len());
+    for server in &config.servers {
+        if let Some(problem) = app.key_problems.get(&server.name) {
+            println!("   [{}] {}: {}", server.group, server.name, problem);
+        }
+    }
+
+    let mut changed = false;
+    for server in config.servers.iter_mut() {
+        let Some(problem) = app.key_problems.get(&server.name) else { continue };
+        match ui::key_fix_prompt(server, problem) {
+            ui::KeyFix::Repoint(auth_type) => server.auth_type = auth_type,
+            ui::KeyFix::UseAgent => server.auth_type = AuthType::Agent,
+            ui::KeyFix::Skip => continue,
+        }
+        changed = true;
+        match keycheck::check(server) {
+            Some(problem) => {
+                println!("⚠️  Still broken: {}", problem);
+                app.key_problems.insert(server.name.clone(), problem);
+            }
+            None => {
+                app.key_problems.remove(&server.name);
+            }
+        }
+    }
+    if changed {
+        config.save()?;
+        println!("💾 Saved.");
+    }
+    Ok(())
+}
+
+/// Walks through the connection one phase at a time and reports where it breaks.
+fn diagnose(server: &Server, servers: &[Server], app: &mut App, settings: &Settings) {
+    println!("🩺 Diagnosing {} ({})...", server.name, app.address(server));
+
+    let result = (|| -> Result<(), (&str, Box<dyn std::error::Error>)> {
+        let started = Instant::now();
+        session::check_credentials(server, settings).map_err(|e| ("credentials", e))?;
+        let stream = session::open_stream(server, servers, settings).map_err(|e| ("transport", e))?;
+        println!("   ✅ Transport connected in {:?}", started.elapsed());
+
+        let started = Instant::now();
+        let sess = session::handshake(stream, settings).map_err(|e| ("handshake", e))?;
+        println!("   ✅ Handshake completed in {:?}", started.elapsed());
+        if let Some(banner) = sess.banner() {
+            println!("      Server: {}", banner.trim());
+        }
+        session::verify_host_key(&sess, server).map_err(|e| ("host key", e))?;
+        println!("   ✅ Host key verified");
+        match sess.auth_methods(&server.user) {
+            Ok(methods) => println!("      Offered auth methods: {}", methods),
+            Err(e) => println!("      Could not list auth methods: {}", e),
+        }
+
+        let started = Instant::now();
+        session::authenticate(&sess, server).map_err(|e| ("authentication", e))?;
+        println!("   ✅ Authenticated in {:?}", started.elapsed());
+        Ok(())
+    })();
+
+    match result {
+        Ok(()) => {
+            println!("✅ All phases passed.");
+            app.record_success(server);
+        }
+        Err((phase, e)) => {
+            println!("   ❌ {} failed: {}", phase, e);
+            println!("❌ Connection breaks at the {} phase.", phase);
+            app.record_failure(server, &e);
+        }
+    }
+}
+
+/// Starts each of the server's forwards that isn't already running. Every
+/// forward gets its own session, since the forwarding thread owns it.
+fn start_forwards(server: &Server, servers: &[Server], app: &mut App, settings: &Settings) {
+    for spec_text in &server.forwards {
+        if app.forwards.iter().any(|f| f.server == server.name && &f.spec == spec_text) {
+            continue;
+        }
+        let result = forward::ForwardSpec::parse(spec_text)
+            .map_err(|e| e.into())
+            .and_then(|spec| {
+                let sess = session::create_session(server, servers, settings)?;
+                spec.start(sess)
+            });
+        match result {
+            Ok(tunnel) => {
+                println!("🔀 {} running", spec_text);
+                app.forwards.push(ActiveForward { server: server.name.clone(), spec: spec_text.clone(), tunnel });
+            }
+            Err(e) => println!("❌ {}: {}", spec_text, e),
+        }
+    }
+}
+
+/// Builds the arguments for the system `ssh` client, ending with the destination.
+fn system_ssh_args(server: &Server, servers: &[Server]) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let mut args = vec!["-p".to_string(), server.port.to_string()];
+    let chain = session::jump_chain(server, servers)?;
+    if !chain.is_empty() {
+        let hops: Vec<String> = chain.iter().map(|s| format!("{}@{}", s.user, config::host_port(&s.host, s.port))).collect();
+        args.extend(["-J".to_string(), hops.join(",")]);
+    }
+    match &server.auth_type {
+        AuthType::Key(path) => args.extend(["-i".to_string(), path.clone()]),
+        AuthType::Certificate { key_path, cert_path } => args.extend([
+            "-i".to_string(), key_path.clone(),
+            "-o".to_string(), format!("CertificateFile={}", cert_path),
+        ]),
+        AuthType::Password(_) | AuthType::Agent | AuthType::KeyboardInteractive => {}
+    }
+    match &server.transport {
+        Transport::Tcp => {}
+        // ssh expands %h/%p/%r in ProxyCommand itself.
+        Transport::ProxyCommand(command) => args.extend(["-o".to_string(), format!("ProxyCommand={}", command)]),
+        Transport::UnixSocket(_) => return Err("The system ssh path does not support Unix socket transports".into()),
+    }
+    for option in &server.ssh_options {
+        args.extend(["-o".to_string(), option.clone()]);
+    }
+    args.push(format!("{}@{}", server.user, server.host));
+    Ok(args)
+}
+
+/// A command line for `server` to paste into another terminal. The scp
+/// form ends at the remote side; files to send go in front of it.
+fn copy_command(server: &Server, servers: &[Server], format: ui::CopyFormat) -> Result<String, Box<dyn std::error::Error>> {
+    let mut args = system_ssh_args(server, servers)?;
+    let program = match format {
+        ui::CopyFormat::Ssh => "ssh",
+        ui::CopyFormat::Scp => {
+            // scp spells the port -P and needs brackets around IPv6 hosts.
+            args[0] = "-P".to_string();
+            if let Some(destination) = args.last_mut() {
+                *destination = format!("{}@{}:", server.user, config::bracket_host(&server.host));
+            }
+            "scp"
+        }
+    };
+    let quoted: Vec<String> = args.iter().map(|arg| shell_quote(arg)).collect();
+    Ok(format!("{} {}", program, quoted.join(" ")))
+}
+
+/// Single-quotes `arg` for a POSIX shell unless it is plainly safe.
+fn shell_quote(arg: &str) -> String {
+    let safe = !arg.is_empty() && arg.chars().all(|c| c.is_ascii_alphanumeric() || "@%+=:,./-_~".contains(c));
+    if safe {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', "'\\''"))
     }
-    
-    Ok(sess)
 }
 
-fn run_shell(sess: Session) -> Result<(), Box<dyn std::error::Error>> {
+fn run_system_ssh(server: &Server, servers: &[Server], app: &App) -> Result<(), Box<dyn std::error::Error>> {
+    let args = system_ssh_args(server, servers)?;
+    if let AuthType::Password(_) = server.auth_type {
+        println!("ℹ️  The system ssh client can't use the stored password; type it when asked.");
+    }
+    if app.privacy_mode {
+        println!("🖥️  ssh ({})", server.name);
+    } else {
+        println!("🖥️  ssh {}", args.join(" "));
+    }
+    let status = std::process::Command::new("ssh").args(&args).status()
+        .map_err(|e| format!("Could not run ssh: {}", e))?;
+    if !status.success() {
+        println!("⚠️  ssh exited with {}", status);
+    }
+    Ok(())
+}
+
+/// `connect` and `sftp`: a shell, or the SFTP menu, for one server without
+/// the main menu. An unknown name or a failure exits with status 1.
+fn connect_by_name(config: &mut Config, settings: &Settings, app: &mut App, name: &str, sftp: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let Some(server) = find_server(&config.servers, name).cloned() else {
+        eprintln!("❌ No server named '{}' (see `sshmanager list`).", name);
+        std::process::exit(1);
+    };
+    if sftp {
+        println!("🚀 Connecting to {} for SFTP...", server.name);
+    } else {
+        println!("🚀 Connecting to {} ({}@{})...", server.name, server.user, server.host);
+    }
+    let started = SystemTime::now();
+    let result = app.connect(&server, &config.servers, settings)
+        .map_err(|e| format!("Connection failed: {}", e))
+        .and_then(|sess| if sftp {
+            run_sftp(sess, &server, &config.servers, settings).map_err(|e| format!("SFTP failed: {}", e))
+        } else {
+            run_shell(sess, &server, None, settings).map_err(|e| format!("Connection failed: {}", e))
+        });
+    if !sftp {
+        record_history(&server, started, result.is_ok(), settings);
+    }
+    if app.stamp_connections(&mut config.servers) {
+        config.save()?;
+    }
+    if let Err(e) = result {
+        eprintln!("❌ {}", e);
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// The server called `name`, ignoring case as the add form does.
+fn find_server<'a>(servers: &'a [Server], name: &str) -> Option<&'a Server> {
+    servers.iter().find(|s| s.name == name)
+        .or_else(|| servers.iter().find(|s| s.name.to_lowercase() == name.trim().to_lowercase()))
+}
+
+/// Opens a shell to each of `members` in its own tmux window: in the
+/// current tmux session when run inside one, otherwise in a new session that
+/// is attached until you detach or close every window. Each window runs
+/// `sshmanager connect <name>` against the same server list. Without tmux,
+/// the servers are connected to one after another instead.
+fn connect_group(members: &[&Server], config: &Config, app: &mut App, settings: &Settings) -> Result<(), Box<dyn std::error::Error>> {
+    let has_tmux = std::process::Command::new("tmux").arg("-V").output().is_ok_and(|out| out.status.success());
+    if !has_tmux {
+        println!("ℹ️  tmux is not installed; connecting to the {} servers one after another.", members.len());
+        for (i, server) in members.iter().enumerate() {
+            println!("\n🚀 [{}/{}] Connecting to {} ({}@{})...", i + 1, members.len(), server.name, app.user(server), app.host(server));
+            let started = SystemTime::now();
+            let result = app.connect(server, &config.servers, settings)
+                .and_then(|sess| run_shell(sess, server, None, settings));
+            if let Err(e) = &result {
+                app.sessions.invalidate(server);
+                println!("❌ {}: {}", server.name, e);
+            }
+            record_history(server, started, result.is_ok(), settings);
+        }
+        println!("\nPress Enter to continue...");
+        let _ = std::io::stdin().read_line(&mut String::new());
+        return Ok(());
+    }
+
+    let mut base = vec![std::env::current_exe()?.display().to_string()];
+    match &config.location {
+        Location::File(path) => base.extend(["--config".to_string(), path.display().to_string()]),
+        Location::Profile(Some(profile)) => base.extend(["--profile".to_string(), profile.clone()]),
+        Location::Profile(None) => {}
+    }
+    // Keep a failed connection's window open long enough to read why.
+    let window_command = |server: &Server| {
+        let args: Vec<String> = base.iter().cloned().chain(["connect".to_string(), server.name.clone()]).collect();
+        let quoted: Vec<String> = args.iter().map(|arg| shell_quote(arg)).collect();
+        format!("{} || {{ printf '\\nPress Enter to close this window'; read _; }}", quoted.join(" "))
+    };
+    let tmux = |args: &[&str]| -> Result<String, Box<dyn std::error::Error>> {
+        let out = std::process::Command::new("tmux").args(args).output()
+            .map_err(|e| format!("Could not run tmux: {}", e))?;
+        if !out.status.success() {
+            return Err(format!("tmux {} failed: {}", args[0], String::from_utf8_lossy(&out.stderr).trim()).into());
+        }
+        Ok(String::from_utf8_lossy(&out.stdout).trim().to_string())
+    };
+
+    if std::env::var_os("TMUX").is_some() {
+        for server in members {
+            tmux(&["new-window", "-d", "-n", &server.name, &window_command(server)])?;
+        }
+        println!("🪟 Opened {} tmux windows in this session.", members.len());
+        return Ok(());
+    }
+
+    let (first, rest) = members.split_first().ok_or("The group has no servers")?;
+    let session = tmux(&["new-session", "-d", "-P", "-F", "#{session_id}", "-n", &first.name, &window_command(first)])?;
+    for server in rest {
+        tmux(&["new-window", "-d", "-t", &format!("{}:", session), "-n", &server.name, &window_command(server)])?;
+    }
+    std::process::Command::new("tmux").args(["attach-session", "-t", &session]).status()
+        .map_err(|e| format!("Could not attach to tmux: {}", e))?;
+    Ok(())
+}
+
+/// Best effort: a history that can't be written shouldn't get in the way.
+fn record_history(server: &Server, started: SystemTime, success: bool, settings: &Settings) {
+    if let Err(e) = history::record(history::Entry::new(&server.name, started, success), settings.history_limit) {
+        println!("⚠️  Could not update the connection history: {}", e);
+    }
+}
+
+/// The most used servers, then every recorded session, newest first.
+fn history_report(settings: &Settings) -> String {
+    let entries = match history::load() {
+        Ok(entries) => entries,
+        Err(e) => return format!("⚠️  {}", e),
+    };
+    if entries.is_empty() {
+        return "No connections recorded yet.".to_string();
+    }
+
+    let mut counts: Vec<(&str, usize)> = Vec::new();
+    for entry in entries.iter().filter(|e| e.success) {
+        match counts.iter_mut().find(|(name, _)| *name == entry.server) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((&entry.server, 1)),
+        }
+    }
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+
+    let mut lines = Vec::new();
+    if !counts.is_empty() {
+        lines.push("Most used:".to_string());
+        for (name, count) in counts.iter().take(5) {
+            lines.push(format!("   {:>4} × {}", count, name));
+        }
+        lines.push(String::new());
+    }
+    let width = entries.iter().map(|e| e.server.chars().count()).max().unwrap_or(0);
+    for entry in entries.iter().rev() {
+        let when = entry.at.map(|at| timefmt::format_timestamp(at, settings)).unwrap_or_default();
+        let (mark, duration) = match entry.success {
+            true => ("✅", history::format_duration(std::time::Duration::from_secs(entry.duration_secs))),
+            false => ("❌", "failed".to_string()),
+        };
+        lines.push(format!("{} {:<width$}  {:>9}  {}", mark, entry.server, duration, when));
+    }
+    lines.join("\n")
+}
+
+/// Creates `session-logs/<server>-<timestamp>.log` under the config dir,
+/// readable only by you.
+fn open_session_log(server: &Server) -> Result<(PathBuf, std::fs::File), Box<dyn std::error::Error>> {
+    let dir = config::get_config_dir()?.join("session-logs");
+    std::fs::create_dir_all(&dir)?;
+    let name: String = server.name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || "-_.".contains(c) { c } else { '_' })
+        .collect();
+    let path = dir.join(format!("{}-{}.log", name, chrono::Local::now().format("%Y%m%d-%H%M%S")));
+    let mut options = std::fs::OpenOptions::new();
+    options.create(true).append(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let file = options.open(&path)?;
+    Ok((path, file))
+}
+
+/// Opens an interactive shell. `initial_input` is typed into it as if the
+/// user had entered it, e.g. a pinned command. With session recording on,
+/// everything the shell prints is also appended to a log file.
+fn run_shell(sess: Session, server: &Server, initial_input: Option<&str>, settings: &Settings) -> Result<(), Box<dyn std::error::Error>> {
+    let mut log = None;
+    if settings.record_sessions {
+        match open_session_log(server) {
+            Ok(opened) => log = Some(opened),
+            Err(e) => println!("⚠️  Could not start the session log ({}); not recording.", e),
+        }
+    }
+
     let mut channel = sess.channel_session()?;
-    channel.request_pty("xterm-256color", None, None)?;
+    let tty = std::io::stdin().is_terminal();
+    // Full-screen programs lay themselves out for the PTY size, so it has
+    // to match the local terminal, now and whenever it is resized.
+    let mut size = crossterm::terminal::size().ok();
+    if tty {
+        let dimensions = size.map(|(columns, rows)| (columns as u32, rows as u32, 0, 0));
+        channel.request_pty(&settings.remote_term(), None, dimensions)?;
+    }
+    // Many servers only accept a few names (sshd's AcceptEnv), so a refusal
+    // is worth a warning but not worth failing the shell over.
+    for (name, value) in &server.env {
+        if let Err(e) = channel.setenv(name, value) {
+            println!("⚠️  {} refused {} ({}); it may need adding to AcceptEnv in sshd_config.", server.name, name, e);
+        }
+    }
     channel.shell()?;
+    if let Some(input) = initial_input {
+        channel.write_all(format!("{}\n", input).as_bytes())?;
+    }
 
-    enable_raw_mode()?;
+    // Without raw mode, input still reaches the remote, just a line at a
+    // time. In raw mode Ctrl-C is an ordinary byte for the remote side
+    // rather than a signal to this process.
+    let raw_mode = if tty {
+        match ui::RawMode::enable() {
+            Ok(guard) => Some(guard),
+            Err(e) => {
+                println!("⚠️  Could not enable raw mode ({}); falling back to line mode.", e);
+                None
+            }
+        }
+    } else {
+        None
+    };
+    if !tty {
+        println!("ℹ️  Not a terminal: running in line mode without a PTY.");
+    }
+    if settings.keepalive_secs > 0 {
+        sess.set_keepalive(true, settings.keepalive_secs.min(u32::MAX as u64) as u32);
+    }
+    let mut next_keepalive = Instant::now();
     sess.set_blocking(false);
 
     let (tx, rx) = mpsc::channel();
@@ -175,83 +1334,365 @@ fn run_shell(sess: Session) -> Result<(), Box<dyn std::error::Error>> {
     let mut buf = [0u8; 2048];
     let mut stdout = std::io::stdout();
 
+    // Once the reader thread is gone (end of input, or it panicked), the
+    // remote side is told there's no more input instead of waiting forever.
+    let mut stdin_open = true;
+    let mut log_error = None;
+    let result = (|| -> Result<(), Box<dyn std::error::Error>> {
+        loop {
+            loop {
+                match rx.try_recv() {
+                    Ok(byte) => {
+                        let _ = channel.write(&[byte]);
+                    }
+                    Err(mpsc::TryRecvError::Empty) => break,
+                    Err(mpsc::TryRecvError::Disconnected) => {
+                        if stdin_open && channel.send_eof().is_ok() {
+                            stdin_open = false;
+                        }
+                        break;
+                    }
+                }
+            }
+
+            // Polled rather than trapping SIGWINCH; the check is one ioctl. If
+            // the request would block, it is sent again on the next pass.
+            if tty {
+                let current = crossterm::terminal::size().ok();
+                if let (Some((columns, rows)), true) = (current, current != size) {
+                    if channel.request_pty_size(columns as u32, rows as u32, None, None).is_ok() {
+                        size = current;
+                    }
+                }
+            }
+
+            match channel.read(&mut buf) {
+                Ok(0) => { if channel.eof() { return Ok(()); } }
+                Ok(n) => {
+                    stdout.write_all(&buf[..n])?;
+                    stdout.flush()?;
+                    // A full disk shouldn't take the shell down with it.
+                    if let (Some((_, file)), None) = (&mut log, &log_error) {
+                        if let Err(e) = file.write_all(&buf[..n]) {
+                            log_error = Some(e);
+                        }
+                    }
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {},
+                Err(e) => return Err(e.into()),
+            }
+
+            if channel.eof() { return Ok(()); }
+
+            // libssh2 only sends a keepalive when due, and says when the next
+            // one is, so this is cheap to call while traffic is flowing.
+            if settings.keepalive_secs > 0 && Instant::now() >= next_keepalive {
+                let wait = sess.keepalive_send().unwrap_or(1).max(1);
+                next_keepalive = Instant::now() + std::time::Duration::from_secs(wait as u64);
+            }
+            thread::sleep(std::time::Duration::from_millis(5));
+        }
+    })();
+
+    let _ = channel.close();
+    let _ = channel.wait_close();
+    sess.set_blocking(true);
+    // The session may be cached and reused for SFTP or commands, which
+    // expect libssh2's default of no keepalives.
+    sess.set_keepalive(false, 0);
+    drop(raw_mode);
+    match (&log, log_error) {
+        (Some((path, _)), None) => println!("\n📝 Session recorded to {}", path.display()),
+        (Some((path, _)), Some(e)) => println!("\n⚠️  Session log {} is incomplete: writing it failed ({})", path.display(), e),
+        (None, _) => {}
+    }
+    result
+}
+
+/// Runs a single command without a PTY, streaming its output, and returns its exit status.
+fn run_exec(sess: Session, command: &str) -> Result<i32, Box<dyn std::error::Error>> {
+    exec(sess, command, &mut std::io::stdout())
+}
+
+/// A shell on `sess`, then whatever the user picks next on the same session
+/// (another shell, SFTP or a one-off command) until they go back. Holding
+/// on to `sess` keeps this to one login even with session reuse off. Each
+/// shell goes into the connection history, the first one timed from
+/// `started`; SFTP and commands don't.
+fn session_loop(sess: Session, index: usize, config: &mut Config, mut started: SystemTime, settings: &Settings) -> Result<(), String> {
+    let mut next = ui::SessionChoice::Shell;
     loop {
-        while let Ok(byte) = rx.try_recv() {
-            let _ = channel.write(&[byte]);
+        let server = &config.servers[index];
+        match next {
+            ui::SessionChoice::Shell => {
+                let result = run_shell(sess.clone(), server, None, settings);
+                record_history(server, started, result.is_ok(), settings);
+                result.map_err(|e| format!("Connection failed: {}", e))?;
+            }
+            ui::SessionChoice::Sftp => {
+                run_sftp(sess.clone(), server, &config.servers, settings).map_err(|e| format!("SFTP failed: {}", e))?;
+            }
+            ui::SessionChoice::Command => {
+                if let Some(command) = ui::one_shot_command_prompt(server) {
+                    config.servers[index].remember_command(&command);
+                    if let Err(e) = config.save() {
+                        println!("⚠️  Could not save the command to recent commands: {}", e);
+                    }
+                    let server = &config.servers[index];
+                    let (output, status) = capture_exec(sess.clone(), &command).map_err(|e| format!("Command failed: {}", e))?;
+                    ui::show_output(&format!("{} $ {} (exit status {})", server.name, command, status), &output);
+                    if lock::locked() {
+                        return Ok(());
+                    }
+                }
+            }
+            ui::SessionChoice::Back => return Ok(()),
         }
+        println!();
+        next = ui::session_menu(&config.servers[index]);
+        started = SystemTime::now();
+    }
+}
 
-        match channel.read(&mut buf) {
-            Ok(0) => { if channel.eof() { break; } }
-            Ok(n) => { stdout.write_all(&buf[..n])?; stdout.flush()?; }
-            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {},
-            Err(e) => return Err(e.into()),
+/// Runs `command` on each of `indices` in turn, one section per server with
+/// its output and exit status, or why it couldn't run there.
+fn run_on_each(indices: &[usize], command: &str, servers: &[Server], app: &mut App, settings: &Settings) -> String {
+    let mut report = String::new();
+    for &index in indices {
+        let server = &servers[index];
+        println!("⏳ Running on {}: {}", server.name, command);
+        let result = match app.connect(server, servers, settings) {
+            Ok(sess) => capture_exec(sess, command).map_err(|e| {
+                app.sessions.invalidate(server);
+                format!("Command failed: {}", e)
+            }),
+            Err(e) => Err(format!("Connection failed: {}", e)),
+        };
+        match result {
+            Ok((output, status)) => {
+                report.push_str(&format!("── {} (exit status {}) ──\n{}", server.name, status, output));
+                if !output.is_empty() && !output.ends_with('\n') {
+                    report.push('\n');
+                }
+            }
+            Err(e) => report.push_str(&format!("── {} ──\n❌ {}\n", server.name, e)),
         }
+        report.push('\n');
+    }
+    report
+}
+
+/// Runs a single command without a PTY and returns its output and exit status.
+fn capture_exec(sess: Session, command: &str) -> Result<(String, i32), Box<dyn std::error::Error>> {
+    let mut output = Vec::new();
+    let status = exec(sess, command, &mut output)?;
+    Ok((String::from_utf8_lossy(&output).into_owned(), status))
+}
 
-        if channel.eof() { break; }
-        thread::sleep(std::time::Duration::from_millis(5));
+/// Writes the command's stdout and stderr, interleaved as they arrive, to `out`.
+fn exec(sess: Session, command: &str, out: &mut dyn Write) -> Result<i32, Box<dyn std::error::Error>> {
+    let mut channel = sess.channel_session()?;
+    channel.handle_extended_data(ssh2::ExtendedData::Merge)?;
+    channel.exec(command)?;
+
+    let mut buf = [0u8; 4096];
+    loop {
+        let n = channel.read(&mut buf)?;
+        if n == 0 { break; }
+        out.write_all(&buf[..n])?;
+        out.flush()?;
     }
-    
-    let _ = channel.close();
-    let _ = channel.wait_close();
-    disable_raw_mode()?;
+
+    channel.wait_close()?;
+    Ok(channel.exit_status()?)
+}
+
+/// Forwards a free local port to `web_port` on the server and opens it in
+/// the browser, keeping the tunnel up until the user presses Enter.
+fn open_web_ui(sess: Session, web_port: u16) -> Result<(), Box<dyn std::error::Error>> {
+    let tunnel = forward::Tunnel::local(sess, "127.0.0.1:0", "localhost", web_port)?;
+    let url = format!("http://localhost:{}", tunnel.local_addr.port());
+    println!("🌐 Forwarding {} → server port {}", url, web_port);
+    if let Err(e) = open::that(&url) {
+        println!("⚠️  Could not open a browser ({}). Open {} yourself.", e, url);
+    }
+    println!("Press Enter to close the tunnel...");
+    let _ = std::io::stdin().read_line(&mut String::new());
+    for failure in tunnel.failures() {
+        println!("⚠️  {}", failure);
+    }
+    tunnel.close();
+    println!("🔌 Tunnel closed.");
     Ok(())
 }
 
-fn run_sftp(sess: Session) -> Result<(), Box<dyn std::error::Error>> {
+/// Transfers and file management on one server until the user goes back.
+/// A failed transfer ends the session; a failed file operation is reported
+/// and the menu offered again.
+fn run_sftp(sess: Session, server: &Server, servers: &[Server], settings: &Settings) -> Result<(), Box<dyn std::error::Error>> {
     let sftp = sess.sftp()?;
-    let direction = ui::file_transfer_menu();
-    
-    match direction {
-        ui::TransferDirection::Upload => {
-            let local_path = ui::get_local_path("Local file path");
-            let remote_path = ui::get_remote_path("Remote destination path");
-            
-            let mut file = File::open(&local_path)?;
-            let file_size = file.metadata()?.len();
-            
-            let mut remote_file = sftp.create(Path::new(&remote_path))?;
-            
-            let pb = ProgressBar::new(file_size);
-            pb.set_style(ProgressStyle::default_bar()
-                .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({eta})")
-                .unwrap()
-                .progress_chars("#>-"));
-            
-            let mut buffer = [0u8; 8192]; // 8KB chunks
-            loop {
-                let n = file.read(&mut buffer)?;
-                if n == 0 { break; }
-                remote_file.write_all(&buffer[..n])?;
-                pb.inc(n as u64);
-            }
-            pb.finish_with_message("Upload complete");
-        },
-        ui::TransferDirection::Download => {
-            let remote_path = ui::get_remote_path("Remote file path");
-            let local_path = ui::get_local_path("Local destination path");
-            
-            let mut remote_file = sftp.open(Path::new(&remote_path))?;
-            let file_stat = remote_file.stat()?;
-            let file_size = file_stat.size.unwrap_or(0);
-            
-            let mut file = File::create(&local_path)?;
-            
-            let pb = ProgressBar::new(file_size);
-            pb.set_style(ProgressStyle::default_bar()
-                .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({eta})")
-                .unwrap()
-                .progress_chars("#>-"));
-                
-            let mut buffer = [0u8; 8192];
-            loop {
-                let n = remote_file.read(&mut buffer)?;
-                if n == 0 { break; }
-                file.write_all(&buffer[..n])?;
-                pb.inc(n as u64);
+    // Parallel transfers log in again for each extra connection, which
+    // mustn't mean asking for a 2FA code or passphrase several times over.
+    let reconnect = || session::create_session(server, servers, settings);
+    let connect: Option<transfer::Connect> = match session::needs_prompts(server, servers) {
+        true => None,
+        false => Some(&reconnect),
+    };
+    while let Some(action) = ui::file_transfer_menu() {
+        match action {
+            ui::SftpAction::Upload => {
+                let local_path = ui::get_local_path("Local file or directory");
+                let Some(remote_dir) = ui::browse_remote(&sftp, ui::BrowseTarget::Upload)? else { continue };
+                let files = transfer::upload(&sftp, connect, Path::new(&local_path), &remote_dir, settings)?;
+                if settings.verify_checksums {
+                    verify_checksums(&sess, &files);
+                }
+            },
+            ui::SftpAction::Download => {
+                let Some(remote_path) = ui::browse_remote(&sftp, ui::BrowseTarget::Download)? else { continue };
+                let prompt = if sftp.stat(&remote_path)?.is_dir() { "Local directory to download into" } else { "Local destination path" };
+                let local_path = ui::get_local_path(prompt);
+                let files = transfer::download(&sftp, connect, &remote_path, Path::new(&local_path), settings)?;
+                if settings.verify_checksums {
+                    verify_checksums(&sess, &files);
+                }
             }
-            pb.finish_with_message("Download complete");
+            action => match manage_remote(&sftp, action) {
+                Ok(Some(done)) => println!("✅ {}", done),
+                Ok(None) => println!("🚫 Cancelled."),
+                Err(e) => println!("❌ {}", e),
+            },
         }
     }
-    
     Ok(())
 }
+
+/// Remote paths per `sha256sum` run, to stay well under command line limits.
+const CHECKSUM_BATCH: usize = 64;
+
+/// Compares SHA-256 checksums of transferred `(local, remote)` files on both
+/// ends, using `sha256sum`, or `shasum` where that's all there is, on the
+/// server. Servers with neither are skipped with a note.
+fn verify_checksums(sess: &Session, files: &[(PathBuf, PathBuf)]) {
+    if files.is_empty() {
+        return;
+    }
+    println!("🔍 Verifying {} file(s) by SHA-256...", files.len());
+    let mut remote_sums = std::collections::HashMap::new();
+    for batch in files.chunks(CHECKSUM_BATCH) {
+        let paths: Vec<String> = batch.iter().map(|(_, remote)| shell_quote(&remote.to_string_lossy())).collect();
+        let command = format!(
+            "if command -v sha256sum >/dev/null 2>&1; then sha256sum -- {0}; \
+             elif command -v shasum >/dev/null 2>&1; then shasum -a 256 -- {0}; else exit 127; fi",
+            paths.join(" "),
+        );
+        match capture_stdout(sess, &command) {
+            Ok((_, 127)) => {
+                println!("ℹ️  The server has neither sha256sum nor shasum; skipped verification.");
+                return;
+            }
+            Ok((output, _)) => remote_sums.extend(transfer::parse_sha256sum(&output)),
+            Err(e) => {
+                println!("⚠️  Could not checksum the files on the server ({}); skipped verification.", e);
+                return;
+            }
+        }
+    }
+
+    let (mut matched, mut mismatched, mut unchecked) = (0, Vec::new(), Vec::new());
+    for (local, remote) in files {
+        match (transfer::local_sha256(local), remote_sums.get(remote.to_string_lossy().as_ref())) {
+            (Ok(local_sum), Some(remote_sum)) if &local_sum == remote_sum => matched += 1,
+            (Ok(_), Some(_)) => mismatched.push(remote),
+            _ => unchecked.push(remote),
+        }
+    }
+    if mismatched.is_empty() && unchecked.is_empty() {
+        println!("✅ All {} file(s) match.", matched);
+        return;
+    }
+    println!("{} match, {} differ, {} could not be checked.", matched, mismatched.len(), unchecked.len());
+    for path in mismatched {
+        println!("   ❌ MISMATCH {}", path.display());
+    }
+    for path in unchecked {
+        println!("   ❔ unchecked {}", path.display());
+    }
+}
+
+/// Runs a command and returns only its stdout, so error messages can't be
+/// mistaken for output, along with its exit status.
+fn capture_stdout(sess: &Session, command: &str) -> Result<(String, i32), Box<dyn std::error::Error>> {
+    let mut channel = sess.channel_session()?;
+    channel.exec(command)?;
+    let mut output = String::new();
+    channel.read_to_string(&mut output)?;
+    std::io::copy(&mut channel.stderr(), &mut std::io::sink())?;
+    channel.wait_close()?;
+    Ok((output, channel.exit_status()?))
+}
+
+/// Carries out a file management action, returning what was done, or
+/// `None` if the user backed out at the confirmation.
+fn manage_remote(sftp: &ssh2::Sftp, action: ui::SftpAction) -> Result<Option<String>, Box<dyn std::error::Error>> {
+    // lstat, so a symlink is deleted or renamed itself rather than followed.
+    let existing = |path: &Path| sftp.lstat(path).map_err(|e| format!("{}: {}", path.display(), e));
+    match action {
+        ui::SftpAction::DeleteFile => {
+            let path = PathBuf::from(ui::get_remote_path("Remote file to delete"));
+            if existing(&path)?.is_dir() {
+                return Err(format!("{} is a directory; use Remove Empty Directory", path.display()).into());
+            }
+            if !ui::confirm_always(&format!("Delete {}? This can't be undone.", path.display())) {
+                return Ok(None);
+            }
+            sftp.unlink(&path).map_err(|e| format!("Could not delete {}: {}", path.display(), e))?;
+            Ok(Some(format!("Deleted {}", path.display())))
+        }
+        ui::SftpAction::RemoveDir => {
+            let path = PathBuf::from(ui::get_remote_path("Remote directory to remove"));
+            if !existing(&path)?.is_dir() {
+                return Err(format!("{} is not a directory; use Delete File", path.display()).into());
+            }
+            // Servers only say "failure" when asked to remove a full directory.
+            if !sftp.readdir(&path)?.is_empty() {
+                return Err(format!("{} is not empty; delete what's in it first", path.display()).into());
+            }
+            if !ui::confirm_always(&format!("Remove the empty directory {}?", path.display())) {
+                return Ok(None);
+            }
+            sftp.rmdir(&path).map_err(|e| format!("Could not remove {}: {}", path.display(), e))?;
+            Ok(Some(format!("Removed {}", path.display())))
+        }
+        ui::SftpAction::Rename => {
+            let from = PathBuf::from(ui::get_remote_path("Remote file or directory to rename"));
+            existing(&from)?;
+            let to = ui::get_remote_path("New name (or a path with / to move it)");
+            // A bare name stays in the same directory.
+            let to = match to.contains('/') {
+                true => PathBuf::from(to),
+                false => from.with_file_name(to),
+            };
+            if sftp.lstat(&to).is_ok() && !ui::confirm_always(&format!("{} already exists. Replace it?", to.display())) {
+                return Ok(None);
+            }
+            sftp.rename(&from, &to, None).map_err(|e| format!("Could not rename {} to {}: {}", from.display(), to.display(), e))?;
+            Ok(Some(format!("Renamed {} to {}", from.display(), to.display())))
+        }
+        ui::SftpAction::MakeDir => {
+            let path = PathBuf::from(ui::get_remote_path("Remote directory to create"));
+            sftp.mkdir(&path, 0o755).map_err(|e| format!("Could not create {}: {}", path.display(), e))?;
+            Ok(Some(format!("Created {}", path.display())))
+        }
+        ui::SftpAction::Chmod => {
+            let path = PathBuf::from(ui::get_remote_path("Remote file or directory"));
+            // stat, not lstat: chmod on a link changes what it points at.
+            let perm = sftp.stat(&path).map_err(|e| format!("{}: {}", path.display(), e))?.perm.unwrap_or(0);
+            println!("🔐 {} is {}", path.display(), transfer::format_mode(perm));
+            let Some(mode) = ui::mode_prompt(perm) else { return Ok(None) };
+            transfer::set_mode(sftp, &path, mode).map_err(|e| format!("Could not chmod: {}", e))?;
+            Ok(Some(format!("{} is now {}", path.display(), transfer::format_mode(mode))))
+        }
+        ui::SftpAction::Upload | ui::SftpAction::Download => Ok(None),
+    }
+}
@@ -0,0 +1,97 @@
+/// One saved PuTTY session from a `.reg` export, with the values the
+/// importer cares about.
+#[derive(Default)]
+pub struct Session {
+    pub name: String,
+    pub host: Option<String>,
+    pub port: Option<u32>,
+    pub user: Option<String>,
+    pub key_file: Option<String>,
+    pub protocol: Option<String>,
+}
+
+const SESSIONS_KEY: &str = "\\Software\\SimonTatham\\PuTTY\\Sessions\\";
+
+/// Reads a registry export as written by `regedit`, which uses UTF-16 with
+/// a byte order mark; older or hand-made exports are plain text.
+pub fn decode(bytes: &[u8]) -> String {
+    let utf16 = |pairs: std::slice::ChunksExact<u8>, from: fn([u8; 2]) -> u16| {
+        let units: Vec<u16> = pairs.map(|p| from([p[0], p[1]])).collect();
+        String::from_utf16_lossy(&units)
+    };
+    match bytes {
+        [0xFF, 0xFE, rest @ ..] => utf16(rest.chunks_exact(2), u16::from_le_bytes),
+        [0xFE, 0xFF, rest @ ..] => utf16(rest.chunks_exact(2), u16::from_be_bytes),
+        [0xEF, 0xBB, 0xBF, rest @ ..] => String::from_utf8_lossy(rest).into_owned(),
+        _ => String::from_utf8_lossy(bytes).into_owned(),
+    }
+}
+
+/// Collects the sessions under `...\SimonTatham\PuTTY\Sessions\<name>`.
+/// Other keys, and values the importer doesn't use, are ignored.
+pub fn parse(text: &str) -> Vec<Session> {
+    let mut sessions: Vec<Session> = Vec::new();
+    let mut in_session = false;
+    for line in text.lines() {
+        let line = line.trim();
+        if let Some(key) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            // `[-...]` deletes a key; subkeys of a session aren't sessions.
+            let name = key.find(SESSIONS_KEY)
+                .filter(|_| !key.starts_with('-'))
+                .map(|at| &key[at + SESSIONS_KEY.len()..])
+                .filter(|name| !name.is_empty() && !name.contains('\\'));
+            in_session = name.is_some();
+            if let Some(name) = name {
+                sessions.push(Session { name: unescape_name(name), ..Default::default() });
+            }
+            continue;
+        }
+        let Some(session) = sessions.last_mut().filter(|_| in_session) else { continue };
+        let Some((name, value)) = line.split_once('=') else { continue };
+        let name = name.trim().trim_matches('"');
+        let value = value.trim();
+        match name {
+            "HostName" => session.host = string_value(value),
+            "UserName" => session.user = string_value(value),
+            "PublicKeyFile" => session.key_file = string_value(value),
+            "Protocol" => session.protocol = string_value(value),
+            "PortNumber" => session.port = value.strip_prefix("dword:").and_then(|v| u32::from_str_radix(v, 16).ok()),
+            _ => {}
+        }
+    }
+    sessions
+}
+
+/// A `"..."` registry string with `\\` and `\"` escapes removed and
+/// surrounding whitespace trimmed. Empty strings count as unset.
+fn string_value(value: &str) -> Option<String> {
+    let inner = value.strip_prefix('"')?.strip_suffix('"')?;
+    let mut out = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => out.extend(chars.next()),
+            c => out.push(c),
+        }
+    }
+    Some(out.trim().to_string()).filter(|s| !s.is_empty())
+}
+
+/// PuTTY stores session names percent-encoded (`my%20server`).
+fn unescape_name(name: &str) -> String {
+    let bytes = name.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            if let Some(byte) = name.get(i + 1..i + 3).and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+                out.push(byte);
+                i += 3;
+                continue;
+            }
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
@@ -0,0 +1,520 @@
+use crate::cert::CertInfo;
+use crate::config::{self, AuthType, Server};
+use crate::keycheck;
+use crate::settings::Settings;
+use crate::transport;
+use crate::ui;
+use base64::{engine::general_purpose, Engine as _};
+use sha2::{Digest, Sha256};
+use ssh2::{CheckResult, ErrorCode, HashType, HostKeyType, KeyboardInteractivePrompt, KnownHostFileKind, Prompt, PublicKey, Session};
+use crossterm::event::{self, Event};
+use indicatif::ProgressBar;
+use std::collections::HashMap;
+use std::io::{IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// libssh2's error for a key file it can't read, which is what a missing or
+/// wrong passphrase looks like.
+const LIBSSH2_ERROR_FILE: i32 = -16;
+const LIBSSH2_ERROR_TIMEOUT: i32 = -9;
+/// libssh2's errors for a server turning down a password, key or
+/// keyboard-interactive answers.
+const LIBSSH2_ERROR_AUTHENTICATION_FAILED: i32 = -18;
+const LIBSSH2_ERROR_PUBLICKEY_UNVERIFIED: i32 = -19;
+const PASSPHRASE_ATTEMPTS: u32 = 3;
+/// Wait before the second attempt at reaching a server; doubled each time.
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+struct CachedSession {
+    session: Session,
+    last_used: Instant,
+}
+
+/// Keeps authenticated sessions alive between actions so that, say, a shell
+/// followed by an SFTP transfer on the same server costs one handshake and
+/// one login (and one 2FA prompt) instead of two. Each action opens its own
+/// channel on the shared session.
+#[derive(Default)]
+pub struct SessionCache {
+    entries: HashMap<String, CachedSession>,
+}
+
+impl SessionCache {
+    /// Returns a cached session for `server` if it is still fresh and alive,
+    /// otherwise connects and caches a new one. An idle window of zero
+    /// disables caching.
+    pub fn get_or_connect(&mut self, server: &Server, servers: &[Server], settings: &Settings) -> Result<Session, Box<dyn std::error::Error>> {
+        let idle = Duration::from_secs(settings.session_idle_secs);
+        self.entries.retain(|_, cached| cached.last_used.elapsed() < idle);
+
+        let key = cache_key(server);
+        if let Some(cached) = self.entries.get_mut(&key) {
+            if is_alive(&cached.session) {
+                cached.last_used = Instant::now();
+                return Ok(cached.session.clone());
+            }
+            self.entries.remove(&key);
+        }
+
+        let session = create_session(server, servers, settings)?;
+        if !idle.is_zero() {
+            self.entries.insert(key, CachedSession { session: session.clone(), last_used: Instant::now() });
+        }
+        Ok(session)
+    }
+
+    /// Drops the cached session for `server`, e.g. after it errored.
+    pub fn invalidate(&mut self, server: &Server) {
+        if let Some(cached) = self.entries.remove(&cache_key(server)) {
+            let _ = cached.session.disconnect(None, "", None);
+        }
+    }
+}
+
+/// Identifies a server by where and as whom we connect, so editing a
+/// server's address never reuses a session to the old one.
+fn cache_key(server: &Server) -> String {
+    format!("{}|{}@{}:{}", server.name, server.user, server.host, server.port)
+}
+
+/// Sends a keepalive to find out whether the connection is still usable.
+/// Keepalives are switched back off afterwards, libssh2's default for a
+/// new session.
+fn is_alive(session: &Session) -> bool {
+    session.set_blocking(true);
+    session.set_keepalive(true, 1);
+    let alive = session.keepalive_send().is_ok() && session.authenticated();
+    session.set_keepalive(false, 0);
+    alive
+}
+
+/// `servers` is the whole list, for looking up jump hosts by alias.
+pub fn create_session(server: &Server, servers: &[Server], settings: &Settings) -> Result<Session, Box<dyn std::error::Error>> {
+    check_credentials(server, settings)?;
+    let sess = reach_with_spinner(server, open_jumps(server, servers, settings)?, settings)?;
+    verify_host_key(&sess, server)?;
+    authenticate(&sess, server)?;
+    Ok(sess)
+}
+
+/// Whether logging in to `server`, or a jump host on the way, asks the user
+/// for something: a 2FA code or a key passphrase. Extra connections are
+/// only opened behind the user's back when it doesn't.
+pub fn needs_prompts(server: &Server, servers: &[Server]) -> bool {
+    let Ok(chain) = jump_chain(server, servers) else { return true };
+    chain.into_iter().chain([server]).any(|hop| match &hop.auth_type {
+        AuthType::KeyboardInteractive => true,
+        AuthType::Key(key) | AuthType::Certificate { key_path: key, .. } => keycheck::is_encrypted(&key_file(key)),
+        AuthType::Password(_) | AuthType::Agent => false,
+    })
+}
+
+/// The jump hosts to pass through on the way to `server`, nearest first.
+pub fn jump_chain<'a>(server: &Server, servers: &'a [Server]) -> Result<Vec<&'a Server>, String> {
+    let mut chain: Vec<&Server> = Vec::new();
+    let mut next = server.jump_host.as_deref();
+    while let Some(alias) = next {
+        let jump = servers.iter().find(|s| s.name == alias)
+            .ok_or_else(|| format!("Jump host '{}' is not a saved server", alias))?;
+        if jump.name == server.name || chain.iter().any(|s| s.name == jump.name) {
+            return Err(format!("Jump hosts for {} loop back to '{}'", server.name, alias));
+        }
+        chain.push(jump);
+        next = jump.jump_host.as_deref();
+    }
+    chain.reverse();
+    Ok(chain)
+}
+
+/// Opens the byte stream to `server`, logging in to each of its jump hosts
+/// in turn and tunnelling through the last one. Makes a single attempt, so
+/// Diagnose sees the first failure as it is.
+pub fn open_stream(server: &Server, servers: &[Server], settings: &Settings) -> Result<transport::Stream, Box<dyn std::error::Error>> {
+    hop_stream(server, open_jumps(server, servers, settings)?, settings)
+}
+
+/// Logs in to each of `server`'s jump hosts in turn, returning the session
+/// to the last one, if any.
+fn open_jumps(server: &Server, servers: &[Server], settings: &Settings) -> Result<Option<Session>, Box<dyn std::error::Error>> {
+    let mut jump = None;
+    for hop in jump_chain(server, servers)? {
+        let sess = (|| -> Result<Session, Box<dyn std::error::Error>> {
+            check_credentials(hop, settings)?;
+            let sess = reach_with_spinner(hop, jump.take(), settings)?;
+            verify_host_key(&sess, hop)?;
+            authenticate(&sess, hop)?;
+            Ok(sess)
+        })().map_err(|e| format!("Jump host {}: {}", hop.name, e))?;
+        jump = Some(sess);
+    }
+    Ok(jump)
+}
+
+/// `reach` on a worker thread, with a spinner until it is done. Esc or
+/// Ctrl-C stops waiting; the worker is left to give up on its own within
+/// the connect timeout. Without a terminal it simply runs `reach`.
+fn reach_with_spinner(server: &Server, jump: Option<Session>, settings: &Settings) -> Result<Session, Box<dyn std::error::Error>> {
+    if !std::io::stdin().is_terminal() {
+        return reach(server, jump, settings, &|note| println!("⚠️  {}", note));
+    }
+
+    let label = format!("Connecting to {}… (Esc to cancel)", server.name);
+    let spinner = ProgressBar::new_spinner().with_message(label.clone());
+    spinner.enable_steady_tick(Duration::from_millis(100));
+    let (tx, rx) = mpsc::channel();
+    let (worker_server, worker_settings, worker_spinner) = (server.clone(), settings.clone(), spinner.clone());
+    thread::spawn(move || {
+        let notify = |note: String| worker_spinner.set_message(format!("{} — {}", label, note));
+        let result = reach(&worker_server, jump, &worker_settings, &notify).map_err(|e| e.to_string());
+        let _ = tx.send(result);
+    });
+
+    // Raw mode so Esc arrives as a key press rather than waiting for Enter.
+    let raw_mode = ui::RawMode::enable()?;
+    let result = loop {
+        match rx.recv_timeout(Duration::from_millis(50)) {
+            Ok(result) => break result.map_err(Into::into),
+            Err(mpsc::RecvTimeoutError::Disconnected) => break Err("The connection attempt stopped unexpectedly".into()),
+            Err(mpsc::RecvTimeoutError::Timeout) => {}
+        }
+        if event::poll(Duration::ZERO)? {
+            if let Event::Key(key) = event::read()? {
+                if ui::is_cancel_key(&key) {
+                    break Err(format!("Cancelled connecting to {}", server.name).into());
+                }
+            }
+        }
+    };
+    drop(raw_mode);
+    spinner.finish_and_clear();
+    result
+}
+
+/// Connects to `server` (through `jump` if given) and completes the SSH
+/// handshake, retrying with exponential backoff up to the configured
+/// number of attempts. Failed attempts are passed to `notify`. Logging in
+/// comes after and is never retried: a rejected password or key would
+/// only be rejected again.
+fn reach(server: &Server, jump: Option<Session>, settings: &Settings, notify: &dyn Fn(String)) -> Result<Session, Box<dyn std::error::Error>> {
+    let attempts = settings.connect_attempts.max(1);
+    let mut delay = RETRY_DELAY;
+    let mut attempt = 1;
+    loop {
+        match hop_stream(server, jump.clone(), settings).and_then(|stream| handshake(stream, settings)) {
+            Ok(sess) => return Ok(sess),
+            Err(e) if attempt < attempts => {
+                notify(format!("attempt {}/{} failed: {}; retrying in {}s", attempt, attempts, e, delay.as_secs()));
+                std::thread::sleep(delay);
+                delay *= 2;
+                attempt += 1;
+            }
+            Err(e) if attempts > 1 => return Err(format!("{} (gave up after {} attempts)", e, attempts).into()),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+fn hop_stream(server: &Server, jump: Option<Session>, settings: &Settings) -> Result<transport::Stream, Box<dyn std::error::Error>> {
+    match jump {
+        Some(jump) => transport::via_jump(jump, &server.host, server.port),
+        None => transport::connect(server, settings),
+    }
+}
+
+/// Catches an expired certificate before we bother the network.
+pub fn check_credentials(server: &Server, settings: &Settings) -> Result<(), Box<dyn std::error::Error>> {
+    if let AuthType::Certificate { cert_path, .. } = &server.auth_type {
+        let cert = CertInfo::load(&key_file(cert_path))?;
+        if cert.is_expired() || cert.is_not_yet_valid() {
+            return Err(format!("Certificate is not valid now (valid {})", cert.validity_window(settings)).into());
+        }
+    }
+    Ok(())
+}
+
+/// Sets the connect timeout on the new session; it stays in force until
+/// `authenticate` succeeds.
+pub fn handshake(stream: transport::Stream, settings: &Settings) -> Result<Session, Box<dyn std::error::Error>> {
+    let mut sess = Session::new()?;
+    sess.set_tcp_stream(stream);
+    sess.set_timeout(settings.connect_timeout().as_millis() as u32);
+    sess.handshake().map_err(|e| explain_timeout(&sess, e.into()))?;
+    Ok(sess)
+}
+
+/// libssh2 reports a timed-out wait as a generic session error.
+fn explain_timeout(sess: &Session, e: Box<dyn std::error::Error>) -> Box<dyn std::error::Error> {
+    match e.downcast_ref::<ssh2::Error>() {
+        Some(err) if err.code() == ErrorCode::Session(LIBSSH2_ERROR_TIMEOUT) => {
+            format!("Connection timed out after {}s waiting for the server", sess.timeout() / 1000).into()
+        }
+        _ => e,
+    }
+}
+
+/// The server's host key as `ssh-keygen -l` shows it: `SHA256:` and the
+/// unpadded base64 of its SHA-256 hash.
+pub fn host_key_fingerprint(sess: &Session) -> Option<String> {
+    sess.host_key_hash(HashType::Sha256)
+        .map(|hash| format!("SHA256:{}", general_purpose::STANDARD_NO_PAD.encode(hash)))
+}
+
+/// Checks the server's host key against `~/.ssh/known_hosts`. An unknown
+/// host is only trusted if the user accepts its fingerprint, which is then
+/// saved; a changed key always fails, before any credentials are sent.
+pub fn verify_host_key(sess: &Session, server: &Server) -> Result<(), Box<dyn std::error::Error>> {
+    let (key, key_type) = sess.host_key().ok_or("Server did not send a host key")?;
+    let path = dirs::home_dir().ok_or("No home dir")?.join(".ssh").join("known_hosts");
+    let contents = match std::fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(format!("Could not read {}: {}", path.display(), e).into()),
+    };
+    // Line by line, so an entry libssh2 can't parse (a key type it doesn't
+    // know) only skips that entry rather than failing every connection.
+    // `@cert-authority` and `@revoked` lines aren't host keys libssh2
+    // understands, so they are left out rather than misread.
+    let mut known_hosts = sess.known_hosts()?;
+    for line in contents.lines().map(str::trim).filter(|l| !l.is_empty() && !l.starts_with('#') && !l.starts_with('@')) {
+        let _ = known_hosts.read_str(line, KnownHostFileKind::OpenSSH);
+    }
+
+    match known_hosts.check_port(&server.host, server.port, key) {
+        CheckResult::Match => Ok(()),
+        CheckResult::Mismatch => Err(format!(
+            "HOST KEY MISMATCH for {}: the server's key differs from the one in {}. \
+             Someone may be intercepting the connection, or the server was reinstalled. \
+             Not connecting. If the change is expected, remove the old key with `ssh-keygen -R {}`.",
+            server.host, path.display(), known_hosts_name(server),
+        ).into()),
+        CheckResult::NotFound => {
+            let fingerprint = host_key_fingerprint(sess).unwrap_or_else(|| "unavailable".to_string());
+            if !ui::accept_host_key_prompt(&known_hosts_name(server), &fingerprint) {
+                return Err("Host key not accepted".into());
+            }
+            append_known_host(sess, &path, &contents, &known_hosts_name(server), key, key_type)
+                .map_err(|e| format!("Could not save {}: {}", path.display(), e).into())
+        }
+        CheckResult::Failure => Err("Could not check the host key against known_hosts".into()),
+    }
+}
+
+/// Appends one `known_hosts` line for `name`, leaving the rest of the file,
+/// comments and all, untouched. `contents` is the file as it was read, to
+/// tell whether it needs a newline first.
+fn append_known_host(sess: &Session, path: &Path, contents: &str, name: &str, key: &[u8], key_type: HostKeyType) -> Result<(), Box<dyn std::error::Error>> {
+    // A store holding only the new host, so libssh2 formats just its line.
+    let mut single = sess.known_hosts()?;
+    single.add(name, key, "added by sshmanager", key_type.into())?;
+    let host = single.hosts()?.pop().ok_or("The new host key was not stored")?;
+    let line = single.write_string(&host, KnownHostFileKind::OpenSSH)?;
+
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let mut file = std::fs::OpenOptions::new().create(true).append(true).open(path)?;
+    let separator = if contents.is_empty() || contents.ends_with('\n') { "" } else { "\n" };
+    file.write_all(format!("{}{}\n", separator, line.trim_end()).as_bytes())?;
+    Ok(())
+}
+
+/// How OpenSSH names a host in `known_hosts`: bare on port 22, else `[host]:port`.
+fn known_hosts_name(server: &Server) -> String {
+    if server.port == 22 {
+        server.host.clone()
+    } else {
+        format!("[{}]:{}", server.host, server.port)
+    }
+}
+
+/// A stored key or certificate path as a file to open. Paths typed by hand
+/// or imported may start with `~`, which libssh2 would take literally.
+fn key_file(path: &str) -> PathBuf {
+    PathBuf::from(config::expand_tilde(path))
+}
+
+/// Logs in with a key file. An encrypted key (or one libssh2 can't open
+/// without a passphrase) gets its passphrase asked for at the terminal; it
+/// is kept in memory only for the attempt and never saved.
+fn pubkey_auth(sess: &Session, user: &str, cert: Option<&Path>, key: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    if !keycheck::is_encrypted(key) {
+        match sess.userauth_pubkey_file(user, cert, key, None) {
+            Err(e) if e.code() == ErrorCode::Session(LIBSSH2_ERROR_FILE) => {}
+            result => return Ok(result?),
+        }
+    }
+
+    let mut attempts = 0;
+    loop {
+        let passphrase = ui::key_passphrase_prompt(key).ok_or("No passphrase given for the encrypted key")?;
+        match sess.userauth_pubkey_file(user, cert, key, Some(&passphrase)) {
+            Err(e) if e.code() == ErrorCode::Session(LIBSSH2_ERROR_FILE) => {
+                attempts += 1;
+                if attempts == PASSPHRASE_ATTEMPTS {
+                    return Err(format!("Could not unlock {}: wrong passphrase", key.display()).into());
+                }
+                println!("❌ Wrong passphrase, try again.");
+            }
+            result => return Ok(result?),
+        }
+    }
+}
+
+/// A key loaded in the SSH agent.
+pub struct AgentIdentity {
+    pub comment: String,
+    pub fingerprint: String,
+}
+
+impl AgentIdentity {
+    fn of(key: &PublicKey) -> AgentIdentity {
+        AgentIdentity {
+            comment: key.comment().to_string(),
+            fingerprint: format!("SHA256:{}", general_purpose::STANDARD_NO_PAD.encode(Sha256::digest(key.blob()))),
+        }
+    }
+
+    /// Whether `wanted`, as stored in `Server::agent_identity`, names this key.
+    pub fn matches(&self, wanted: &str) -> bool {
+        let wanted = wanted.trim();
+        self.fingerprint == wanted || (!self.comment.is_empty() && self.comment == wanted)
+    }
+
+    /// The comment (usually `user@host` or a file name), else the fingerprint.
+    pub fn label(&self) -> &str {
+        if self.comment.is_empty() { &self.fingerprint } else { &self.comment }
+    }
+}
+
+/// The keys the local agent holds, for picking one in the server form. No
+/// server is involved.
+pub fn agent_identities() -> Result<Vec<AgentIdentity>, Box<dyn std::error::Error>> {
+    let sess = Session::new()?;
+    let mut agent = sess.agent()?;
+    agent.connect()?;
+    agent.list_identities()?;
+    let identities = agent.identities()?.iter().map(AgentIdentity::of).collect();
+    let _ = agent.disconnect();
+    Ok(identities)
+}
+
+/// Logs in through the SSH agent, offering the server's preferred identity
+/// first. When that one isn't loaded or is refused, the others are tried in
+/// the agent's order, and whichever works is reported.
+fn agent_auth(sess: &Session, server: &Server) -> Result<(), Box<dyn std::error::Error>> {
+    let mut agent = sess.agent()?;
+    agent.connect().map_err(|e| format!("Could not reach the SSH agent (is SSH_AUTH_SOCK set?): {}", e))?;
+    agent.list_identities()?;
+    let keys = agent.identities()?;
+    if keys.is_empty() {
+        return Err("The SSH agent has no keys loaded (add one with ssh-add)".into());
+    }
+
+    let mut order: Vec<(&PublicKey, AgentIdentity)> = keys.iter().map(|k| (k, AgentIdentity::of(k))).collect();
+    if let Some(wanted) = &server.agent_identity {
+        match order.iter().position(|(_, identity)| identity.matches(wanted)) {
+            Some(at) => {
+                let preferred = order.remove(at);
+                order.insert(0, preferred);
+            }
+            None => println!("⚠️  Agent identity '{}' is not loaded in the agent; trying all {} keys.", wanted, keys.len()),
+        }
+    }
+
+    for (i, (key, identity)) in order.iter().enumerate() {
+        if agent.userauth(&server.user, key).is_ok() {
+            let fell_back = i > 0 && server.agent_identity.is_some();
+            if fell_back || (server.agent_identity.is_none() && keys.len() > 1) {
+                println!("🔑 Logged in with agent identity {} ({})", identity.label(), identity.fingerprint);
+            }
+            return Ok(());
+        }
+    }
+    Err(format!("The server refused all {} keys in the SSH agent", keys.len()).into())
+}
+
+/// Hands keyboard-interactive challenges to the user at the terminal.
+struct TerminalPrompter;
+
+impl KeyboardInteractivePrompt for TerminalPrompter {
+    fn prompt<'a>(&mut self, _username: &str, instructions: &str, prompts: &[Prompt<'a>]) -> Vec<String> {
+        ui::keyboard_interactive_answers(instructions, prompts)
+    }
+}
+
+pub fn authenticate(sess: &Session, server: &Server) -> Result<(), Box<dyn std::error::Error>> {
+    login(sess, server)
+        .or_else(|e| agent_fallback(sess, server, e))
+        .map_err(|e| explain_timeout(sess, e))?;
+    // The timeout only guards logging in; a shell or transfer can wait on
+    // the server for as long as it likes.
+    sess.set_timeout(0);
+    Ok(())
+}
+
+fn login(sess: &Session, server: &Server) -> Result<(), Box<dyn std::error::Error>> {
+    match &server.auth_type {
+        AuthType::Password(p) => {
+            // Servers that ask for a one-time code alongside (or instead of)
+            // the password only accept it over keyboard-interactive.
+            if let Err(e) = sess.userauth_password(&server.user, p) {
+                let offered = sess.auth_methods(&server.user).unwrap_or_default();
+                if !offered.split(',').any(|method| method == "keyboard-interactive") {
+                    return Err(e.into());
+                }
+                println!("ℹ️  Password login failed; the server asks for keyboard-interactive login instead.");
+                sess.userauth_keyboard_interactive(&server.user, &mut TerminalPrompter)?;
+            }
+        }
+        AuthType::KeyboardInteractive => sess.userauth_keyboard_interactive(&server.user, &mut TerminalPrompter)?,
+        AuthType::Key(p) => pubkey_auth(sess, &server.user, None, &key_file(p))?,
+        AuthType::Agent => agent_auth(sess, server)?,
+        AuthType::Certificate { key_path, cert_path } => {
+            pubkey_auth(sess, &server.user, Some(&key_file(cert_path)), &key_file(key_path))?
+        }
+    }
+
+    if !sess.authenticated() {
+        return Err(ssh2::Error::new(ErrorCode::Session(LIBSSH2_ERROR_AUTHENTICATION_FAILED), "Authentication failed").into());
+    }
+    
+    Ok(())
+}
+
+/// After a stored password or key is refused, tries the SSH agent as a last
+/// resort if the server accepts public keys and an agent is running, so a
+/// stale password doesn't lock you out while the agent holds a valid key.
+/// Any other failure, such as a timeout or a dropped connection, is
+/// returned as it is.
+fn agent_fallback(sess: &Session, server: &Server, error: Box<dyn std::error::Error>) -> Result<(), Box<dyn std::error::Error>> {
+    let refused = error.downcast_ref::<ssh2::Error>().is_some_and(|e| {
+        [LIBSSH2_ERROR_AUTHENTICATION_FAILED, LIBSSH2_ERROR_PUBLICKEY_UNVERIFIED].map(ErrorCode::Session).contains(&e.code())
+    });
+    if !refused {
+        return Err(error);
+    }
+    let stored = match server.auth_type {
+        AuthType::Password(_) => "Password",
+        AuthType::Key(_) => "Key",
+        AuthType::Certificate { .. } => "Certificate",
+        AuthType::Agent | AuthType::KeyboardInteractive => return Err(error),
+    };
+    let offered = sess.auth_methods(&server.user).unwrap_or_default();
+    if !offered.split(',').any(|method| method == "publickey") || std::env::var_os("SSH_AUTH_SOCK").is_none() {
+        return Err(error);
+    }
+
+    println!("ℹ️  {} login failed ({}); trying the SSH agent.", stored, error);
+    let tried = format!("{}, agent", stored.to_lowercase());
+    match agent_auth(sess, server) {
+        Ok(()) if sess.authenticated() => {
+            println!("🔑 Logged in with the SSH agent (tried {}).", tried);
+            Ok(())
+        }
+        Ok(()) => Err(format!("{} (tried {})", error, tried).into()),
+        Err(agent_error) => Err(format!("{} (tried {}; agent: {})", error, tried, agent_error).into()),
+    }
+}
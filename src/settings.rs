@@ -0,0 +1,212 @@
+use crate::config::{get_config_dir, write_atomic, Server, AddressFamily, Kdf, DEFAULT_ARGON2_MEMORY_KIB, DEFAULT_ARGON2_PASSES};
+use crate::theme::ThemeConfig;
+use crate::transfer;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::time::Duration;
+
+/// libssh2 splits SFTP writes into ~30KB packets and pipelines them, and
+/// pre-fetches ahead of reads, so a bigger buffer keeps more requests in
+/// flight on high-latency links. The cost is memory, once per transfer.
+const DEFAULT_TRANSFER_BUFFER_KIB: usize = 256;
+const DEFAULT_SESSION_IDLE_SECS: u64 = 300;
+const DEFAULT_CONNECT_TIMEOUT_SECS: u64 = 10;
+const DEFAULT_CONNECT_ATTEMPTS: u32 = 3;
+const DEFAULT_HISTORY_LIMIT: usize = 500;
+/// Enough to hide most of the per-file round trips on a slow link without
+/// opening a crowd of connections.
+const DEFAULT_PARALLEL_TRANSFERS: usize = 4;
+/// Well inside the few minutes typical NAT and firewall idle timeouts allow.
+const DEFAULT_KEEPALIVE_SECS: u64 = 30;
+const DEFAULT_REMOTE_TERM: &str = "xterm-256color";
+
+/// How server lists are ordered. Only the display order changes; the saved
+/// list keeps the order servers were added in, unless rearranged with
+/// Reorder Servers, and `Manual` shows it as is.
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+pub enum SortMode {
+    Name,
+    #[default]
+    Group,
+    LastConnected,
+    Manual,
+}
+
+impl SortMode {
+    pub fn label(self) -> &'static str {
+        match self {
+            SortMode::Name => "name",
+            SortMode::Group => "group",
+            SortMode::LastConnected => "last connected",
+            SortMode::Manual => "your order",
+        }
+    }
+
+    pub fn next(self) -> SortMode {
+        match self {
+            SortMode::Name => SortMode::Group,
+            SortMode::Group => SortMode::LastConnected,
+            SortMode::LastConnected => SortMode::Manual,
+            SortMode::Manual => SortMode::Name,
+        }
+    }
+
+    /// Indices into `servers` in display order. Favorites come first, each
+    /// part ordered by the mode. Most recently connected comes first;
+    /// servers never connected to go last.
+    pub fn order(self, servers: &[Server]) -> Vec<usize> {
+        let mut order: Vec<usize> = (0..servers.len()).collect();
+        let name = |i: &usize| servers[*i].name.to_lowercase();
+        match self {
+            SortMode::Name => order.sort_by_key(name),
+            SortMode::Group => order.sort_by_key(|i| (servers[*i].group.to_lowercase(), name(i))),
+            SortMode::LastConnected => order.sort_by_key(|i| (std::cmp::Reverse(servers[*i].last_connected), name(i))),
+            SortMode::Manual => {}
+        }
+        order.sort_by_key(|i| !servers[*i].is_favorite);
+        order
+    }
+}
+
+/// Preferences that hold no secrets, kept in plaintext next to the
+/// encrypted server list.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct Settings {
+    pub transfer_buffer_kib: usize,
+    /// Octal mode (e.g. `"0640"`) set on every uploaded file. Unset leaves
+    /// it to the server's umask.
+    pub upload_mode: Option<String>,
+    /// Files transferred at once, each on its own connection; 1 copies them
+    /// one after another.
+    pub parallel_transfers: usize,
+    /// Cap on the combined SFTP transfer rate in KiB/s; 0 is unlimited.
+    pub rate_limit_kib: u64,
+    /// Compare SHA-256 checksums on both ends after each SFTP transfer.
+    pub verify_checksums: bool,
+    /// Save server lists as plain JSON, with no master password. For setups
+    /// that store no secrets, such as agent-only logins.
+    pub plaintext_servers: bool,
+    /// Minutes the main menu may sit unused before the master password is
+    /// needed again; 0 never locks.
+    pub idle_lock_mins: u64,
+    /// Username offered when adding a server; unset offers none.
+    pub default_user: Option<String>,
+    /// Port used when the add form's port is left blank.
+    pub default_port: u16,
+    /// Skip the confirmations listed in `ui::Confirmation`.
+    pub fast_mode: bool,
+    /// Show timestamps as "3 minutes ago" rather than a local date and time.
+    pub relative_times: bool,
+    pub clock_24h: bool,
+    pub address_family: AddressFamily,
+    /// How long an unused session is kept for reuse; 0 connects fresh every time.
+    pub session_idle_secs: u64,
+    /// Proxy for every TCP connection unless a server overrides it. Unset
+    /// falls back to `ALL_PROXY`/`HTTPS_PROXY`.
+    pub proxy: Option<String>,
+    /// Argon2id cost for deriving the encryption key from the master
+    /// password. Higher is slower to unlock and to brute-force.
+    pub kdf_memory_kib: u32,
+    pub kdf_passes: u32,
+    /// How long to wait for a server to answer while connecting and logging
+    /// in, before giving up with "connection timed out".
+    pub connect_timeout_secs: u64,
+    /// Tries at reaching a server before giving up, waiting 1s, 2s, 4s...
+    /// in between. Only the connection and handshake are retried, never a
+    /// failed login.
+    pub connect_attempts: u32,
+    pub sort_mode: SortMode,
+    /// Colour preset and per-role overrides for menus and popups.
+    pub theme: ThemeConfig,
+    /// How often an idle shell sends a keepalive; 0 never does.
+    pub keepalive_secs: u64,
+    /// Append everything interactive shells print to a plaintext log under
+    /// the config dir.
+    pub record_sessions: bool,
+    /// Connections kept in `history.json`, oldest dropped first; 0 keeps none.
+    pub history_limit: usize,
+    /// `$TERM` announced to the server for interactive shells. Unset uses
+    /// `xterm-256color`, which nearly every server has terminfo for.
+    pub remote_term: Option<String>,
+    /// Remapped letter keys for the pager and health view, by action name
+    /// (see `keymap::Binding::name`). Unmapped actions keep their defaults.
+    pub keys: BTreeMap<String, String>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            transfer_buffer_kib: DEFAULT_TRANSFER_BUFFER_KIB,
+            upload_mode: None,
+            parallel_transfers: DEFAULT_PARALLEL_TRANSFERS,
+            rate_limit_kib: 0,
+            verify_checksums: false,
+            plaintext_servers: false,
+            idle_lock_mins: 0,
+            default_user: None,
+            default_port: 22,
+            fast_mode: false,
+            relative_times: true,
+            clock_24h: true,
+            address_family: AddressFamily::Auto,
+            session_idle_secs: DEFAULT_SESSION_IDLE_SECS,
+            proxy: None,
+            kdf_memory_kib: DEFAULT_ARGON2_MEMORY_KIB,
+            kdf_passes: DEFAULT_ARGON2_PASSES,
+            connect_timeout_secs: DEFAULT_CONNECT_TIMEOUT_SECS,
+            connect_attempts: DEFAULT_CONNECT_ATTEMPTS,
+            sort_mode: SortMode::default(),
+            theme: ThemeConfig::default(),
+            keys: BTreeMap::new(),
+            remote_term: None,
+            keepalive_secs: DEFAULT_KEEPALIVE_SECS,
+            record_sessions: false,
+            history_limit: DEFAULT_HISTORY_LIMIT,
+        }
+    }
+}
+
+impl Settings {
+    pub fn load() -> Result<Self, Box<dyn std::error::Error>> {
+        let path = get_config_dir()?.join("settings.json");
+        if !path.exists() {
+            return Ok(Settings::default());
+        }
+        let content = fs::read_to_string(&path)?;
+        serde_json::from_str(&content)
+            .map_err(|e| format!("Failed to parse settings at {:?}: {}", path, e).into())
+    }
+
+    pub fn save(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let dir = get_config_dir()?;
+        fs::create_dir_all(&dir)?;
+        write_atomic(&dir.join("settings.json"), serde_json::to_string_pretty(self)?.as_bytes())?;
+        Ok(())
+    }
+
+    pub fn kdf(&self) -> Kdf {
+        Kdf::argon2id(self.kdf_memory_kib, self.kdf_passes)
+    }
+
+    pub fn connect_timeout(&self) -> Duration {
+        Duration::from_secs(self.connect_timeout_secs.max(1))
+    }
+
+    /// Terminal type for remote shells: the setting, else `xterm-256color`.
+    pub fn remote_term(&self) -> String {
+        self.remote_term.clone()
+            .filter(|term| !term.trim().is_empty())
+            .unwrap_or_else(|| DEFAULT_REMOTE_TERM.to_string())
+    }
+
+    /// Mode to chmod uploaded files to, if one is set and valid.
+    pub fn upload_mode(&self) -> Option<u32> {
+        self.upload_mode.as_deref().and_then(|mode| transfer::parse_mode(mode).ok())
+    }
+
+    pub fn transfer_buffer_size(&self) -> usize {
+        self.transfer_buffer_kib.max(1) * 1024
+    }
+}
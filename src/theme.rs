@@ -0,0 +1,181 @@
+use console::{Color, Style, StyledObject, style};
+use dialoguer::theme::ColorfulTheme;
+use serde::{Deserialize, Serialize};
+use std::sync::RwLock;
+
+/// Built-in colour schemes. `Dark` is the original look.
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+pub enum Preset {
+    #[default]
+    Dark,
+    Light,
+}
+
+impl Preset {
+    pub fn label(self) -> &'static str {
+        match self {
+            Preset::Dark => "dark",
+            Preset::Light => "light",
+        }
+    }
+
+    pub fn next(self) -> Preset {
+        match self {
+            Preset::Dark => Preset::Light,
+            Preset::Light => Preset::Dark,
+        }
+    }
+}
+
+/// Theme as stored in settings: a preset, with any role overridden by a
+/// colour name (`cyan`), a 256-colour index (`208`) or `#rrggbb`.
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct ThemeConfig {
+    pub preset: Preset,
+    /// The `?` in front of each question.
+    pub prompt: Option<String>,
+    /// The selected menu item, headings and popup borders.
+    pub accent: Option<String>,
+    /// Answers, the selection cursor and fuzzy-search matches.
+    pub highlight: Option<String>,
+    /// Validation errors and the error popup.
+    pub error: Option<String>,
+}
+
+/// Colours for each role, resolved from a `ThemeConfig`.
+#[derive(Clone, Copy)]
+struct Theme {
+    prompt: Color,
+    accent: Color,
+    highlight: Color,
+    error: Color,
+}
+
+static CURRENT: RwLock<Option<Theme>> = RwLock::new(None);
+
+impl Theme {
+    fn preset(preset: Preset) -> Theme {
+        match preset {
+            Preset::Dark => Theme { prompt: Color::Yellow, accent: Color::Cyan, highlight: Color::Green, error: Color::Red },
+            // Yellow and cyan wash out on a white background.
+            Preset::Light => Theme { prompt: Color::Blue, accent: Color::Blue, highlight: Color::Magenta, error: Color::Red },
+        }
+    }
+}
+
+/// Makes `config` the theme for everything drawn from now on. Roles with an
+/// unreadable colour keep the preset's and are returned as warnings.
+pub fn apply(config: &ThemeConfig) -> Vec<String> {
+    let mut theme = Theme::preset(config.preset);
+    let mut warnings = Vec::new();
+    for (role, value, slot) in [
+        ("prompt", &config.prompt, &mut theme.prompt),
+        ("accent", &config.accent, &mut theme.accent),
+        ("highlight", &config.highlight, &mut theme.highlight),
+        ("error", &config.error, &mut theme.error),
+    ] {
+        let Some(value) = value else { continue };
+        match parse_color(value) {
+            Ok(color) => *slot = color,
+            Err(e) => warnings.push(format!("Theme {} colour: {}", role, e)),
+        }
+    }
+    if let Ok(mut current) = CURRENT.write() {
+        *current = Some(theme);
+    }
+    warnings
+}
+
+fn current() -> Theme {
+    CURRENT.read().ok().and_then(|t| *t).unwrap_or_else(|| Theme::preset(Preset::Dark))
+}
+
+/// The dialoguer theme for prompts and menus.
+pub fn dialog() -> ColorfulTheme {
+    let theme = current();
+    let accent = Style::new().for_stderr().fg(theme.accent);
+    let highlight = Style::new().for_stderr().fg(theme.highlight);
+    let error = Style::new().for_stderr().fg(theme.error);
+    ColorfulTheme {
+        prompt_prefix: style("?".to_string()).for_stderr().fg(theme.prompt),
+        success_prefix: style("✔".to_string()).for_stderr().fg(theme.highlight),
+        error_prefix: style("✘".to_string()).for_stderr().fg(theme.error),
+        error_style: error,
+        values_style: highlight.clone(),
+        active_item_style: accent,
+        active_item_prefix: style("❯".to_string()).for_stderr().fg(theme.highlight),
+        checked_item_prefix: style("✔".to_string()).for_stderr().fg(theme.highlight),
+        picked_item_prefix: style("❯".to_string()).for_stderr().fg(theme.highlight),
+        fuzzy_match_highlight_style: highlight.bold(),
+        ..ColorfulTheme::default()
+    }
+}
+
+/// Border style for informational popups.
+pub fn accent() -> Style {
+    Style::new().fg(current().accent).bold()
+}
+
+/// Border style for the error popup.
+pub fn error() -> Style {
+    Style::new().fg(current().error).bold()
+}
+
+/// `text` in the accent colour, for headings outside dialoguer.
+pub fn accented<D>(text: D) -> StyledObject<D> {
+    style(text).fg(current().accent)
+}
+
+/// `text` in the highlight colour, for values outside dialoguer.
+pub fn highlighted<D>(text: D) -> StyledObject<D> {
+    style(text).fg(current().highlight)
+}
+
+fn parse_color(value: &str) -> Result<Color, String> {
+    let value = value.trim();
+    if let Some(hex) = value.strip_prefix('#') {
+        let channel = |i: usize| hex.get(i..i + 2).and_then(|c| u8::from_str_radix(c, 16).ok());
+        return match (hex.len(), channel(0), channel(2), channel(4)) {
+            (6, Some(r), Some(g), Some(b)) => Ok(Color::Color256(nearest_256(r, g, b))),
+            _ => Err(format!("'{}' is not a #rrggbb colour", value)),
+        };
+    }
+    if let Ok(index) = value.parse::<u8>() {
+        return Ok(Color::Color256(index));
+    }
+    match value.to_ascii_lowercase().as_str() {
+        "black" => Ok(Color::Black),
+        "red" => Ok(Color::Red),
+        "green" => Ok(Color::Green),
+        "yellow" => Ok(Color::Yellow),
+        "blue" => Ok(Color::Blue),
+        "magenta" => Ok(Color::Magenta),
+        "cyan" => Ok(Color::Cyan),
+        "white" => Ok(Color::White),
+        _ => Err(format!("unknown colour '{}' (use a name, 0-255 or #rrggbb)", value)),
+    }
+}
+
+/// Closest entry in the xterm 256-colour palette: the 6×6×6 cube or the
+/// grey ramp, whichever is nearer. Truecolor isn't assumed to be available.
+fn nearest_256(r: u8, g: u8, b: u8) -> u8 {
+    const LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+    let level = |c: u8| LEVELS.iter().enumerate().min_by_key(|(_, l)| (**l as i32 - c as i32).abs()).map_or(0, |(i, _)| i);
+    let (ri, gi, bi) = (level(r), level(g), level(b));
+    let cube = (LEVELS[ri], LEVELS[gi], LEVELS[bi]);
+
+    let average = (r as u32 + g as u32 + b as u32) / 3;
+    let grey_index = (average.saturating_sub(8) / 10).min(23) as u8;
+    let grey = 8 + 10 * grey_index;
+
+    let distance = |(cr, cg, cb): (u8, u8, u8)| {
+        let d = |a: u8, b: u8| (a as i32 - b as i32).pow(2);
+        d(r, cr) + d(g, cg) + d(b, cb)
+    };
+    if distance((grey, grey, grey)) < distance(cube) {
+        232 + grey_index
+    } else {
+        16 + 36 * ri as u8 + 6 * gi as u8 + bi as u8
+    }
+}
@@ -0,0 +1,86 @@
+use crate::settings::Settings;
+use chrono::{DateTime, Local, Locale};
+use std::time::{Duration, SystemTime};
+
+/// Differences smaller than this are shown as "just now", in either
+/// direction, so small clock skew between machines doesn't read oddly.
+const SKEW_TOLERANCE: Duration = Duration::from_secs(5);
+
+/// Formats a timestamp the way the user asked for in settings.
+pub fn format_timestamp(time: SystemTime, settings: &Settings) -> String {
+    if settings.relative_times {
+        format_relative(time)
+    } else {
+        format_absolute(time, settings)
+    }
+}
+
+/// Local date and time, using the locale from `LC_ALL`/`LC_TIME`/`LANG`.
+pub fn format_absolute(time: SystemTime, settings: &Settings) -> String {
+    let local: DateTime<Local> = time.into();
+    let pattern = if settings.clock_24h { "%x %H:%M:%S" } else { "%x %I:%M:%S %p" };
+    local.format_localized(pattern, user_locale()).to_string()
+}
+
+/// Humanized distance from now, e.g. "3 minutes ago" or "in 2 days".
+/// Timestamps from the future (another machine's clock) never panic.
+pub fn format_relative(time: SystemTime) -> String {
+    let now = SystemTime::now();
+    let (elapsed, future) = match now.duration_since(time) {
+        Ok(elapsed) => (elapsed, false),
+        Err(e) => (e.duration(), true),
+    };
+    if elapsed < SKEW_TOLERANCE {
+        return "just now".to_string();
+    }
+
+    let secs = elapsed.as_secs();
+    let (amount, unit) = match secs {
+        s if s < 60 => (s, "second"),
+        s if s < 3600 => (s / 60, "minute"),
+        s if s < 86_400 => (s / 3600, "hour"),
+        s if s < 30 * 86_400 => (s / 86_400, "day"),
+        s if s < 365 * 86_400 => (s / (30 * 86_400), "month"),
+        s => (s / (365 * 86_400), "year"),
+    };
+    let plural = if amount == 1 { "" } else { "s" };
+    if future {
+        format!("in {} {}{}", amount, unit, plural)
+    } else {
+        format!("{} {}{} ago", amount, unit, plural)
+    }
+}
+
+/// Serde helpers storing an optional timestamp as an RFC 3339 string,
+/// e.g. `"2024-05-01T09:30:00Z"`, so the config stays readable.
+pub mod rfc3339 {
+    use chrono::{DateTime, SecondsFormat, Utc};
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::time::SystemTime;
+
+    pub fn serialize<S: Serializer>(time: &Option<SystemTime>, serializer: S) -> Result<S::Ok, S::Error> {
+        match time {
+            Some(time) => serializer.serialize_str(&DateTime::<Utc>::from(*time).to_rfc3339_opts(SecondsFormat::Secs, true)),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<SystemTime>, D::Error> {
+        Option::<String>::deserialize(deserializer)?
+            .map(|text| DateTime::parse_from_rfc3339(&text).map(SystemTime::from).map_err(serde::de::Error::custom))
+            .transpose()
+    }
+}
+
+fn user_locale() -> Locale {
+    ["LC_ALL", "LC_TIME", "LANG"]
+        .iter()
+        .filter_map(|var| std::env::var(var).ok())
+        .find(|value| !value.is_empty())
+        .and_then(|value| {
+            // "de_DE.UTF-8@euro" -> "de_DE"
+            let name = value.split(['.', '@']).next().unwrap_or_default().to_string();
+            Locale::try_from(name.as_str()).ok()
+        })
+        .unwrap_or(Locale::POSIX)
+}
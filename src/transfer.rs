@@ -0,0 +1,555 @@
+use crate::settings::Settings;
+use crate::ui;
+use console::Term;
+use crossterm::event::{self, Event};
+use indicatif::{HumanBytes, MultiProgress, ProgressBar, ProgressDrawTarget, ProgressState, ProgressStyle, TermLike};
+use ssh2::{FileStat, OpenFlags, OpenType, Session, Sftp};
+use std::fs::{self, File, OpenOptions};
+use std::io::{IsTerminal, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Opens another session to the same server, for a parallel worker. libssh2
+/// serialises everything on one session, so each worker needs its own.
+pub type Connect<'a> = &'a dyn Fn() -> Result<Session, Box<dyn std::error::Error>>;
+
+/// One file to copy, planned before anything moves so the progress bar can
+/// show the total for the whole tree.
+struct FileJob {
+    from: PathBuf,
+    to: PathBuf,
+    size: u64,
+    /// When the source was last modified, in seconds since the epoch.
+    modified: Option<u64>,
+    /// Bytes already at the destination from an earlier, interrupted run.
+    done: u64,
+}
+
+/// Directories to create, parents first, and files to copy into them.
+#[derive(Default)]
+struct Plan {
+    dirs: Vec<PathBuf>,
+    files: Vec<FileJob>,
+}
+
+impl Plan {
+    fn total_bytes(&self) -> u64 {
+        self.files.iter().map(|f| f.size).sum()
+    }
+
+    /// Looks at what's already at each destination, given as its size and
+    /// modification time. A file no larger than the source and written since
+    /// the source last changed is taken as an interrupted (or, at full size,
+    /// finished) copy, and the user is asked whether to resume. Anything
+    /// else, such as an older or unrelated file, is copied from scratch
+    /// rather than appended to.
+    fn resume(&mut self, existing: impl Fn(&Path) -> Option<(u64, Option<u64>)>) {
+        for job in &mut self.files {
+            let source_modified = job.modified;
+            job.done = existing(&job.to)
+                .filter(|&(size, modified)| size <= job.size && modified.zip(source_modified).is_some_and(|(dest, source)| dest >= source))
+                .map_or(0, |(size, _)| size);
+        }
+        let partial: Vec<&FileJob> = self.files.iter().filter(|job| job.done > 0).collect();
+        if partial.is_empty() {
+            return;
+        }
+        let done_bytes: u64 = partial.iter().map(|job| job.done).sum();
+        if !ui::resume_transfer_prompt(partial.len(), done_bytes) {
+            for job in &mut self.files {
+                job.done = 0;
+            }
+        }
+    }
+
+    fn done_bytes(&self) -> u64 {
+        self.files.iter().map(|f| f.done).sum()
+    }
+}
+
+/// Uploads a local file or directory tree into `remote_dir`, offering to
+/// resume partial uploads. With `connect`, several files
+/// go at once on extra connections. A file that fails is reported at the
+/// end without stopping the others. `Esc` or `Ctrl-C` cancels, leaving a
+/// file cut off part-way to be resumed. Returns `(local, remote)` for
+/// every file that arrived.
+pub fn upload(sftp: &Sftp, connect: Option<Connect>, local: &Path, remote_dir: &Path, settings: &Settings) -> Result<Vec<(PathBuf, PathBuf)>, Box<dyn std::error::Error>> {
+    let name = local.file_name().ok_or("Local path has no file name")?;
+    let mut plan = Plan::default();
+    plan_local(local, &remote_dir.join(name), &mut plan)?;
+    plan.resume(|path| sftp.stat(path).ok().and_then(|stat| Some((stat.size?, stat.mtime))));
+
+    for dir in &plan.dirs {
+        if sftp.stat(dir).is_err() {
+            sftp.mkdir(dir, 0o755).map_err(|e| format!("Could not create {}: {}", dir.display(), e))?;
+        }
+    }
+
+    let outcome = run_jobs(&plan, sftp, connect, settings, |sftp, job, buffer, meter| {
+        let mut from = File::open(&job.from)?;
+        let mut to = if job.done > 0 {
+            from.seek(SeekFrom::Start(job.done))?;
+            let mut to = sftp.open_mode(&job.to, OpenFlags::WRITE, 0o644, OpenType::File)?;
+            to.seek(SeekFrom::Start(job.done))?;
+            to
+        } else {
+            sftp.create(&job.to).map_err(|e| format!("Could not create {}: {}", job.to.display(), e))?
+        };
+        Ok(copy(&mut from, &mut to, buffer, meter)?)
+    });
+    let uploaded: Vec<&FileJob> = plan.files.iter().filter(|job| outcome.arrived(job)).collect();
+    println!("⬆️  Uploaded {} file(s) to {}", uploaded.len(), remote_dir.join(name).display());
+
+    if let Some(mode) = settings.upload_mode() {
+        for job in &uploaded {
+            set_mode(sftp, &job.to, mode).map_err(|e| format!("Uploaded, but {}", e))?;
+        }
+        println!("🔐 Set {} on the uploaded file(s)", format_mode(mode));
+    }
+    outcome.report();
+    Ok(uploaded.into_iter().map(|job| (job.from.clone(), job.to.clone())).collect())
+}
+
+/// Parses an octal mode such as `644` or `0755`. Modes that would lock the
+/// owner out of reading the file are refused as typos (`044` for `0644`).
+pub fn parse_mode(input: &str) -> Result<u32, String> {
+    let input = input.trim();
+    if !(3..=4).contains(&input.len()) || !input.chars().all(|c| c.is_digit(8)) {
+        return Err(format!("'{}' is not an octal mode like 644 or 0755", input));
+    }
+    let mode = u32::from_str_radix(input, 8).map_err(|e| e.to_string())?;
+    if mode & 0o400 == 0 {
+        return Err(format!("{:04o} would leave the owner unable to read the file", mode));
+    }
+    Ok(mode)
+}
+
+/// `0644 (rw-r--r--)`. File type bits are ignored.
+pub fn format_mode(perm: u32) -> String {
+    let mut symbolic = String::new();
+    for shift in [6, 3, 0] {
+        let bits = perm >> shift;
+        symbolic.push(if bits & 4 != 0 { 'r' } else { '-' });
+        symbolic.push(if bits & 2 != 0 { 'w' } else { '-' });
+        symbolic.push(if bits & 1 != 0 { 'x' } else { '-' });
+    }
+    format!("{:04o} ({})", perm & 0o7777, symbolic)
+}
+
+/// chmod over SFTP: only the permissions are sent, nothing else changes.
+pub fn set_mode(sftp: &Sftp, path: &Path, mode: u32) -> Result<(), String> {
+    let stat = FileStat { size: None, uid: None, gid: None, perm: Some(mode), atime: None, mtime: None };
+    sftp.setstat(path, stat).map_err(|e| format!("could not set {} on {}: {}", format_mode(mode), path.display(), e))
+}
+
+/// Downloads a remote file to `local`, or a remote directory tree into the
+/// directory `local`, offering to resume partial downloads. Parallelism,
+/// failures and the result are as for `upload`.
+pub fn download(sftp: &Sftp, connect: Option<Connect>, remote: &Path, local: &Path, settings: &Settings) -> Result<Vec<(PathBuf, PathBuf)>, Box<dyn std::error::Error>> {
+    let stat = sftp.stat(remote)?;
+    let target = if stat.is_dir() {
+        local.join(remote.file_name().ok_or("Remote path has no name")?)
+    } else {
+        local.to_path_buf()
+    };
+    let mut plan = Plan::default();
+    plan_remote(sftp, remote, &target, &mut plan)?;
+    plan.resume(|path| fs::metadata(path).ok().map(|meta| (meta.len(), modified_secs(&meta))));
+
+    for dir in &plan.dirs {
+        fs::create_dir_all(dir)?;
+    }
+
+    let outcome = run_jobs(&plan, sftp, connect, settings, |sftp, job, buffer, meter| {
+        let mut from = sftp.open(&job.from).map_err(|e| format!("Could not open {}: {}", job.from.display(), e))?;
+        let mut to = if job.done > 0 {
+            from.seek(SeekFrom::Start(job.done))?;
+            OpenOptions::new().append(true).open(&job.to)?
+        } else {
+            File::create(&job.to)?
+        };
+        Ok(copy(&mut from, &mut to, buffer, meter)?)
+    });
+    let downloaded = plan.files.iter().filter(|job| outcome.arrived(job)).count();
+    println!("⬇️  Downloaded {} file(s) to {}", downloaded, target.display());
+    outcome.report();
+    Ok(plan.files.iter()
+        .filter(|job| outcome.arrived(job))
+        .map(|job| (job.to.clone(), job.from.clone()))
+        .collect())
+}
+
+/// SHA-256 of a local file, as lowercase hex.
+pub fn local_sha256(path: &Path) -> std::io::Result<String> {
+    use sha2::{Digest, Sha256};
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; 64 * 1024];
+    loop {
+        let n = file.read(&mut buffer)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buffer[..n]);
+    }
+    Ok(hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+/// Reads `sha256sum` output into (path, hash) pairs. Names with a newline
+/// or backslash are escaped by the tool and flagged with a leading `\`.
+pub fn parse_sha256sum(output: &str) -> Vec<(String, String)> {
+    output.lines().filter_map(|line| {
+        let (escaped, line) = match line.strip_prefix('\\') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        let (hash, name) = line.split_at_checked(64)?;
+        let name = name.strip_prefix("  ").or_else(|| name.strip_prefix(" *"))?;
+        if !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+        let name = if escaped { unescape(name) } else { name.to_string() };
+        Some((name, hash.to_ascii_lowercase()))
+    }).collect()
+}
+
+/// Undoes `sha256sum`'s `\n` and `\\` escapes.
+fn unescape(name: &str) -> String {
+    let mut out = String::with_capacity(name.len());
+    let mut chars = name.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some('n') => out.push('\n'),
+                Some(other) => out.push(other),
+                None => out.push('\\'),
+            },
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// How a run of copies ended.
+struct Outcome {
+    /// Files that failed, with why.
+    failures: Vec<(PathBuf, String)>,
+    /// Files left uncopied by cancelling, including any cut off part-way.
+    cancelled: Vec<PathBuf>,
+}
+
+impl Outcome {
+    fn arrived(&self, job: &FileJob) -> bool {
+        !self.failures.iter().any(|(from, _)| *from == job.from) && !self.cancelled.contains(&job.from)
+    }
+
+    fn report(&self) {
+        report_failures(&self.failures);
+        if !self.cancelled.is_empty() {
+            println!("🚫 Cancelled: {} file(s) not copied. Anything copied part-way is kept and offered for resuming next time.", self.cancelled.len());
+        }
+    }
+}
+
+/// Copies every file in `plan` that still needs copying, with `transfer`
+/// doing one file. Workers take the next file as they finish one: the
+/// caller's `sftp` plus up to `parallel_transfers - 1` extra connections
+/// from `connect`. On a terminal, `Esc` or `Ctrl-C` stops every worker
+/// mid-file.
+fn run_jobs<F>(plan: &Plan, sftp: &Sftp, connect: Option<Connect>, settings: &Settings, transfer: F) -> Outcome
+where
+    F: Fn(&Sftp, &FileJob, &mut [u8], &Meter) -> Result<(), Box<dyn std::error::Error>> + Sync,
+{
+    let jobs: Vec<&FileJob> = plan.files.iter().filter(|job| job.done < job.size || job.size == 0).collect();
+    let wanted = settings.parallel_transfers.max(1).min(jobs.len().max(1)) - 1;
+    let extra = connect.map(|connect| extra_channels(connect, wanted)).unwrap_or_default();
+
+    let throttle = Throttle::new(settings);
+    // Raw mode so Esc arrives as a key press rather than waiting for Enter.
+    let raw_mode = (std::io::stdin().is_terminal() && std::io::stderr().is_terminal())
+        .then(ui::RawMode::enable)
+        .and_then(Result::ok);
+    let multi = match raw_mode {
+        Some(_) => MultiProgress::with_draw_target(ProgressDrawTarget::term_like_with_hz(Box::new(RawTerm(Term::stderr())), 20)),
+        None => MultiProgress::new(),
+    };
+    let overall = multi.add(progress_bar(plan.total_bytes(), plan.done_bytes()));
+    if raw_mode.is_some() {
+        overall.set_message("Esc to cancel");
+    }
+    let next = AtomicUsize::new(0);
+    let cancel = AtomicBool::new(false);
+    let finished = AtomicBool::new(false);
+    let failures = Mutex::new(Vec::new());
+    let cancelled = Mutex::new(Vec::new());
+    let work = |sftp: &Sftp| {
+        let mut buffer = vec![0u8; settings.transfer_buffer_size()];
+        while !cancel.load(Ordering::Relaxed) {
+            let Some(job) = jobs.get(next.fetch_add(1, Ordering::Relaxed)) else { break };
+            // A bar per file only helps when there is more than one.
+            let file_bar = (jobs.len() > 1).then(|| multi.insert_before(&overall, file_bar(job)));
+            let meter = Meter {
+                bars: [Some(&overall), file_bar.as_ref()].into_iter().flatten().collect(),
+                throttle: throttle.as_ref(),
+                cancel: &cancel,
+            };
+            if let Err(e) = transfer(sftp, job, &mut buffer, &meter) {
+                if cancel.load(Ordering::Relaxed) {
+                    if let Ok(mut cancelled) = cancelled.lock() {
+                        cancelled.push(job.from.clone());
+                    }
+                } else if let Ok(mut failures) = failures.lock() {
+                    failures.push((job.from.clone(), e.to_string()));
+                }
+            }
+            if let Some(bar) = file_bar {
+                bar.finish_and_clear();
+                multi.remove(&bar);
+            }
+        }
+    };
+    std::thread::scope(|scope| {
+        if raw_mode.is_some() {
+            scope.spawn(|| watch_for_cancel(&cancel, &finished, &overall));
+        }
+        std::thread::scope(|workers| {
+            for sftp in &extra {
+                workers.spawn(|| work(sftp));
+            }
+            work(sftp);
+        });
+        finished.store(true, Ordering::Relaxed);
+    });
+
+    let failures = failures.into_inner().unwrap_or_default();
+    let mut cancelled = cancelled.into_inner().unwrap_or_default();
+    if cancel.load(Ordering::Relaxed) {
+        let started = next.load(Ordering::Relaxed).min(jobs.len());
+        cancelled.extend(jobs[started..].iter().map(|job| job.from.clone()));
+    }
+    if failures.is_empty() && cancelled.is_empty() {
+        overall.finish();
+    } else {
+        overall.abandon();
+    }
+    drop(raw_mode);
+    Outcome { failures, cancelled }
+}
+
+/// Sets `cancel` when Esc or Ctrl-C is pressed, until `finished` is set.
+fn watch_for_cancel(cancel: &AtomicBool, finished: &AtomicBool, overall: &ProgressBar) {
+    while !finished.load(Ordering::Relaxed) {
+        if !event::poll(Duration::from_millis(50)).unwrap_or(false) {
+            continue;
+        }
+        if let Ok(Event::Key(key)) = event::read() {
+            if ui::is_cancel_key(&key) {
+                cancel.store(true, Ordering::Relaxed);
+                overall.set_message("cancelling…");
+                return;
+            }
+        }
+    }
+}
+
+/// The terminal as seen by progress bars drawn while raw mode is on, which
+/// stops a bare `\n` from returning to the start of the line.
+#[derive(Debug)]
+struct RawTerm(Term);
+
+impl TermLike for RawTerm {
+    fn width(&self) -> u16 {
+        self.0.size().1
+    }
+
+    fn height(&self) -> u16 {
+        self.0.size().0
+    }
+
+    fn move_cursor_up(&self, n: usize) -> std::io::Result<()> {
+        self.0.move_cursor_up(n)
+    }
+
+    fn move_cursor_down(&self, n: usize) -> std::io::Result<()> {
+        self.0.move_cursor_down(n)
+    }
+
+    fn move_cursor_right(&self, n: usize) -> std::io::Result<()> {
+        self.0.move_cursor_right(n)
+    }
+
+    fn move_cursor_left(&self, n: usize) -> std::io::Result<()> {
+        self.0.move_cursor_left(n)
+    }
+
+    fn write_line(&self, s: &str) -> std::io::Result<()> {
+        self.0.write_str(&format!("{}\r\n", s))
+    }
+
+    fn write_str(&self, s: &str) -> std::io::Result<()> {
+        self.0.write_str(s)
+    }
+
+    fn clear_line(&self) -> std::io::Result<()> {
+        self.0.clear_line()
+    }
+
+    fn flush(&self) -> std::io::Result<()> {
+        self.0.flush()
+    }
+}
+
+/// Up to `wanted` more SFTP channels, each on a session of its own. Fewer
+/// is fine: the transfer goes ahead on whatever opened.
+fn extra_channels(connect: Connect, wanted: usize) -> Vec<Sftp> {
+    let mut channels = Vec::new();
+    while channels.len() < wanted {
+        match connect().and_then(|sess| Ok(sess.sftp()?)) {
+            Ok(sftp) => channels.push(sftp),
+            Err(e) => {
+                println!("⚠️  Could not open another connection ({}); transferring on {}.", e, channels.len() + 1);
+                break;
+            }
+        }
+    }
+    channels
+}
+
+fn report_failures(failures: &[(PathBuf, String)]) {
+    if failures.is_empty() {
+        return;
+    }
+    println!("⚠️  {} file(s) failed:", failures.len());
+    for (path, error) in failures {
+        println!("   ❌ {}: {}", path.display(), error);
+    }
+}
+
+/// A local file's modification time in whole seconds, as SFTP reports them.
+fn modified_secs(meta: &fs::Metadata) -> Option<u64> {
+    meta.modified().ok()?.duration_since(std::time::UNIX_EPOCH).ok().map(|since| since.as_secs())
+}
+
+/// Symlinks are skipped with a warning rather than followed, so a link
+/// back up the tree can't make the walk loop forever.
+fn plan_local(from: &Path, to: &Path, plan: &mut Plan) -> Result<(), Box<dyn std::error::Error>> {
+    let meta = fs::symlink_metadata(from)?;
+    if meta.file_type().is_symlink() {
+        println!("⚠️  Skipping symlink {}", from.display());
+    } else if meta.is_dir() {
+        plan.dirs.push(to.to_path_buf());
+        let mut entries = fs::read_dir(from)?.collect::<Result<Vec<_>, _>>()?;
+        entries.sort_by_key(|e| e.file_name());
+        for entry in entries {
+            plan_local(&entry.path(), &to.join(entry.file_name()), plan)?;
+        }
+    } else {
+        plan.files.push(FileJob { from: from.to_path_buf(), to: to.to_path_buf(), size: meta.len(), modified: modified_secs(&meta), done: 0 });
+    }
+    Ok(())
+}
+
+fn plan_remote(sftp: &Sftp, from: &Path, to: &Path, plan: &mut Plan) -> Result<(), Box<dyn std::error::Error>> {
+    let stat = sftp.lstat(from)?;
+    if stat.file_type().is_symlink() {
+        println!("⚠️  Skipping symlink {}", from.display());
+    } else if stat.is_dir() {
+        plan.dirs.push(to.to_path_buf());
+        let mut entries = sftp.readdir(from)?;
+        entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+        for (path, _) in entries {
+            let Some(name) = path.file_name() else { continue };
+            plan_remote(sftp, &path, &to.join(name), plan)?;
+        }
+    } else {
+        plan.files.push(FileJob { from: from.to_path_buf(), to: to.to_path_buf(), size: stat.size.unwrap_or(0), modified: stat.mtime, done: 0 });
+    }
+    Ok(())
+}
+
+/// Where a copy reports its progress, what paces it, and what stops it.
+struct Meter<'a> {
+    bars: Vec<&'a ProgressBar>,
+    throttle: Option<&'a Throttle>,
+    cancel: &'a AtomicBool,
+}
+
+/// Keeps the combined rate of all workers under the configured cap by
+/// sleeping whenever they get ahead of it, so the average stays at or
+/// below the limit.
+struct Throttle {
+    bytes_per_sec: u64,
+    started: Instant,
+    sent: AtomicU64,
+}
+
+impl Throttle {
+    fn new(settings: &Settings) -> Option<Throttle> {
+        let bytes_per_sec = settings.rate_limit_kib.checked_mul(1024).filter(|&rate| rate > 0)?;
+        Some(Throttle { bytes_per_sec, started: Instant::now(), sent: AtomicU64::new(0) })
+    }
+
+    /// Largest read that keeps each pause to about a tenth of a second, so
+    /// the bars move smoothly even at low limits.
+    fn chunk(&self) -> usize {
+        (self.bytes_per_sec / 10).max(1024) as usize
+    }
+
+    fn pace(&self, n: usize) {
+        let sent = self.sent.fetch_add(n as u64, Ordering::Relaxed) + n as u64;
+        let due = Duration::from_secs_f64(sent as f64 / self.bytes_per_sec as f64);
+        if let Some(ahead) = due.checked_sub(self.started.elapsed()) {
+            std::thread::sleep(ahead);
+        }
+    }
+}
+
+fn copy(from: &mut dyn Read, to: &mut dyn Write, buffer: &mut [u8], meter: &Meter) -> std::io::Result<()> {
+    let len = meter.throttle.map_or(buffer.len(), |throttle| throttle.chunk().min(buffer.len()));
+    loop {
+        if meter.cancel.load(Ordering::Relaxed) {
+            return Err(std::io::Error::new(std::io::ErrorKind::Interrupted, "cancelled"));
+        }
+        let n = from.read(&mut buffer[..len])?;
+        if n == 0 {
+            return Ok(());
+        }
+        to.write_all(&buffer[..n])?;
+        for bar in &meter.bars {
+            bar.inc(n as u64);
+        }
+        if let Some(throttle) = meter.throttle {
+            throttle.pace(n);
+        }
+    }
+}
+
+/// One in-flight file, under the overall bar.
+fn file_bar(job: &FileJob) -> ProgressBar {
+    let name = job.from.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+    let pb = ProgressBar::new(job.size).with_position(job.done).with_message(name);
+    pb.set_style(ProgressStyle::default_bar()
+        .template("  {msg:30!} [{bar:20.cyan/blue}] {bytes}/{total_bytes}")
+        .unwrap()
+        .progress_chars("#>-"));
+    pb
+}
+
+/// The overall bar, with the current rate and the average since the start.
+/// Bytes resumed from an earlier run don't count towards the average.
+fn progress_bar(total: u64, done: u64) -> ProgressBar {
+    let pb = ProgressBar::new(total).with_position(done);
+    let average = move |state: &ProgressState, w: &mut dyn std::fmt::Write| {
+        let secs = state.elapsed().as_secs_f64();
+        let rate = if secs > 0.0 { (state.pos().saturating_sub(done) as f64 / secs) as u64 } else { 0 };
+        let _ = write!(w, "{}/s", HumanBytes(rate));
+    };
+    pb.set_style(ProgressStyle::default_bar()
+        .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {bytes}/{total_bytes} {bytes_per_sec}, avg {average} ({eta}) {msg}")
+        .unwrap()
+        .with_key("average", average)
+        .progress_chars("#>-"));
+    pb
+}
@@ -0,0 +1,216 @@
+use crate::config::{AddressFamily, Server, Transport};
+use crate::forward;
+use crate::proxy;
+use ssh2::Session;
+use crate::settings::Settings;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+#[cfg(unix)]
+use std::os::unix::io::{AsRawFd, RawFd};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+#[cfg(windows)]
+use std::os::windows::io::{AsRawSocket, RawSocket};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::Duration;
+
+/// The byte stream a `Session` runs over. ssh2 needs a real file descriptor,
+/// so proxy commands are bridged through a socket pair.
+pub enum Stream {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+#[cfg(unix)]
+impl AsRawFd for Stream {
+    fn as_raw_fd(&self) -> RawFd {
+        match self {
+            Stream::Tcp(s) => s.as_raw_fd(),
+            Stream::Unix(s) => s.as_raw_fd(),
+        }
+    }
+}
+
+#[cfg(windows)]
+impl AsRawSocket for Stream {
+    fn as_raw_socket(&self) -> RawSocket {
+        match self {
+            Stream::Tcp(s) => s.as_raw_socket(),
+        }
+    }
+}
+
+pub fn connect(server: &Server, settings: &Settings) -> Result<Stream, Box<dyn std::error::Error>> {
+    match &server.transport {
+        Transport::Tcp => {
+            let family = server.address_family.unwrap_or(settings.address_family);
+            let timeout = settings.connect_timeout();
+            match proxy::for_server(server, settings)? {
+                Some(proxy) => {
+                    let stream = connect_tcp(&proxy.host, proxy.port, family, timeout)
+                        .map_err(|e| format!("Could not reach proxy {}: {}", proxy, e))?;
+                    // A proxy that accepts but never answers must not hang us either.
+                    stream.set_read_timeout(Some(timeout))?;
+                    let stream = proxy.tunnel(stream, &server.host, server.port)?;
+                    stream.set_read_timeout(None)?;
+                    Ok(Stream::Tcp(stream))
+                }
+                None => Ok(Stream::Tcp(connect_tcp(&server.host, server.port, family, timeout)?)),
+            }
+        }
+        #[cfg(unix)]
+        Transport::UnixSocket(path) => {
+            let stream = UnixStream::connect(path)
+                .map_err(|e| format!("Could not connect to socket {}: {}", path, e))?;
+            Ok(Stream::Unix(stream))
+        }
+        #[cfg(unix)]
+        Transport::ProxyCommand(command) => spawn_proxy(&expand_proxy_command(command, server)),
+        #[cfg(not(unix))]
+        _ => Err("Unix sockets and proxy commands are only supported on Unix".into()),
+    }
+}
+
+/// Resolves the host and tries its addresses in the order the address
+/// family preference asks for, giving each `timeout` to answer.
+fn connect_tcp(host: &str, port: u16, family: AddressFamily, timeout: Duration) -> Result<TcpStream, Box<dyn std::error::Error>> {
+    let resolved: Vec<SocketAddr> = (host, port).to_socket_addrs()
+        .map_err(|e| format!("Could not resolve {}: {}", host, e))?
+        .collect();
+    let (v4, v6): (Vec<SocketAddr>, Vec<SocketAddr>) = resolved.iter().partition(|a| a.is_ipv4());
+    let candidates = match family {
+        AddressFamily::Auto => resolved,
+        AddressFamily::PreferIpv4 => [v4, v6].concat(),
+        AddressFamily::PreferIpv6 => [v6, v4].concat(),
+        AddressFamily::Ipv4Only => v4,
+        AddressFamily::Ipv6Only => v6,
+    };
+    if candidates.is_empty() {
+        return Err(format!("{} has no addresses for {}", host, family.label()).into());
+    }
+
+    let mut last_error = None;
+    for addr in candidates {
+        match TcpStream::connect_timeout(&addr, timeout) {
+            Ok(stream) => return Ok(stream),
+            Err(e) if e.kind() == std::io::ErrorKind::TimedOut => {
+                last_error = Some(format!("{}: connection timed out after {}s", addr, timeout.as_secs()));
+            }
+            Err(e) => last_error = Some(format!("{}: {}", addr, e)),
+        }
+    }
+    Err(last_error.unwrap_or_default().into())
+}
+
+/// Expands the OpenSSH-style tokens `%h`, `%p`, `%r` and `%%`.
+pub fn expand_proxy_command(command: &str, server: &Server) -> String {
+    let mut expanded = String::new();
+    let mut chars = command.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            expanded.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('h') => expanded.push_str(&server.host),
+            Some('p') => expanded.push_str(&server.port.to_string()),
+            Some('r') => expanded.push_str(&server.user),
+            Some('%') => expanded.push('%'),
+            Some(other) => {
+                expanded.push('%');
+                expanded.push(other);
+            }
+            None => expanded.push('%'),
+        }
+    }
+    expanded
+}
+
+#[cfg(unix)]
+fn spawn_proxy(command: &str) -> Result<Stream, Box<dyn std::error::Error>> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("Could not start proxy command '{}': {}", command, e))?;
+
+    let (ours, theirs) = UnixStream::pair()?;
+    let mut child_stdin = child.stdin.take().ok_or("Proxy command has no stdin")?;
+    let mut child_stdout = child.stdout.take().ok_or("Proxy command has no stdout")?;
+    let mut to_child = theirs.try_clone()?;
+    let mut from_child = theirs;
+
+    // Session -> proxy. When the session hangs up, close the proxy's stdin
+    // and reap it.
+    thread::spawn(move || {
+        let mut buf = [0u8; 8192];
+        loop {
+            match to_child.read(&mut buf) {
+                Ok(0) | Err(_) => break,
+                Ok(n) => {
+                    if child_stdin.write_all(&buf[..n]).is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+        drop(child_stdin);
+        let _ = child.wait();
+    });
+
+    // Proxy -> session.
+    thread::spawn(move || {
+        let mut buf = [0u8; 8192];
+        loop {
+            match child_stdout.read(&mut buf) {
+                Ok(0) | Err(_) => break,
+                Ok(n) => {
+                    if from_child.write_all(&buf[..n]).is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+        let _ = from_child.shutdown(std::net::Shutdown::Write);
+    });
+
+    Ok(Stream::Unix(ours))
+}
+
+/// Reaches `host:port` through an authenticated session to a jump host
+/// (`ssh -J`). The jump session is moved to a thread that pumps between a
+/// `direct-tcpip` channel and one end of a socket pair, whose other end
+/// becomes the target session's stream.
+#[cfg(unix)]
+pub fn via_jump(jump: Session, host: &str, port: u16) -> Result<Stream, Box<dyn std::error::Error>> {
+    let mut channel = jump.channel_direct_tcpip(host, port, None)
+        .map_err(|e| format!("Jump host could not reach {}:{}: {}", host, port, e))?;
+    let (ours, mut theirs) = UnixStream::pair()?;
+    theirs.set_nonblocking(true)?;
+
+    thread::spawn(move || {
+        jump.set_blocking(false);
+        let mut buf = [0u8; 16384];
+        loop {
+            let mut idle = true;
+            if !forward::pump(&mut theirs, &mut channel, &mut buf, &mut idle) {
+                break;
+            }
+            if idle {
+                thread::sleep(Duration::from_millis(2));
+            }
+        }
+        let _ = jump.disconnect(None, "", None);
+    });
+
+    Ok(Stream::Unix(ours))
+}
+
+#[cfg(not(unix))]
+pub fn via_jump(_jump: Session, _host: &str, _port: u16) -> Result<Stream, Box<dyn std::error::Error>> {
+    Err("Jump hosts are only supported on Unix".into())
+}
@@ -1,164 +1,1878 @@
-use crate::config::{Server, AuthType};
-use dialoguer::{theme::ColorfulTheme, Input, Select, Password, FuzzySelect};
-use console::Term;
+use crate::app::App;
+use crate::config::{self, parse_host_spec, AddressFamily, validate_ssh_option, Server, AuthType, Transport};
+use crate::draft::ServerDraft;
+use crate::forward::ForwardSpec;
+use crate::keymap::{self, Binding};
+use crate::lock;
+use crate::proxy::{self, Proxy};
+use crate::settings::{Settings, SortMode};
+use crate::session;
+use crate::theme;
+use crate::transfer;
+use crate::keycheck;
+use dialoguer::{Confirm, Input, Select, MultiSelect, Password, FuzzySelect};
+use dialoguer::Completion;
+use console::{style, Key, Style, Term};
+use indicatif::HumanBytes;
+use ssh2::{Prompt, Sftp};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use zeroize::Zeroizing;
 
+#[derive(Clone, Copy)]
 pub enum Action {
     Connect,
+    ConnectGroup,
+    SystemSsh,
+    CopyCommand,
+    OpenWebUi,
     AddServer,
+    EditServer,
+    DuplicateServer,
     RemoveServer,
     ListServers,
-    ImportConfig,
+    HealthView,
+    ServerDetails,
+    ToggleFavorite,
+    ReorderServers,
+    History,
+    PinnedCommands,
+    RunCommand,
+    SshOptions,
+    Forwards,
+    Diagnose,
+    CheckKeys,
+    Import,
+    Export,
     FileTransfer,
+    Settings,
+    ChangeMasterPassword,
+    SwitchProfile,
+    TogglePrivacy,
+    Help,
     Exit,
 }
 
-pub enum TransferDirection {
+/// Confirmations that fast mode is allowed to skip. Anything not listed here
+/// is irreversible enough that it always asks.
+#[derive(Clone, Copy)]
+pub enum Confirmation {
+    RemoveServer,
+    Quit,
+}
+
+pub enum PaletteChoice {
+    Run(usize),
+    Inject(usize),
+    Add,
+    Remove,
+    Back,
+}
+
+pub enum ForwardsChoice {
+    Start,
+    Stop,
+    Add,
+    Remove(usize),
+    Back,
+}
+
+pub enum OptionsChoice {
+    Add,
+    Remove(usize),
+    Back,
+}
+
+/// What to do next on a server after its shell ends, on the same session.
+#[derive(Clone, Copy)]
+pub enum SessionChoice {
+    Shell,
+    Sftp,
+    Command,
+    Back,
+}
+
+#[derive(Clone, Copy)]
+pub enum ImportSource {
+    SshConfig,
+    /// Like `SshConfig`, but refreshes servers that were imported before.
+    SshConfigSync,
+    Csv,
+    Json,
+    HostList,
+    Putty,
+    Plaintext,
+}
+
+pub enum KeyFix {
+    /// New auth settings with the corrected paths.
+    Repoint(AuthType),
+    UseAgent,
+    Skip,
+}
+
+/// What the remote browser is picking.
+#[derive(Clone, Copy, PartialEq)]
+pub enum BrowseTarget {
+    /// A file, or a whole directory, to download.
+    Download,
+    /// A directory to upload into.
+    Upload,
+}
+
+#[derive(Clone, Copy)]
+pub enum CopyFormat {
+    Ssh,
+    Scp,
+}
+
+#[derive(Clone, Copy)]
+pub enum SftpAction {
     Upload,
     Download,
+    DeleteFile,
+    RemoveDir,
+    Rename,
+    MakeDir,
+    Chmod,
 }
 
-pub fn main_menu() -> Action {
-    let items = vec![
-        "🚀 Connect to Server",
-        "➕ Add New Server",
-        "🗑️  Remove Server",
-        "📋 List Servers",
-        "📥 Import from SSH Config",
-        "📂 File Transfer (SFTP)",
-        "🚪 Exit",
+/// Raw terminal mode for as long as this is held. Dropping it restores the
+/// terminal, so early returns and panics can't leave it raw.
+pub struct RawMode(());
+
+impl RawMode {
+    pub fn enable() -> std::io::Result<RawMode> {
+        crossterm::terminal::enable_raw_mode()?;
+        Ok(RawMode(()))
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        let _ = crossterm::terminal::disable_raw_mode();
+    }
+}
+
+/// Esc or Ctrl-C, which stop a long wait while raw mode is on.
+pub fn is_cancel_key(key: &crossterm::event::KeyEvent) -> bool {
+    use crossterm::event::{KeyCode, KeyEventKind, KeyModifiers};
+    let ctrl_c = key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL);
+    key.kind == KeyEventKind::Press && (key.code == KeyCode::Esc || ctrl_c)
+}
+
+/// Every key the app responds to, grouped by where it applies. This is the
+/// single source for the help screen; the pager and health view rows show
+/// the keys currently bound in settings.
+fn key_bindings() -> Vec<(&'static str, Vec<(String, &'static str)>)> {
+    let k = keymap::label;
+    let rows = |rows: &[(&str, &'static str)]| rows.iter().map(|(keys, what)| (keys.to_string(), *what)).collect();
+    vec![
+        ("Menus", rows(&[
+            ("↑ / ↓ / j / k", "Move the selection"),
+            ("Enter / Space", "Choose the highlighted item"),
+            ("Esc / q", "Cancel (exits from the main menu)"),
+        ])),
+        ("Server search", rows(&[
+            ("Type", "Fuzzy-filter servers by alias, user or host, and groups by name"),
+            ("Enter on a group", "Collapse or expand that group"),
+            ("Enter on ↕️ Sorted", "Sort by name, group or last connected"),
+            ("Backspace", "Remove the last search character"),
+            ("↑ / ↓", "Move through matches"),
+            ("Enter", "Pick the highlighted server"),
+            ("Esc", "Cancel"),
+        ])),
+        ("Text prompts", rows(&[
+            ("Enter", "Accept the value (or the default shown in brackets)"),
+            ("← / →", "Move the cursor"),
+        ])),
+        ("Interactive shell", rows(&[
+            ("Any key", "Sent to the remote shell, including Ctrl-C"),
+            ("exit / Ctrl-D", "Close the session and return to the menu"),
+        ])),
+        ("Health view", vec![
+            (k(Binding::ToggleView), "Switch between status and group views"),
+            (k(Binding::Refresh), "Probe all servers again now"),
+            (k(Binding::Help), "Show this help"),
+            (format!("{} / Esc", k(Binding::Quit)), "Back to the main menu"),
+        ]),
+        ("Command output", vec![
+            (format!("↑ / ↓ / {} / {}", k(Binding::Next), k(Binding::Prev)), "Scroll a line"),
+            (format!("{} / {}", k(Binding::PageDown), k(Binding::PageUp)), "Scroll a page down / up"),
+            (format!("{0}{0} / {1}", k(Binding::Top), k(Binding::Bottom)), "Jump to the top / bottom"),
+            (
+                format!("5{} / 3{} / 40{}", k(Binding::Next), k(Binding::PageUp), k(Binding::Bottom)),
+                "A number first repeats the motion, or picks the line",
+            ),
+            (k(Binding::Help), "Show this help"),
+            (format!("{} / Esc", k(Binding::Quit)), "Close the output"),
+        ]),
+        ("SFTP", rows(&[
+            ("Type", "Filter the entries of the current directory"),
+            ("Enter on 📁", "Open a directory (📁 .. goes up)"),
+            ("Enter", "Confirm a path or direction"),
+            ("Esc", "Cancel the prompt"),
+            ("Esc / Ctrl-C", "Cancel a running transfer (partly copied files can be resumed)"),
+        ])),
+    ]
+}
+
+/// Every key binding, grouped by where it applies, in a box over the
+/// screen. Only the help and quit keys and Esc close it; other keys are
+/// ignored rather than passed on to whatever is underneath.
+pub fn show_help() {
+    let term = Term::stderr();
+    let mut lines = Vec::new();
+    for (context, bindings) in key_bindings() {
+        if !lines.is_empty() {
+            lines.push(String::new());
+        }
+        lines.push(theme::accented(context).bold().to_string());
+        for (keys, description) in bindings {
+            lines.push(format!("  {:<20} {}", theme::highlighted(keys), description));
+        }
+    }
+    let (help, quit) = (keymap::key(Binding::Help), keymap::key(Binding::Quit));
+    lines.push(String::new());
+    lines.push(style(format!("{} / Esc / {} to close", keymap::label(Binding::Help), keymap::label(Binding::Quit))).dim().to_string());
+    draw_popup(&term, "⌨️  Keyboard Shortcuts", &lines, theme::accent());
+
+    loop {
+        match term.read_key() {
+            Ok(Key::Char(c)) if c == help || c == quit => break,
+            Ok(Key::Escape) | Err(_) => break,
+            _ => {}
+        }
+    }
+    let _ = term.clear_screen();
+}
+
+pub fn main_menu(app: &mut App, profile: &str, server_count: usize) -> Action {
+    let profile_label = format!("👤 Profile: {}", profile);
+    let forwards_label = match app.forwards.len() {
+        0 => "🔀 Port Forwards".to_string(),
+        n => format!("🔀 Port Forwards ({} active)", n),
+    };
+    let items = [
+        ("🚀 Connect to Server", Action::Connect),
+        ("🪟 Connect to Group (tmux)", Action::ConnectGroup),
+        ("🖥️  Connect with System ssh", Action::SystemSsh),
+        ("📋 Copy ssh Command", Action::CopyCommand),
+        ("🌐 Open Web UI", Action::OpenWebUi),
+        ("➕ Add New Server", Action::AddServer),
+        ("✏️  Edit Server", Action::EditServer),
+        ("📄 Duplicate Server", Action::DuplicateServer),
+        ("🗑️  Remove Server", Action::RemoveServer),
+        ("📋 List Servers", Action::ListServers),
+        ("🚦 Health View", Action::HealthView),
+        ("🔎 Server Details", Action::ServerDetails),
+        ("⭐ Toggle Favorite", Action::ToggleFavorite),
+        ("↕️  Reorder Servers", Action::ReorderServers),
+        ("📜 Connection History", Action::History),
+        ("📌 Pinned Commands", Action::PinnedCommands),
+        ("⚡ Run Command", Action::RunCommand),
+        ("🔧 System ssh Options", Action::SshOptions),
+        (forwards_label.as_str(), Action::Forwards),
+        ("🩺 Diagnose Connection", Action::Diagnose),
+        ("🗝️  Check Key Files", Action::CheckKeys),
+        ("📥 Import Servers", Action::Import),
+        ("📤 Export Servers (Plaintext)", Action::Export),
+        ("📂 File Transfer (SFTP)", Action::FileTransfer),
+        ("⚙️  Settings", Action::Settings),
+        ("🔑 Change Master Password", Action::ChangeMasterPassword),
+        (profile_label.as_str(), Action::SwitchProfile),
+        (if app.privacy_mode { "🙈 Privacy Mode: On" } else { "👀 Privacy Mode: Off" }, Action::TogglePrivacy),
+        ("❓ Help", Action::Help),
+        ("🚪 Exit", Action::Exit),
     ];
+    let labels: Vec<&str> = items.iter().map(|(label, _)| *label).collect();
+
+    let selection = Select::with_theme(&theme::dialog())
+        .with_prompt(format!(
+            "SSH Manager - Select an action {}",
+            style(format!("({} server{})", server_count, if server_count == 1 { "" } else { "s" })).dim(),
+        ))
+        .default(app.menu_selection.min(items.len() - 1))
+        .items(&labels)
+        .interact_on_opt(&Term::stderr());
+    // Esc and `q` leave the menu the same way as picking Exit.
+    let Ok(Some(selection)) = selection else {
+        return Action::Exit;
+    };
+
+    app.menu_selection = selection;
+    items[selection].1
+}
+
+/// Shows an error in a red box in the middle of the screen until any key is
+/// pressed, so it can't scroll away unnoticed.
+pub fn error_popup(message: &str) {
+    let term = Term::stderr();
+    let width = (term.size().1 as usize).saturating_sub(8).clamp(20, 72);
+    let mut lines = wrap(message, width);
+    lines.push(String::new());
+    lines.push(style("Press any key to dismiss").dim().to_string());
+    draw_popup(&term, "Error", &lines, theme::error());
+    let _ = term.read_key();
+    let _ = term.clear_screen();
+}
+
+/// Clears the screen and draws `lines` in a box with `title` in the middle.
+/// Lines may be styled; they're padded by their visible width.
+fn draw_popup(term: &Term, title: &str, lines: &[String], border: Style) {
+    let (rows, columns) = term.size();
+    let inner = lines.iter().map(|line| console::measure_text_width(line))
+        .chain([console::measure_text_width(title) + 4])
+        .max()
+        .unwrap_or(0)
+        .min((columns as usize).saturating_sub(4));
+    let indent = " ".repeat((columns as usize).saturating_sub(inner + 4) / 2);
+    let side = border.apply_to("│");
+
+    let mut screen = vec![String::new(); (rows as usize).saturating_sub(lines.len() + 2) / 2];
+    screen.push(format!("{}{}", indent, border.apply_to(format!("┌─ {} {}┐", title, "─".repeat(inner.saturating_sub(console::measure_text_width(title) + 1))))));
+    for line in lines {
+        let line = console::pad_str(line, inner, console::Alignment::Left, Some("…"));
+        screen.push(format!("{}{} {} {}", indent, side, line, side));
+    }
+    screen.push(format!("{}{}", indent, border.apply_to(format!("└{}┘", "─".repeat(inner + 2)))));
+
+    let _ = term.clear_screen();
+    let _ = term.write_line(&screen.join("\n"));
+}
+
+/// Word-wraps `text` to `width` columns, breaking words that don't fit.
+fn wrap(text: &str, width: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    for paragraph in text.lines() {
+        let mut line = String::new();
+        for word in paragraph.split_whitespace() {
+            let mut word: Vec<char> = word.chars().collect();
+            while word.len() > width {
+                if !line.is_empty() {
+                    lines.push(std::mem::take(&mut line));
+                }
+                lines.push(word.drain(..width).collect());
+            }
+            let word: String = word.into_iter().collect();
+            if !line.is_empty() && line.chars().count() + 1 + word.chars().count() > width {
+                lines.push(std::mem::take(&mut line));
+            }
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.push_str(&word);
+        }
+        lines.push(line);
+    }
+    lines
+}
 
-    let selection = Select::with_theme(&ColorfulTheme::default())
-        .with_prompt("SSH Manager - Select an action")
+/// Fuzzy-searchable list of a server's pinned commands plus management entries.
+pub fn command_palette(server: &Server) -> PaletteChoice {
+    let mut items: Vec<String> = server.commands
+        .iter()
+        .map(|(name, command)| format!("▶ {} → {}", name, command))
+        .collect();
+    items.push("➕ Add command".to_string());
+    items.push("🗑️  Remove command".to_string());
+    items.push("⬅️  Back".to_string());
+
+    let Ok(selection) = FuzzySelect::with_theme(&theme::dialog())
+        .with_prompt(format!("Pinned commands for {} (Type to search)", server.name))
         .default(0)
         .items(&items)
         .interact_on(&Term::stderr())
-        .unwrap_or(6); 
+    else {
+        return PaletteChoice::Back;
+    };
 
-    match selection {
-        0 => Action::Connect,
-        1 => Action::AddServer,
-        2 => Action::RemoveServer,
-        3 => Action::ListServers,
-        4 => Action::ImportConfig,
-        5 => Action::FileTransfer,
-        _ => Action::Exit,
+    let count = server.commands.len();
+    if selection == count {
+        return PaletteChoice::Add;
     }
+    if selection == count + 1 {
+        return PaletteChoice::Remove;
+    }
+    if selection > count + 1 {
+        return PaletteChoice::Back;
+    }
+
+    let modes = vec!["Run and show output", "Open a shell and type it"];
+    match Select::with_theme(&theme::dialog())
+        .with_prompt("How should it run?")
+        .default(0)
+        .items(&modes)
+        .interact_on(&Term::stderr())
+    {
+        Ok(0) => PaletteChoice::Run(selection),
+        Ok(_) => PaletteChoice::Inject(selection),
+        Err(_) => PaletteChoice::Back,
+    }
+}
+
+/// Asks for a one-shot command, offering the server's recent ones first.
+pub fn one_shot_command_prompt(server: &Server) -> Option<String> {
+    if !server.recent_commands.is_empty() {
+        let mut items = vec!["✏️  New command".to_string()];
+        items.extend(server.recent_commands.iter().cloned());
+        let selection = FuzzySelect::with_theme(&theme::dialog())
+            .with_prompt(format!("Run on {} (Type to search recent commands)", server.name))
+            .default(0)
+            .items(&items)
+            .interact_on(&Term::stderr())
+            .ok()?;
+        if selection > 0 {
+            return Some(server.recent_commands[selection - 1].clone());
+        }
+    }
+
+    let command: String = Input::with_theme(&theme::dialog())
+        .with_prompt("Command")
+        .interact_text()
+        .unwrap();
+    let command = command.trim();
+    (!command.is_empty()).then(|| command.to_string())
+}
+
+/// Shows command output, paging it when it doesn't fit on screen.
+pub fn show_output(title: &str, text: &str) {
+    let term = Term::stdout();
+    let lines: Vec<&str> = text.lines().collect();
+    let (rows, columns) = term.size();
+    let page = (rows as usize).saturating_sub(3).max(1);
+
+    if lines.len() <= page {
+        println!("{}", style(title).bold());
+        for line in &lines {
+            println!("{}", line);
+        }
+        println!("\nPress Enter to continue...");
+        lock::waiting();
+        let _ = std::io::stdin().read_line(&mut String::new());
+        lock::resumed();
+        return;
+    }
+
+    let last_top = lines.len() - page;
+    let mut top = 0;
+    // Vim-style: a number typed first repeats the next motion (`5j`) or,
+    // before `gg`/`G`, names the line to jump to.
+    let mut count: Option<usize> = None;
+    let mut pending_g = false;
+    loop {
+        let _ = term.clear_screen();
+        println!("{}", style(title).bold());
+        for line in lines.iter().skip(top).take(page) {
+            println!("{}", console::truncate_str(line, columns as usize, "…"));
+        }
+        let pending = match (count, pending_g) {
+            (None, false) => String::new(),
+            (count, g) => format!("  {}{}", count.map(|n| n.to_string()).unwrap_or_default(), if g { "g" } else { "" }),
+        };
+        let k = keymap::label;
+        println!("{}", style(format!(
+            "lines {}-{} of {}  ↑/↓ scroll  {}/{} page  {}{}/{} top/bottom  {} help  {} close{}",
+            top + 1, (top + page).min(lines.len()), lines.len(),
+            k(Binding::PageDown), k(Binding::PageUp), k(Binding::Top), k(Binding::Top), k(Binding::Bottom),
+            k(Binding::Help), k(Binding::Quit), pending,
+        )).dim());
+
+        lock::waiting();
+        let key = term.read_key();
+        if lock::resumed() {
+            return;
+        }
+        if let Ok(Key::Char(digit @ '0'..='9')) = key {
+            if digit != '0' || count.is_some() {
+                let digit = digit.to_digit(10).unwrap_or(0) as usize;
+                count = Some(count.unwrap_or(0).saturating_mul(10).saturating_add(digit));
+                pending_g = false;
+                continue;
+            }
+        }
+        let explicit = count.take();
+        let times = explicit.unwrap_or(1);
+        let line = |n: usize| n.saturating_sub(1);
+        let binding = match key {
+            Ok(Key::ArrowDown) => Some(Binding::Next),
+            Ok(Key::ArrowUp) => Some(Binding::Prev),
+            Ok(Key::PageDown) => Some(Binding::PageDown),
+            Ok(Key::PageUp) => Some(Binding::PageUp),
+            Ok(Key::Escape) | Err(_) => Some(Binding::Quit),
+            Ok(Key::Char(c)) => keymap::lookup(c, keymap::PAGER),
+            _ => None,
+        };
+        top = match (key, binding) {
+            (_, Some(Binding::Next)) => top.saturating_add(times),
+            (_, Some(Binding::Prev)) => top.saturating_sub(times),
+            (_, Some(Binding::PageDown)) => top.saturating_add(page.saturating_mul(times)),
+            (_, Some(Binding::PageUp)) => top.saturating_sub(page.saturating_mul(times)),
+            (_, Some(Binding::Top)) if !pending_g => {
+                pending_g = true;
+                count = explicit;
+                continue;
+            }
+            (_, Some(Binding::Top)) => explicit.map_or(0, line),
+            (Ok(Key::Home), _) => 0,
+            (_, Some(Binding::Bottom)) => explicit.map_or(last_top, line),
+            (Ok(Key::End), _) => last_top,
+            (_, Some(Binding::Help)) => {
+                show_help();
+                top
+            }
+            (_, Some(Binding::Quit)) => break,
+            _ => top,
+        }
+        .min(last_top);
+        pending_g = false;
+    }
+}
+
+pub fn pinned_command_prompt() -> (String, String) {
+    println!("ℹ️  Snippets are stored with the server; keep passwords and tokens out of them.");
+    let name: String = Input::with_theme(&theme::dialog())
+        .with_prompt("Command name")
+        .interact_text()
+        .unwrap();
+    let command: String = Input::with_theme(&theme::dialog())
+        .with_prompt("Command")
+        .interact_text()
+        .unwrap();
+    (name, command)
 }
 
-pub fn file_transfer_menu() -> TransferDirection {
-    let items = vec!["⬆️  Upload (Local -> Remote)", "⬇️  Download (Remote -> Local)"];
-    let selection = Select::with_theme(&ColorfulTheme::default())
-        .with_prompt("Select transfer direction")
+pub fn select_pinned_command(server: &Server) -> Option<usize> {
+    if server.commands.is_empty() {
+        println!("⚠️  No pinned commands yet.");
+        return None;
+    }
+    let items: Vec<&str> = server.commands.iter().map(|(name, _)| name.as_str()).collect();
+    FuzzySelect::with_theme(&theme::dialog())
+        .with_prompt("Select a command")
         .default(0)
         .items(&items)
-        .interact()
+        .interact_on(&Term::stderr())
+        .ok()
+}
+
+/// Offered when a shell ends. *Back* is highlighted, so Enter returns to the
+/// main menu as before; the rest reuse the session without logging in again.
+pub fn session_menu(server: &Server) -> SessionChoice {
+    let choices = [
+        ("🖥️  Shell", SessionChoice::Shell),
+        ("📂 File Transfer (SFTP)", SessionChoice::Sftp),
+        ("⚡ Run Command", SessionChoice::Command),
+        ("⬅️  Back", SessionChoice::Back),
+    ];
+    let items: Vec<&str> = choices.iter().map(|(label, _)| *label).collect();
+    Select::with_theme(&theme::dialog())
+        .with_prompt(format!("Still connected to {}", server.name))
+        .default(choices.len() - 1)
+        .items(&items)
+        .interact_on_opt(&Term::stderr())
+        .ok()
+        .flatten()
+        .map_or(SessionChoice::Back, |i| choices[i].1)
+}
+
+/// Lists a server's `-o` options; picking one offers to remove it.
+pub fn ssh_options_menu(server: &Server) -> OptionsChoice {
+    let mut items: Vec<String> = server.ssh_options.iter().map(|o| format!("-o {}", o)).collect();
+    items.push("➕ Add option".to_string());
+    items.push("⬅️  Back".to_string());
+
+    let Ok(selection) = Select::with_theme(&theme::dialog())
+        .with_prompt(format!("System ssh options for {} (pick one to remove it)", server.name))
+        .default(0)
+        .items(&items)
+        .interact_on(&Term::stderr())
+    else {
+        return OptionsChoice::Back;
+    };
+
+    if selection < server.ssh_options.len() {
+        OptionsChoice::Remove(selection)
+    } else if selection == server.ssh_options.len() {
+        OptionsChoice::Add
+    } else {
+        OptionsChoice::Back
+    }
+}
+
+pub fn ssh_option_prompt() -> String {
+    Input::with_theme(&theme::dialog())
+        .with_prompt("Option (Key=Value, e.g. ServerAliveInterval=60)")
+        .validate_with(|v: &String| validate_ssh_option(v.trim()))
+        .interact_text()
+        .unwrap()
+        .trim()
+        .to_string()
+}
+
+/// A server's saved forwards, marking the ones running, plus start/stop.
+pub fn forwards_menu(server: &Server, active: &[&str]) -> ForwardsChoice {
+    let mut items: Vec<String> = server.forwards.iter()
+        .map(|f| format!("{} {}", if active.contains(&f.as_str()) { "🟢" } else { "⚪" }, f))
+        .collect();
+    let mut actions = Vec::new();
+    if active.len() < server.forwards.len() {
+        actions.push(("▶️  Start forwards", ForwardsChoice::Start));
+    }
+    if !active.is_empty() {
+        actions.push(("⏹️  Stop forwards", ForwardsChoice::Stop));
+    }
+    actions.push(("➕ Add forward", ForwardsChoice::Add));
+    actions.push(("⬅️  Back", ForwardsChoice::Back));
+    items.extend(actions.iter().map(|(label, _)| label.to_string()));
+
+    let Ok(selection) = Select::with_theme(&theme::dialog())
+        .with_prompt(format!("Port forwards for {} (pick one to remove it)", server.name))
+        .default(server.forwards.len())
+        .items(&items)
+        .interact_on(&Term::stderr())
+    else {
+        return ForwardsChoice::Back;
+    };
+
+    if selection < server.forwards.len() {
+        ForwardsChoice::Remove(selection)
+    } else {
+        actions.swap_remove(selection - server.forwards.len()).1
+    }
+}
+
+pub fn forward_prompt() -> String {
+    Input::with_theme(&theme::dialog())
+        .with_prompt("Forward (L 8080:localhost:80 or R 9000:localhost:9000)")
+        .validate_with(|v: &String| ForwardSpec::parse(v).map(|_| ()))
+        .interact_text()
+        .unwrap()
+        .trim()
+        .to_string()
+}
+
+/// Some destinations already hold part (or all) of their file from an
+/// earlier run. Yes skips what's there; no, the default, copies everything
+/// again, which is always safe.
+pub fn resume_transfer_prompt(partial: usize, done_bytes: u64) -> bool {
+    Confirm::with_theme(&theme::dialog())
+        .with_prompt(format!("Resume {} file(s) already partly transferred ({} there)? No starts over", partial, HumanBytes(done_bytes)))
+        .default(false)
+        .interact_on(&Term::stderr())
+        .unwrap_or(false)
+}
+
+pub fn copy_format_prompt() -> Option<CopyFormat> {
+    let items = ["ssh command (ssh -p PORT user@host)", "scp command (scp -P PORT ... user@host:)"];
+    Select::with_theme(&theme::dialog())
+        .with_prompt("Copy as")
+        .default(0)
+        .items(&items)
+        .interact_on_opt(&Term::stderr())
+        .ok()
+        .flatten()
+        .map(|i| if i == 0 { CopyFormat::Ssh } else { CopyFormat::Scp })
+}
+
+/// `None` when the user is done with the server.
+pub fn file_transfer_menu() -> Option<SftpAction> {
+    let items = [
+        ("⬆️  Upload (Local -> Remote)", Some(SftpAction::Upload)),
+        ("⬇️  Download (Remote -> Local)", Some(SftpAction::Download)),
+        ("✏️  Rename or Move", Some(SftpAction::Rename)),
+        ("📁 Create Directory", Some(SftpAction::MakeDir)),
+        ("🔐 Permissions (chmod)", Some(SftpAction::Chmod)),
+        ("🗑️  Delete File", Some(SftpAction::DeleteFile)),
+        ("🗑️  Remove Empty Directory", Some(SftpAction::RemoveDir)),
+        ("⬅️  Back", None),
+    ];
+    let labels: Vec<&str> = items.iter().map(|(label, _)| *label).collect();
+    let selection = Select::with_theme(&theme::dialog())
+        .with_prompt("SFTP")
+        .default(0)
+        .items(&labels)
+        .interact_on(&Term::stderr())
+        .unwrap_or(items.len() - 1);
+    items[selection].1
+}
+
+/// New octal permissions, starting from `current`. Blank keeps them.
+pub fn mode_prompt(current: u32) -> Option<u32> {
+    let mode: String = Input::with_theme(&theme::dialog())
+        .with_prompt("New mode, e.g. 644 or 0755 (blank keeps it)")
+        .allow_empty(true)
+        .validate_with(|v: &String| if v.trim().is_empty() { Ok(()) } else { transfer::parse_mode(v).map(|_| ()) })
+        .interact_text()
         .unwrap();
+    transfer::parse_mode(&mode).ok().filter(|mode| *mode != current & 0o7777)
+}
 
-    match selection {
-        0 => TransferDirection::Upload,
-        _ => TransferDirection::Download,
+/// Asks even in fast mode, for actions that can't be undone.
+pub fn confirm_always(prompt: &str) -> bool {
+    Confirm::with_theme(&theme::dialog())
+        .with_prompt(prompt)
+        .default(false)
+        .interact_on(&Term::stderr())
+        .unwrap_or(false)
+}
+
+pub fn confirm(kind: Confirmation, prompt: &str, fast_mode: bool) -> bool {
+    match kind {
+        Confirmation::RemoveServer | Confirmation::Quit if fast_mode => return true,
+        _ => {}
+    }
+    Confirm::with_theme(&theme::dialog())
+        .with_prompt(prompt)
+        .default(false)
+        .interact_on(&Term::stderr())
+        .unwrap_or(false)
+}
+
+/// Lets the user edit settings until they pick "Back". Returns whether anything changed.
+pub fn settings_menu(settings: &mut Settings) -> bool {
+    let mut changed = false;
+    loop {
+        let items = vec![
+            format!("SFTP transfer buffer: {} KiB", settings.transfer_buffer_kib),
+            format!("Fast mode (skip routine confirmations): {}", if settings.fast_mode { "on" } else { "off" }),
+            format!("Timestamps: {}", if settings.relative_times { "relative (3 minutes ago)" } else { "absolute" }),
+            format!("Clock: {}", if settings.clock_24h { "24-hour" } else { "12-hour" }),
+            format!("Address family: {}", settings.address_family.label()),
+            match settings.session_idle_secs {
+                0 => "Reuse sessions: off".to_string(),
+                secs => format!("Reuse sessions: for {}s after last use", secs),
+            },
+            format!("Proxy: {}", settings.proxy.as_deref().unwrap_or("from ALL_PROXY/HTTPS_PROXY")),
+            format!("Master password key derivation: Argon2id, {} MiB, {} passes", settings.kdf_memory_kib / 1024, settings.kdf_passes),
+            format!("Connection timeout: {}s", settings.connect_timeout_secs),
+            format!("Theme: {}", settings.theme.preset.label()),
+            format!("Remote terminal type: {}", settings.remote_term.as_deref().unwrap_or("xterm-256color")),
+            match settings.keepalive_secs {
+                0 => "Keepalive: off".to_string(),
+                secs => format!("Keepalive: every {}s while a shell is idle", secs),
+            },
+            match settings.connect_attempts {
+                0 | 1 => "Connection attempts: 1 (no retries)".to_string(),
+                n => format!("Connection attempts: {} (backing off 1s, 2s, 4s...)", n),
+            },
+            format!("Record shell sessions to log files: {}", if settings.record_sessions { "on" } else { "off" }),
+            match settings.history_limit {
+                0 => "Connection history: off".to_string(),
+                n => format!("Connection history: last {} connections", n),
+            },
+            match settings.upload_mode() {
+                Some(mode) => format!("Permissions for uploaded files: {}", transfer::format_mode(mode)),
+                None => "Permissions for uploaded files: server default".to_string(),
+            },
+            match settings.parallel_transfers {
+                0 | 1 => "Parallel transfers: off (one file at a time)".to_string(),
+                n => format!("Parallel transfers: up to {} files at once", n),
+            },
+            match settings.rate_limit_kib {
+                0 => "Transfer rate limit: unlimited".to_string(),
+                kib => format!("Transfer rate limit: {} KiB/s", kib),
+            },
+            format!("Verify transfers by SHA-256: {}", if settings.verify_checksums { "on" } else { "off" }),
+            format!("Encrypt server lists: {}", if settings.plaintext_servers { "off (no master password)" } else { "on" }),
+            match settings.idle_lock_mins {
+                0 => "Idle lock: off".to_string(),
+                mins => format!("Idle lock: after {} min at the main menu", mins),
+            },
+            format!("New server defaults: user {}, port {}", settings.default_user.as_deref().unwrap_or("(none)"), settings.default_port),
+            "⬅️  Back".to_string(),
+        ];
+        let selection = Select::with_theme(&theme::dialog())
+            .with_prompt("Settings")
+            .default(0)
+            .items(&items)
+            .interact_on(&Term::stderr())
+            .unwrap_or(items.len() - 1);
+
+        match selection {
+            0 => {
+                println!("Larger buffers are faster on high-latency links; smaller ones use less memory.");
+                settings.transfer_buffer_kib = Input::with_theme(&theme::dialog())
+                    .with_prompt("SFTP transfer buffer (KiB)")
+                    .default(settings.transfer_buffer_kib)
+                    .validate_with(|v: &usize| if (4..=16384).contains(v) { Ok(()) } else { Err("Choose between 4 and 16384 KiB") })
+                    .interact_text()
+                    .unwrap();
+                changed = true;
+            }
+            1 => {
+                settings.fast_mode = !settings.fast_mode;
+                changed = true;
+            }
+            2 => {
+                settings.relative_times = !settings.relative_times;
+                changed = true;
+            }
+            3 => {
+                settings.clock_24h = !settings.clock_24h;
+                changed = true;
+            }
+            4 => {
+                let labels: Vec<&str> = AddressFamily::ALL.iter().map(|f| f.label()).collect();
+                let current = AddressFamily::ALL.iter().position(|f| *f == settings.address_family).unwrap_or(0);
+                if let Ok(i) = Select::with_theme(&theme::dialog())
+                    .with_prompt("Address family for dual-stack hosts")
+                    .default(current)
+                    .items(&labels)
+                    .interact_on(&Term::stderr())
+                {
+                    settings.address_family = AddressFamily::ALL[i];
+                    changed = true;
+                }
+            }
+            5 => {
+                println!("Later actions on the same server reuse its session instead of logging in again. 0 turns this off.");
+                settings.session_idle_secs = Input::with_theme(&theme::dialog())
+                    .with_prompt("Keep idle sessions for (seconds)")
+                    .default(settings.session_idle_secs)
+                    .interact_text()
+                    .unwrap();
+                changed = true;
+            }
+            7 => {
+                println!("Higher costs make unlocking slower for you and guessing far slower for an attacker. Applies from the next save.");
+                let memory_mib: u32 = Input::with_theme(&theme::dialog())
+                    .with_prompt("Memory (MiB)")
+                    .default(settings.kdf_memory_kib / 1024)
+                    .validate_with(|v: &u32| if (8..=4096).contains(v) { Ok(()) } else { Err("Choose between 8 and 4096 MiB") })
+                    .interact_text()
+                    .unwrap();
+                settings.kdf_passes = Input::with_theme(&theme::dialog())
+                    .with_prompt("Passes")
+                    .default(settings.kdf_passes)
+                    .validate_with(|v: &u32| if (1..=20).contains(v) { Ok(()) } else { Err("Choose between 1 and 20 passes") })
+                    .interact_text()
+                    .unwrap();
+                settings.kdf_memory_kib = memory_mib * 1024;
+                changed = true;
+            }
+            6 => {
+                settings.proxy = proxy_prompt("Proxy (socks5://host:port or http://host:port; blank for environment)", settings.proxy.as_deref());
+                changed = true;
+            }
+            8 => {
+                println!("How long to wait for a server while connecting and logging in.");
+                settings.connect_timeout_secs = Input::with_theme(&theme::dialog())
+                    .with_prompt("Connection timeout (seconds)")
+                    .default(settings.connect_timeout_secs)
+                    .validate_with(|v: &u64| if (1..=600).contains(v) { Ok(()) } else { Err("Choose between 1 and 600 seconds") })
+                    .interact_text()
+                    .unwrap();
+                changed = true;
+            }
+            9 => {
+                settings.theme.preset = settings.theme.preset.next();
+                theme::apply(&settings.theme);
+                changed = true;
+            }
+            10 => {
+                println!("Sent as $TERM to remote shells. Pick one the servers have terminfo for.");
+                let term: String = Input::with_theme(&theme::dialog())
+                    .with_prompt("Remote terminal type (blank for xterm-256color)")
+                    .with_initial_text(settings.remote_term.clone().unwrap_or_default())
+                    .allow_empty(true)
+                    .validate_with(|v: &String| {
+                        if v.chars().all(|c| c.is_ascii_alphanumeric() || "-+._".contains(c)) { Ok(()) } else { Err("Letters, digits and - + . _ only") }
+                    })
+                    .interact_text()
+                    .unwrap();
+                settings.remote_term = Some(term.trim().to_string()).filter(|t| !t.is_empty());
+                changed = true;
+            }
+            11 => {
+                println!("Keeps idle shells from being dropped by firewalls and NAT. 0 turns this off.");
+                settings.keepalive_secs = Input::with_theme(&theme::dialog())
+                    .with_prompt("Send a keepalive every (seconds)")
+                    .default(settings.keepalive_secs)
+                    .validate_with(|v: &u64| if *v <= 3600 { Ok(()) } else { Err("Choose at most 3600 seconds") })
+                    .interact_text()
+                    .unwrap();
+                changed = true;
+            }
+            12 => {
+                println!("Retries ride out a dropped packet or a server that is still booting. Failed logins are never retried.");
+                settings.connect_attempts = Input::with_theme(&theme::dialog())
+                    .with_prompt("Attempts at reaching a server")
+                    .default(settings.connect_attempts)
+                    .validate_with(|v: &u32| if (1..=10).contains(v) { Ok(()) } else { Err("Choose between 1 and 10 attempts") })
+                    .interact_text()
+                    .unwrap();
+                changed = true;
+            }
+            13 => {
+                if !settings.record_sessions {
+                    println!("⚠️  Logs are plaintext: anything shown in the shell, including secrets, ends up in them.");
+                }
+                settings.record_sessions = !settings.record_sessions;
+                changed = true;
+            }
+            14 => {
+                println!("The oldest connections are dropped beyond this many. 0 stops recording history.");
+                settings.history_limit = Input::with_theme(&theme::dialog())
+                    .with_prompt("Connections to keep in the history")
+                    .default(settings.history_limit)
+                    .validate_with(|v: &usize| if *v <= 100_000 { Ok(()) } else { Err("Choose at most 100000") })
+                    .interact_text()
+                    .unwrap();
+                changed = true;
+            }
+            15 => {
+                println!("Every uploaded file is chmodded to this mode once the upload finishes. Directories are left alone.");
+                let mode: String = Input::with_theme(&theme::dialog())
+                    .with_prompt("Mode for uploaded files, e.g. 640 (blank for the server default)")
+                    .with_initial_text(settings.upload_mode.clone().unwrap_or_default())
+                    .allow_empty(true)
+                    .validate_with(|v: &String| if v.trim().is_empty() { Ok(()) } else { transfer::parse_mode(v).map(|_| ()) })
+                    .interact_text()
+                    .unwrap();
+                settings.upload_mode = transfer::parse_mode(&mode).ok().map(|mode| format!("{:04o}", mode));
+                changed = true;
+            }
+            16 => {
+                println!("Each extra file in flight opens its own connection. Servers that ask for a 2FA code or key passphrase always get one file at a time.");
+                settings.parallel_transfers = Input::with_theme(&theme::dialog())
+                    .with_prompt("Files to transfer at once")
+                    .default(settings.parallel_transfers)
+                    .validate_with(|v: &usize| if (1..=16).contains(v) { Ok(()) } else { Err("Choose between 1 and 16") })
+                    .interact_text()
+                    .unwrap();
+                changed = true;
+            }
+            17 => {
+                println!("Keeps uploads and downloads from saturating a shared link. The cap covers all files in flight together. 0 means unlimited.");
+                settings.rate_limit_kib = Input::with_theme(&theme::dialog())
+                    .with_prompt("Transfer rate limit (KiB/s)")
+                    .default(settings.rate_limit_kib)
+                    .interact_text()
+                    .unwrap();
+                changed = true;
+            }
+            18 => {
+                settings.verify_checksums = !settings.verify_checksums;
+                changed = true;
+            }
+            19 => {
+                if !settings.plaintext_servers {
+                    println!("⚠️  Server lists will be saved as plain JSON: anyone who can read the file sees every host,");
+                    println!("   and any stored passwords in plain text. Meant for agent- or key-only setups.");
+                    if !confirm_always("Turn off encryption?") {
+                        continue;
+                    }
+                } else {
+                    println!("🔒 The server list will be encrypted again. Without a master password yet, you'll be asked to set one.");
+                }
+                settings.plaintext_servers = !settings.plaintext_servers;
+                changed = true;
+            }
+            20 => {
+                println!("Blanks the screen and asks for the master password again once the main menu sits unused this long. 0 turns this off.");
+                settings.idle_lock_mins = Input::with_theme(&theme::dialog())
+                    .with_prompt("Lock after (minutes)")
+                    .default(settings.idle_lock_mins)
+                    .validate_with(|v: &u64| if *v <= 1440 { Ok(()) } else { Err("Choose at most 1440 minutes") })
+                    .interact_text()
+                    .unwrap();
+                changed = true;
+            }
+            21 => {
+                println!("Offered when adding a server. A user@host:port typed as the host still wins.");
+                let user: String = Input::with_theme(&theme::dialog())
+                    .with_prompt("Default username (blank for none)")
+                    .with_initial_text(settings.default_user.clone().unwrap_or_else(whoami::username))
+                    .allow_empty(true)
+                    .interact_text()
+                    .unwrap();
+                settings.default_user = Some(user.trim().to_string()).filter(|u| !u.is_empty());
+                let port: String = Input::with_theme(&theme::dialog())
+                    .with_prompt("Default port")
+                    .with_initial_text(settings.default_port.to_string())
+                    .validate_with(|v: &String| parse_port(v).map(|_| ()))
+                    .interact_text()
+                    .unwrap();
+                settings.default_port = parse_port(&port).unwrap_or(22);
+                changed = true;
+            }
+            _ => return changed,
+        }
     }
 }
 
 pub fn get_local_path(prompt: &str) -> String {
-    Input::with_theme(&ColorfulTheme::default())
+    Input::with_theme(&theme::dialog())
         .with_prompt(prompt)
         .interact_text()
         .unwrap()
 }
 
+/// Browses the server's file system from the remote home directory and
+/// returns the picked file or directory, or `None` if cancelled.
+pub fn browse_remote(sftp: &Sftp, target: BrowseTarget) -> Result<Option<PathBuf>, Box<dyn std::error::Error>> {
+    let mut dir = sftp.realpath(Path::new("."))?;
+    loop {
+        let mut entries = match sftp.readdir(&dir) {
+            Ok(entries) => entries,
+            Err(e) => {
+                println!("⚠️  Could not list {}: {}", dir.display(), e);
+                if !dir.pop() {
+                    return Err(e.into());
+                }
+                continue;
+            }
+        };
+        entries.sort_by(|(a, a_stat), (b, b_stat)| b_stat.is_dir().cmp(&a_stat.is_dir()).then(a.cmp(b)));
+        if target == BrowseTarget::Download {
+            entries.retain(|(_, stat)| stat.is_dir() || stat.is_file());
+        } else {
+            entries.retain(|(_, stat)| stat.is_dir());
+        }
+
+        let mut items = vec!["📁 ..".to_string()];
+        items.push(match target {
+            BrowseTarget::Download => "📦 Download this whole directory".to_string(),
+            BrowseTarget::Upload => "✅ Upload here".to_string(),
+        });
+        items.push("✏️  Type a path".to_string());
+        let fixed = items.len();
+        for (path, stat) in &entries {
+            let name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+            items.push(if stat.is_dir() {
+                format!("📁 {}/", name)
+            } else {
+                format!("📄 {}  ({})", name, HumanBytes(stat.size.unwrap_or(0)))
+            });
+        }
+
+        let Ok(selection) = FuzzySelect::with_theme(&theme::dialog())
+            .with_prompt(format!("{} (Type to search)", dir.display()))
+            .default(0)
+            .items(&items)
+            .interact_on(&Term::stderr())
+        else {
+            return Ok(None);
+        };
+
+        match items[selection].as_str() {
+            "📁 .." => {
+                dir.pop();
+            }
+            "✅ Upload here" | "📦 Download this whole directory" => return Ok(Some(dir)),
+            "✏️  Type a path" => {
+                let typed = get_remote_path(match target {
+                    BrowseTarget::Download => "Remote file or directory path",
+                    BrowseTarget::Upload => "Remote directory",
+                });
+                return Ok(Some(dir.join(typed)));
+            }
+            _ => {
+                let (path, stat) = &entries[selection - fixed];
+                if stat.is_dir() {
+                    dir = path.clone();
+                } else {
+                    return Ok(Some(path.clone()));
+                }
+            }
+        }
+    }
+}
+
 pub fn get_remote_path(prompt: &str) -> String {
-    Input::with_theme(&ColorfulTheme::default())
+    Input::with_theme(&theme::dialog())
         .with_prompt(prompt)
         .interact_text()
         .unwrap()
 }
 
-pub fn add_server_prompt() -> Server {
-    println!("📝 Enter server details:");
-    
-    let group: String = Input::with_theme(&ColorfulTheme::default())
-        .with_prompt("Group")
-        .default("General".to_string())
-        .interact_text()
-        .unwrap();
+pub fn import_menu() -> Option<ImportSource> {
+    let items = [
+        ("📥 From ~/.ssh/config", ImportSource::SshConfig),
+        ("🔄 Sync from ~/.ssh/config (update existing)", ImportSource::SshConfigSync),
+        ("📄 From a CSV file", ImportSource::Csv),
+        ("🧾 From a JSON inventory", ImportSource::Json),
+        ("📃 From a host list or /etc/hosts", ImportSource::HostList),
+        ("🪟 From a PuTTY registry export (.reg)", ImportSource::Putty),
+        ("🔓 From a plaintext export", ImportSource::Plaintext),
+    ];
+    let labels: Vec<&str> = items.iter().map(|(label, _)| *label).collect();
+
+    let selection = Select::with_theme(&theme::dialog())
+        .with_prompt("Import servers")
+        .default(0)
+        .items(&labels)
+        .interact_on(&Term::stderr())
+        .ok()?;
+    Some(items[selection].1)
+}
+
+/// Picks the profile to switch to: the default one, a saved one, or a new
+/// name. `Some(None)` means the default profile; `None` means cancelled.
+pub fn profile_prompt(current: Option<&str>, profiles: &[String]) -> Option<Option<String>> {
+    let mut choices: Vec<Option<String>> = vec![None];
+    choices.extend(profiles.iter().cloned().map(Some));
+    let mut items: Vec<String> = choices.iter()
+        .map(|choice| {
+            let name = choice.as_deref().unwrap_or("default");
+            if choice.as_deref() == current { format!("{} (current)", name) } else { name.to_string() }
+        })
+        .collect();
+    items.push("➕ New profile...".to_string());
 
-    let name: String = Input::with_theme(&ColorfulTheme::default())
-        .with_prompt("Server Name (alias)")
+    let selection = Select::with_theme(&theme::dialog())
+        .with_prompt("Switch to profile")
+        .default(choices.iter().position(|choice| choice.as_deref() == current).unwrap_or(0))
+        .items(&items)
+        .interact_on(&Term::stderr())
+        .ok()?;
+    if let Some(choice) = choices.get(selection) {
+        return Some(choice.clone());
+    }
+
+    let name: String = Input::with_theme(&theme::dialog())
+        .with_prompt("New profile name")
+        .validate_with(|name: &String| config::validate_profile_name(name.trim()))
         .interact_text()
-        .unwrap();
+        .ok()?;
+    Some(Some(name.trim().to_string()))
+}
+
+/// Warns that an export is unencrypted and asks where to write it. `None`
+/// if the user backs out.
+pub fn export_prompt() -> Option<String> {
+    println!("{}", style("⚠️  The export is NOT encrypted: it contains every stored password in plain text.").red().bold());
+    println!("   Keep it somewhere safe and delete it once you no longer need it.");
+    let proceed = Confirm::with_theme(&theme::dialog())
+        .with_prompt("Export anyway?")
+        .default(false)
+        .interact_on(&Term::stderr())
+        .unwrap_or(false);
+    proceed.then(|| get_local_path("Export to (JSON file path)"))
+}
 
-    let user: String = Input::with_theme(&ColorfulTheme::default())
-        .with_prompt("Username")
+pub fn group_prompt(default: &str) -> String {
+    Input::with_theme(&theme::dialog())
+        .with_prompt("Group")
+        .default(default.to_string())
         .interact_text()
-        .unwrap();
+        .unwrap()
+}
 
-    let host: String = Input::with_theme(&ColorfulTheme::default())
-        .with_prompt("Host (IP or domain)")
+pub fn field_mapping_prompt() -> String {
+    Input::with_theme(&theme::dialog())
+        .with_prompt("Field mapping (field=path, comma separated)")
+        .default("name=name,host=host,user=user".to_string())
         .interact_text()
-        .unwrap();
+        .unwrap()
+}
 
-    let port: u16 = Input::with_theme(&ColorfulTheme::default())
-        .with_prompt("Port")
-        .default(22)
+/// Text prompt that starts pre-filled with `initial` (editable) if there is
+/// one, and otherwise offers `default`. Blank answers are asked again.
+fn text_prompt(prompt: &str, initial: Option<&str>, default: Option<String>) -> String {
+    checked_prompt(prompt, initial, default, |_| Ok(()))
+}
+
+/// `text_prompt` with an extra `check` on the trimmed answer; its error is
+/// shown under the prompt and the question asked again. The answer comes
+/// back trimmed too.
+fn checked_prompt(prompt: &str, initial: Option<&str>, default: Option<String>, check: impl Fn(&str) -> Result<(), String>) -> String {
+    let theme = theme::dialog();
+    let mut input = Input::<String>::with_theme(&theme)
+        .with_prompt(prompt)
+        .allow_empty(true)
+        .validate_with(|v: &String| match v.trim() {
+            "" => Err("This field is required".to_string()),
+            v => check(v),
+        });
+    match (initial, default) {
+        (Some(initial), _) => input = input.with_initial_text(initial),
+        (None, Some(default)) => input = input.default(default),
+        (None, None) => {}
+    }
+    input.interact_text().unwrap().trim().to_string()
+}
+
+/// Asks for a new server, recording a draft as it goes. Returns `None` if
+/// the user decides not to save it. The name may not match any of `taken`.
+/// The username and port start from the defaults in `settings`.
+pub fn add_server_prompt(draft: ServerDraft, taken: &[String], settings: &Settings) -> Option<Server> {
+    println!("📝 Enter server details:");
+    server_form(draft, None, taken, settings.default_user.as_deref(), settings.default_port)
+}
+
+/// The add form pre-filled from `existing`. Stored passwords are never shown;
+/// leaving the password blank keeps the current one.
+pub fn edit_server_prompt(existing: &Server, taken: &[String]) -> Option<Server> {
+    println!("✏️  Editing {}:", existing.name);
+    let draft = ServerDraft {
+        group: Some(existing.group.clone()),
+        tags: Some(existing.tags.clone()),
+        name: Some(existing.name.clone()),
+        host: Some(existing.host.clone()),
+        user: Some(existing.user.clone()),
+        port: Some(existing.port),
+    };
+    server_form(draft, Some(existing), taken, None, 22)
+}
+
+/// Shared by add and edit. Only new servers are drafted: a draft is always
+/// resumed as an add, which would duplicate an edited server.
+fn server_form(mut draft: ServerDraft, existing: Option<&Server>, taken: &[String], default_user: Option<&str>, default_port: u16) -> Option<Server> {
+    let save_draft = |draft: &ServerDraft| if existing.is_none() { draft.save() };
+
+    let group = text_prompt("Group", draft.group.as_deref(), Some("General".to_string()));
+    draft.group = Some(group.clone());
+    save_draft(&draft);
+
+    let tags: String = Input::with_theme(&theme::dialog())
+        .with_prompt("Tags (comma separated, e.g. prod, web; blank for none)")
+        .with_initial_text(draft.tags.as_deref().unwrap_or_default().join(", "))
+        .allow_empty(true)
         .interact_text()
         .unwrap();
+    let tags = config::parse_tags(&tags);
+    draft.tags = Some(tags.clone());
+    save_draft(&draft);
+
+    let name = checked_prompt("Server Name (alias)", draft.name.as_deref(), None, |v| {
+        match taken.iter().find(|t| config::same_name(t, v)) {
+            Some(existing) => Err(format!("A server named '{}' already exists", existing)),
+            None => Ok(()),
+        }
+    });
+    draft.name = Some(name.clone());
+    save_draft(&draft);
+
+    let host_input = checked_prompt("Host (IP, domain or user@host:port)", draft.host.as_deref(), None, |v| {
+        if parse_host_spec(v).1.is_empty() { Err("The host part is missing".to_string()) } else { Ok(()) }
+    });
+    let (parsed_user, host, parsed_port) = parse_host_spec(&host_input);
+    draft.host = Some(host.clone());
+    save_draft(&draft);
+
+    let user = text_prompt("Username", draft.user.as_deref(), parsed_user.or(default_user.map(str::to_string)));
+    draft.user = Some(user.clone());
+    save_draft(&draft);
+
+    let port = port_prompt(parsed_port.or(draft.port), default_port);
+    draft.port = Some(port);
+    save_draft(&draft);
 
-    let auth_modes = vec!["Password", "SSH Key", "SSH Agent (No auth stored)"];
-    let auth_selection = Select::with_theme(&ColorfulTheme::default())
+    let auth_modes = vec![
+        "Password",
+        "SSH Key",
+        "SSH Agent (No auth stored)",
+        "SSH Certificate (CA-signed)",
+        "Keyboard-interactive (2FA, prompts on connect)",
+    ];
+    let current_auth = existing.map(|s| &s.auth_type);
+    let auth_selection = Select::with_theme(&theme::dialog())
         .with_prompt("Authentication Method")
-        .default(0)
+        .default(match current_auth {
+            Some(AuthType::Key(_)) => 1,
+            Some(AuthType::Agent) => 2,
+            Some(AuthType::Certificate { .. }) => 3,
+            Some(AuthType::KeyboardInteractive) => 4,
+            _ => 0,
+        })
         .items(&auth_modes)
         .interact()
         .unwrap();
 
     let auth_type = match auth_selection {
-        0 => {
-             let pass = Password::with_theme(&ColorfulTheme::default())
-                .with_prompt("Password")
-                .interact()
-                .unwrap();
-             AuthType::Password(pass)
+        0 => match current_auth {
+            Some(AuthType::Password(current)) => {
+                let pass = Zeroizing::new(Password::with_theme(&theme::dialog())
+                    .with_prompt("Password (blank keeps the current one)")
+                    .allow_empty_password(true)
+                    .interact()
+                    .unwrap());
+                AuthType::Password(if pass.is_empty() { current.clone() } else { pass })
+            }
+            _ => {
+                let pass = Zeroizing::new(Password::with_theme(&theme::dialog())
+                    .with_prompt("Password")
+                    .interact()
+                    .unwrap());
+                AuthType::Password(pass)
+            }
         },
         1 => {
-            let key_path: String = Input::with_theme(&ColorfulTheme::default())
-                .with_prompt("Path to Private Key")
-                .default("~/.ssh/id_rsa".to_string())
-                .interact_text()
-                .unwrap();
-            AuthType::Key(key_path)
+            let current = match current_auth {
+                Some(AuthType::Key(path)) => Some(path.as_str()),
+                _ => None,
+            };
+            AuthType::Key(key_file_prompt("Key", "Path to Private Key", current, Some("~/.ssh/id_rsa".to_string())))
         },
+        3 => {
+            let (current_key, current_cert) = match current_auth {
+                Some(AuthType::Certificate { key_path, cert_path }) => (Some(key_path.as_str()), Some(cert_path.as_str())),
+                _ => (None, None),
+            };
+            let key_path = key_file_prompt("Key", "Path to Private Key", current_key, Some("~/.ssh/id_ed25519".to_string()));
+            let cert_path = key_file_prompt("Certificate", "Path to Signed Certificate", current_cert, Some(format!("{}-cert.pub", key_path)));
+            AuthType::Certificate { key_path, cert_path }
+        },
+        4 => AuthType::KeyboardInteractive,
         _ => AuthType::Agent,
     };
 
-    Server {
+    let agent_identity = match auth_type {
+        AuthType::Agent => agent_identity_prompt(existing.and_then(|s| s.agent_identity.as_deref())),
+        _ => None,
+    };
+
+    let mut server = Server {
         name,
         user,
         host,
         port,
         auth_type,
+        agent_identity,
         group,
+        tags,
+        ..existing.cloned().unwrap_or_default()
+    };
+
+    let advanced = Confirm::with_theme(&theme::dialog())
+        .with_prompt("Configure advanced connection options?")
+        .default(false)
+        .interact()
+        .unwrap();
+    if advanced {
+        server.transport = transport_prompt();
+        server.web_port = web_port_prompt();
+        server.address_family = address_family_prompt();
+        let jump: String = Input::with_theme(&theme::dialog())
+            .with_prompt("Jump host (alias of a saved server, blank for none)")
+            .allow_empty(true)
+            .interact_text()
+            .unwrap();
+        server.jump_host = Some(jump.trim().to_string()).filter(|j| !j.is_empty());
+        if server.transport == Transport::Tcp && server.jump_host.is_none() {
+            server.proxy = proxy_prompt("Proxy (socks5://host:port, http://host:port, 'none' for direct; blank for global)", None);
+        }
+        let env: String = Input::with_theme(&theme::dialog())
+            .with_prompt("Shell environment (NAME=value, comma separated, e.g. LANG=en_US.UTF-8; blank for none)")
+            .with_initial_text(config::format_env(&server.env))
+            .allow_empty(true)
+            .validate_with(|v: &String| config::parse_env(v).map(|_| ()))
+            .interact_text()
+            .unwrap();
+        server.env = config::parse_env(&env).unwrap_or_default();
     }
-}
 
-pub fn select_server(servers: &[Server]) -> Option<usize> {
-    if servers.is_empty() {
-        println!("⚠️  No servers found. Add one first!");
+    let save = Confirm::with_theme(&theme::dialog())
+        .with_prompt(format!("Save {}?", server.name))
+        .default(true)
+        .interact()
+        .unwrap();
+    if !save {
+        if existing.is_none() {
+            ServerDraft::clear();
+        }
         return None;
     }
 
-    let items: Vec<String> = servers
-        .iter()
-        .map(|s| format!("[{}] {} ({}@{}:{})", s.group, s.name, s.user, s.host, s.port))
-        .collect();
+    Some(server.trimmed())
+}
+
+/// Which agent key to offer first, picked from the keys the agent holds now.
+/// Without a reachable agent the comment or fingerprint is typed instead.
+fn agent_identity_prompt(current: Option<&str>) -> Option<String> {
+    let identities = match session::agent_identities() {
+        Ok(identities) if !identities.is_empty() => identities,
+        _ => {
+            let wanted: String = Input::with_theme(&theme::dialog())
+                .with_prompt("Agent key to use (comment or SHA256 fingerprint, blank tries all)")
+                .with_initial_text(current.unwrap_or_default())
+                .allow_empty(true)
+                .interact_text()
+                .unwrap();
+            return Some(wanted.trim().to_string()).filter(|w| !w.is_empty());
+        }
+    };
 
-    let selection = FuzzySelect::with_theme(&ColorfulTheme::default())
-        .with_prompt("Select a server (Type to search)")
+    let mut items = vec!["Any (try every key in the agent)".to_string()];
+    items.extend(identities.iter().map(|i| format!("{}  {}", i.label(), i.fingerprint)));
+    let missing = current.filter(|c| !identities.iter().any(|i| i.matches(c)));
+    if let Some(missing) = missing {
+        items.push(format!("{}  (not loaded now)", missing));
+    }
+    let selected = current.map_or(0, |c| identities.iter().position(|i| i.matches(c)).map_or(items.len() - 1, |i| i + 1));
+    let selection = Select::with_theme(&theme::dialog())
+        .with_prompt("Agent key to use")
+        .default(selected)
+        .items(&items)
+        .interact()
+        .unwrap();
+    match selection {
+        0 => None,
+        i if i <= identities.len() => Some(identities[i - 1].fingerprint.clone()),
+        _ => missing.map(str::to_string),
+    }
+}
+
+/// SSH port, re-asked until it is a number in 1..=65535. Blank means
+/// `default`.
+fn port_prompt(initial: Option<u16>, default: u16) -> u16 {
+    let port: String = Input::with_theme(&theme::dialog())
+        .with_prompt(format!("Port (blank for {})", default))
+        .with_initial_text(initial.map(|p| p.to_string()).unwrap_or_default())
+        .allow_empty(true)
+        .validate_with(|v: &String| parse_port(v).map(|_| ()))
+        .interact_text()
+        .unwrap();
+    if port.trim().is_empty() {
+        return default;
+    }
+    parse_port(&port).unwrap_or(default)
+}
+
+fn parse_port(input: &str) -> Result<u16, String> {
+    let input = input.trim();
+    if input.is_empty() {
+        return Ok(22);
+    }
+    match input.parse::<u16>() {
+        Ok(port) if port != 0 => Ok(port),
+        _ if !input.chars().all(|c| c.is_ascii_digit()) => Err(format!("Invalid port '{}': digits only", input)),
+        _ => Err(format!("Invalid port {}: must be between 1 and 65535", input)),
+    }
+}
+
+pub fn resume_draft_prompt(draft: &ServerDraft) -> bool {
+    Confirm::with_theme(&theme::dialog())
+        .with_prompt(format!("You have an unsaved server draft ({}) — resume?", draft.describe()))
+        .default(true)
+        .interact_on(&Term::stderr())
+        .unwrap_or(false)
+}
+
+pub fn web_port_prompt() -> Option<u16> {
+    let port: String = Input::with_theme(&theme::dialog())
+        .with_prompt("Web UI port on the server (blank for none)")
+        .allow_empty(true)
+        .validate_with(|v: &String| {
+            if v.trim().is_empty() || v.trim().parse::<u16>().is_ok_and(|p| p != 0) { Ok(()) } else { Err("Enter a port between 1 and 65535") }
+        })
+        .interact_text()
+        .unwrap();
+    port.trim().parse().ok()
+}
+
+/// First contact with a host: show its fingerprint and ask whether to trust it.
+pub fn accept_host_key_prompt(host: &str, fingerprint: &str) -> bool {
+    println!("🔐 The authenticity of host {} can't be established.", host);
+    println!("   Host key fingerprint is {}", fingerprint);
+    Confirm::with_theme(&theme::dialog())
+        .with_prompt("Trust this host and save its key to known_hosts?")
+        .default(false)
+        .interact_on(&Term::stderr())
+        .unwrap_or(false)
+}
+
+/// Answers a keyboard-interactive challenge at the terminal. The server's
+/// instructions and prompts are printed exactly as sent; answers to prompts
+/// the server marks as secret are not echoed.
+pub fn keyboard_interactive_answers(instructions: &str, prompts: &[Prompt]) -> Vec<String> {
+    if !instructions.is_empty() {
+        println!("{}", instructions);
+    }
+    prompts.iter().map(|prompt| {
+        print!("{}", prompt.text);
+        let _ = std::io::stdout().flush();
+        let answer = if prompt.echo {
+            let mut line = String::new();
+            std::io::stdin().read_line(&mut line).map(|_| line.trim_end_matches(['\r', '\n']).to_string())
+        } else {
+            rpassword::read_password()
+        };
+        answer.unwrap_or_default()
+    }).collect()
+}
+
+/// Asks for the passphrase of an encrypted private key. `None` if cancelled.
+pub fn key_passphrase_prompt(key_path: &Path) -> Option<Zeroizing<String>> {
+    Password::with_theme(&theme::dialog())
+        .with_prompt(format!("Passphrase for {}", key_path.display()))
+        .interact_on(&Term::stderr())
+        .ok()
+        .map(Zeroizing::new)
+}
+
+/// Offers ways to fix a server whose key files have gone missing.
+pub fn key_fix_prompt(server: &Server, problem: &str) -> KeyFix {
+    let items = ["Point to the new file location", "Switch to SSH agent", "Leave it for now"];
+    let selection = Select::with_theme(&theme::dialog())
+        .with_prompt(format!("{}: {}", server.name, problem))
         .default(0)
         .items(&items)
         .interact_on(&Term::stderr())
-        .ok()?;
+        .unwrap_or(2);
+
+    match (selection, &server.auth_type) {
+        (0, AuthType::Key(key_path)) => KeyFix::Repoint(AuthType::Key(key_file_prompt("Key", "Path to Private Key", Some(key_path), None))),
+        (0, AuthType::Certificate { key_path, cert_path }) => KeyFix::Repoint(AuthType::Certificate {
+            key_path: key_file_prompt("Key", "Path to Private Key", Some(key_path), None),
+            cert_path: key_file_prompt("Certificate", "Path to Signed Certificate", Some(cert_path), None),
+        }),
+        (1, _) => KeyFix::UseAgent,
+        _ => KeyFix::Skip,
+    }
+}
+
+/// Asks for a key or certificate file, re-asking until it names a readable
+/// file. Tab completes the path; a leading `~` is expanded in the answer,
+/// since libssh2 opens the path as given.
+fn key_file_prompt(label: &str, prompt: &str, initial: Option<&str>, default: Option<String>) -> String {
+    let theme = theme::dialog();
+    let mut input = Input::<String>::with_theme(&theme)
+        .with_prompt(format!("{} (Tab completes)", prompt))
+        .completion_with(&PathCompletion)
+        .validate_with(|v: &String| match keycheck::check_file(label, v.trim()) {
+            Some(problem) => Err(problem),
+            None => Ok(()),
+        });
+    match (initial, default) {
+        (Some(initial), _) => input = input.with_initial_text(initial),
+        (None, Some(default)) => input = input.default(default),
+        (None, None) => {}
+    }
+    config::expand_tilde(input.interact_text().unwrap().trim())
+}
+
+/// Completes a file path typed so far as far as the matches agree, adding a
+/// `/` once it names a single directory. Nothing typed starts at `~/.ssh/`.
+/// Hidden entries are only offered once a `.` has been typed.
+struct PathCompletion;
 
-    Some(selection)
+impl Completion for PathCompletion {
+    fn get(&self, input: &str) -> Option<String> {
+        match input {
+            "" => return Some("~/.ssh/".to_string()),
+            "~" => return Some("~/".to_string()),
+            _ => {}
+        }
+        let expanded = config::expand_tilde(input);
+        let (dir, prefix) = match expanded.rfind('/') {
+            Some(i) => (&expanded[..=i], &expanded[i + 1..]),
+            None => ("./", expanded.as_str()),
+        };
+        let matches: Vec<(String, bool)> = fs::read_dir(dir).ok()?
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| {
+                let name = entry.file_name().into_string().ok()?;
+                let offered = name.starts_with(prefix) && (prefix.starts_with('.') || !name.starts_with('.'));
+                offered.then(|| (name, entry.path().is_dir()))
+            })
+            .collect();
+
+        let completed = match matches.as_slice() {
+            [] => return None,
+            [(name, true)] => format!("{}/", name),
+            [(name, false)] => name.clone(),
+            [(first, _), rest @ ..] => rest.iter().fold(first.clone(), |common, (name, _)| {
+                let len = common.chars().zip(name.chars()).take_while(|(a, b)| a == b).map(|(a, _)| a.len_utf8()).sum();
+                common[..len].to_string()
+            }),
+        };
+        completed.strip_prefix(prefix)
+            .filter(|rest| !rest.is_empty())
+            .map(|rest| format!("{}{}", input, rest))
+    }
+}
+
+/// Asks for a proxy URL; blank means unset.
+fn proxy_prompt(prompt: &str, current: Option<&str>) -> Option<String> {
+    let url: String = Input::with_theme(&theme::dialog())
+        .with_prompt(prompt)
+        .with_initial_text(current.unwrap_or_default())
+        .allow_empty(true)
+        .validate_with(|v: &String| {
+            let v = v.trim();
+            if v.is_empty() || v == proxy::DIRECT { Ok(()) } else { Proxy::parse(v).map(|_| ()) }
+        })
+        .interact_text()
+        .unwrap();
+    let url = url.trim();
+    (!url.is_empty()).then(|| url.to_string())
+}
+
+/// Per-server override; `None` means use the global setting.
+fn address_family_prompt() -> Option<AddressFamily> {
+    let mut items = vec!["Use global setting"];
+    items.extend(AddressFamily::ALL.iter().map(|f| f.label()));
+    let selection = Select::with_theme(&theme::dialog())
+        .with_prompt("Address family")
+        .default(0)
+        .items(&items)
+        .interact()
+        .unwrap();
+    selection.checked_sub(1).map(|i| AddressFamily::ALL[i])
+}
+
+fn transport_prompt() -> Transport {
+    let transports = vec!["Direct TCP", "Unix domain socket", "Proxy command (stdio)"];
+    let selection = Select::with_theme(&theme::dialog())
+        .with_prompt("Transport")
+        .default(0)
+        .items(&transports)
+        .interact()
+        .unwrap();
+
+    match selection {
+        1 => {
+            let path: String = Input::with_theme(&theme::dialog())
+                .with_prompt("Socket path")
+                .interact_text()
+                .unwrap();
+            Transport::UnixSocket(path)
+        },
+        2 => {
+            let command: String = Input::with_theme(&theme::dialog())
+                .with_prompt("Proxy command (%h host, %p port, %r user)")
+                .interact_text()
+                .unwrap();
+            Transport::ProxyCommand(command)
+        },
+        _ => Transport::Tcp,
+    }
+}
+
+/// A row in the server picker: the sort switch, a group header or a server index.
+enum PickerRow<'a> {
+    Sort,
+    Tag,
+    Several,
+    Group(&'a str),
+    Server(usize),
+}
+
+/// Picks the tag to filter server lists by. `Some(None)` shows every server
+/// again; `None` means cancelled.
+fn tag_filter_prompt(tags: &[String], current: Option<&str>) -> Option<Option<String>> {
+    let mut items = vec!["All servers".to_string()];
+    items.extend(tags.iter().map(|t| format!("#{}", t)));
+    let default = current
+        .and_then(|c| tags.iter().position(|t| t.eq_ignore_ascii_case(c)))
+        .map_or(0, |i| i + 1);
+    let selection = FuzzySelect::with_theme(&theme::dialog())
+        .with_prompt("Show only servers tagged")
+        .default(default)
+        .items(&items)
+        .interact_on_opt(&Term::stderr())
+        .ok()??;
+    Some(selection.checked_sub(1).map(|i| tags[i].clone()))
+}
+
+/// Picks one of the groups in use, shown with how many servers it holds.
+pub fn select_group(servers: &[Server]) -> Option<String> {
+    let mut groups: Vec<(&str, usize)> = Vec::new();
+    for server in servers {
+        match groups.iter_mut().find(|(group, _)| *group == server.group) {
+            Some((_, count)) => *count += 1,
+            None => groups.push((&server.group, 1)),
+        }
+    }
+    if groups.is_empty() {
+        println!("No servers saved yet.");
+        return None;
+    }
+    groups.sort_by_key(|(group, _)| group.to_lowercase());
+
+    let items: Vec<String> = groups.iter()
+        .map(|(group, count)| format!("{} ({} server{})", group, count, if *count == 1 { "" } else { "s" }))
+        .collect();
+    FuzzySelect::with_theme(&theme::dialog())
+        .with_prompt("Group")
+        .items(&items)
+        .default(0)
+        .interact_on_opt(&Term::stderr())
+        .ok()
+        .flatten()
+        .map(|i| groups[i].0.to_string())
+}
+
+/// One step of Reorder Servers: the saved list with `moving` marked, then a
+/// choice to move it up (-1) or down (1). `last` keeps the previous choice
+/// highlighted, so pressing Enter again keeps dragging the same way. `None`
+/// when done.
+pub fn move_step(servers: &[Server], moving: usize, last: &mut usize) -> Option<isize> {
+    let term = Term::stderr();
+    let _ = term.clear_screen();
+    println!("↕️  Reorder Servers (favorites are still listed first elsewhere)");
+    for (i, server) in servers.iter().enumerate() {
+        match i == moving {
+            true => println!("  ▶ {}. {}", i + 1, style(&server.name).bold()),
+            false => println!("    {}. {}", i + 1, server.name),
+        }
+    }
+    println!();
+    let choice = Select::with_theme(&theme::dialog())
+        .with_prompt(format!("Move {}", servers[moving].name))
+        .items(&["⬆️  Up", "⬇️  Down", "✅ Done"])
+        .default(*last)
+        .interact_on_opt(&term)
+        .ok()??;
+    *last = choice;
+    match choice {
+        0 => Some(-1),
+        1 => Some(1),
+        _ => None,
+    }
+}
+
+/// Servers listed in the current sort order, under their group headers when
+/// sorted by group. Picking a header collapses or expands that group, and
+/// picking the sort row switches the order, instead of returning.
+pub fn select_server(servers: &[Server], app: &mut App) -> Option<usize> {
+    server_picker(servers, app, false).map(|picked| picked[0])
+}
+
+/// Like `select_server`, with an extra row that lists every server (in the
+/// same order, tag filter included) with checkboxes to tick with `Space`.
+/// Picking a server directly returns just that one.
+pub fn select_servers(servers: &[Server], app: &mut App) -> Option<Vec<usize>> {
+    server_picker(servers, app, true)
+}
+
+fn server_picker(servers: &[Server], app: &mut App, several: bool) -> Option<Vec<usize>> {
+    if servers.is_empty() {
+        println!("⚠️  No servers found. Add one first!");
+        return None;
+    }
+    app.poll_key_scan();
+    app.collect_probes();
+
+    let tags = config::all_tags(servers);
+    let mut toggled: Option<PickerRow> = None;
+    loop {
+        // A tag that no server carries any more filters nothing out.
+        if app.tag_filter.as_ref().is_some_and(|tag| !tags.iter().any(|t| t.eq_ignore_ascii_case(tag))) {
+            app.tag_filter = None;
+        }
+        let order: Vec<usize> = app.sort_mode.order(servers).into_iter()
+            .filter(|&i| app.tag_filter.as_ref().is_none_or(|tag| servers[i].has_tag(tag)))
+            .collect();
+        let mut rows = vec![PickerRow::Sort];
+        let mut items = vec![format!("↕️  Sorted by {} (pick to change)", app.sort_mode.label())];
+        if !tags.is_empty() {
+            rows.push(PickerRow::Tag);
+            items.push(match &app.tag_filter {
+                Some(tag) => format!("🏷️  Only #{} (pick to change)", tag),
+                None => "🏷️  All tags (pick to filter)".to_string(),
+            });
+        }
+        if several {
+            rows.push(PickerRow::Several);
+            items.push("☑️  Select several (Space to tick)".to_string());
+        }
+        let switches = rows.len();
+        // Tags are part of the row so typing `#prod` also narrows the list.
+        let server_item = |i: usize| {
+            let s = &servers[i];
+            let tags: String = s.tags.iter().map(|t| format!(" #{}", t)).collect();
+            let star = if s.is_favorite { "★ " } else { "" };
+            format!("    {} {}{}{} ({}){}", app.status_dot(s), star, app.marker(s), s.name, app.address(s), tags)
+        };
+        // Favorites stay pinned above the groups, outside any fold.
+        let (favorites, order): (Vec<usize>, Vec<usize>) = order.into_iter().partition(|&i| servers[i].is_favorite);
+        let listed: Vec<usize> = favorites.iter().chain(&order).copied().collect();
+        let checklist: Vec<String> = match several {
+            true => listed.iter().map(|&i| server_item(i).trim_start().to_string()).collect(),
+            false => Vec::new(),
+        };
+        for &i in &favorites {
+            rows.push(PickerRow::Server(i));
+            items.push(server_item(i));
+        }
+        if app.sort_mode == SortMode::Group {
+            let mut groups: Vec<&str> = Vec::new();
+            for &i in &order {
+                if !groups.contains(&servers[i].group.as_str()) {
+                    groups.push(&servers[i].group);
+                }
+            }
+            for group in groups {
+                let members: Vec<usize> = order.iter().copied().filter(|&i| servers[i].group == group).collect();
+                let collapsed = app.collapsed_groups.contains(group);
+                rows.push(PickerRow::Group(group));
+                items.push(format!("{} 📂 {} ({})", if collapsed { "▸" } else { "▾" }, group, members.len()));
+                if collapsed {
+                    continue;
+                }
+                for i in members {
+                    rows.push(PickerRow::Server(i));
+                    items.push(server_item(i));
+                }
+            }
+        } else {
+            for i in order {
+                rows.push(PickerRow::Server(i));
+                items.push(server_item(i));
+            }
+        }
+
+        let default = rows.iter()
+            .position(|row| match (row, &toggled) {
+                (PickerRow::Sort, Some(PickerRow::Sort)) => true,
+                (PickerRow::Tag, Some(PickerRow::Tag)) => true,
+                (PickerRow::Several, Some(PickerRow::Several)) => true,
+                (PickerRow::Group(group), Some(PickerRow::Group(toggled))) => group == toggled,
+                (PickerRow::Server(i), None) => app.last_server.as_deref() == Some(servers[*i].name.as_str()),
+                _ => false,
+            })
+            .unwrap_or(rows.len().min(switches)); // the first row under the switches
+
+        lock::waiting();
+        let selection = FuzzySelect::with_theme(&theme::dialog())
+            .with_prompt("Select a server (Type to search, pick a group to fold it)")
+            .default(default)
+            .items(&items)
+            .interact_on(&Term::stderr());
+        if lock::resumed() {
+            return None;
+        }
+        let selection = selection.ok()?;
+
+        match rows[selection] {
+            PickerRow::Sort => {
+                app.sort_mode = app.sort_mode.next();
+                toggled = Some(PickerRow::Sort);
+            }
+            PickerRow::Tag => {
+                if let Some(filter) = tag_filter_prompt(&tags, app.tag_filter.as_deref()) {
+                    app.tag_filter = filter;
+                }
+                toggled = Some(PickerRow::Tag);
+            }
+            PickerRow::Several => {
+                let ticked = MultiSelect::with_theme(&theme::dialog())
+                    .with_prompt("Tick servers with Space, then press Enter (Esc to go back)")
+                    .items(&checklist)
+                    .interact_on_opt(&Term::stderr())
+                    .ok()
+                    .flatten()
+                    .unwrap_or_default();
+                if !ticked.is_empty() {
+                    return Some(ticked.into_iter().map(|row| listed[row]).collect());
+                }
+                toggled = Some(PickerRow::Several);
+            }
+            PickerRow::Group(group) => {
+                if !app.collapsed_groups.remove(group) {
+                    app.collapsed_groups.insert(group.to_string());
+                }
+                toggled = Some(PickerRow::Group(group));
+            }
+            PickerRow::Server(i) => {
+                app.last_server = Some(servers[i].name.clone());
+                return Some(vec![i]);
+            }
+        }
+    }
 }